@@ -18,10 +18,10 @@ JNIEXPORT jfloat JNICALL Java_com_github_eliak_VScoreNative_cosineSimilarity
 /*
  * Class:     com_github_eliak_VScoreNative
  * Method:    createScorerFactory
- * Signature: ()J
+ * Signature: (IJIIII)J
  */
 JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createScorerFactory
-  (JNIEnv *, jclass);
+  (JNIEnv *, jclass, jint, jlong, jint, jint, jint, jint);
 
 /*
  * Class:     com_github_eliak_VScoreNative
@@ -31,13 +31,37 @@ JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createScorerFactory
 JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_destroyScorerFactory
   (JNIEnv *, jclass, jlong);
 
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    invalidate
+ * Signature: (JJI)Z
+ */
+JNIEXPORT jboolean JNICALL Java_com_github_eliak_VScoreNative_invalidate
+  (JNIEnv *, jclass, jlong, jlong, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    dropSegment
+ * Signature: (JJ)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_dropSegment
+  (JNIEnv *, jclass, jlong, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    clear
+ * Signature: (J)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_clear
+  (JNIEnv *, jclass, jlong);
+
 /*
  * Class:     com_github_eliak_VScoreNative
  * Method:    createScorer
- * Signature: (J[F)J
+ * Signature: (J[FII)J
  */
 JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createScorer
-  (JNIEnv *, jclass, jlong, jfloatArray);
+  (JNIEnv *, jclass, jlong, jfloatArray, jint, jint);
 
 /*
  * Class:     com_github_eliak_VScoreNative
@@ -50,10 +74,290 @@ JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_destroyScorer
 /*
  * Class:     com_github_eliak_VScoreNative
  * Method:    score
- * Signature: (JILcom/github/eliak/VScoreNative/ScorerCallback;)F
+ * Signature: (JJILcom/github/eliak/VScoreNative/ScorerCallback;)F
  */
 JNIEXPORT jfloat JNICALL Java_com_github_eliak_VScoreNative_score
-  (JNIEnv *, jclass, jlong, jint, jobject);
+  (JNIEnv *, jclass, jlong, jlong, jint, jobject);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    scoreBatch
+ * Signature: (JJ[II[FLcom/github/eliak/VScoreNative/BatchCallback;)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_scoreBatch
+  (JNIEnv *, jclass, jlong, jlong, jintArray, jint, jfloatArray, jobject);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    topK
+ * Signature: (JJI[I[I[FILcom/github/eliak/VScoreNative/BatchCallback;)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_topK
+  (JNIEnv *, jclass, jlong, jlong, jint, jintArray, jintArray, jfloatArray, jint, jobject);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createIndex
+ * Signature: (IIIIII)J
+ */
+JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createIndex
+  (JNIEnv *, jclass, jint, jint, jint, jint, jint, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyIndex
+ * Signature: (J)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_destroyIndex
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    addVector
+ * Signature: (JI[F)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_addVector
+  (JNIEnv *, jclass, jlong, jint, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    indexSize
+ * Signature: (J)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_indexSize
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    search
+ * Signature: (J[FII[I[F)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_search
+  (JNIEnv *, jclass, jlong, jfloatArray, jint, jint, jintArray, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createIvfIndex
+ * Signature: (IIIII)J
+ */
+JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createIvfIndex
+  (JNIEnv *, jclass, jint, jint, jint, jint, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyIvfIndex
+ * Signature: (J)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_destroyIvfIndex
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    trainIvf
+ * Signature: (J[FI)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_trainIvf
+  (JNIEnv *, jclass, jlong, jfloatArray, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    addIvfVector
+ * Signature: (JI[F)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_addIvfVector
+  (JNIEnv *, jclass, jlong, jint, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    ivfSize
+ * Signature: (J)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_ivfSize
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    setNprobe
+ * Signature: (JI)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_setNprobe
+  (JNIEnv *, jclass, jlong, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    searchIvf
+ * Signature: (J[FII[I[F)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_searchIvf
+  (JNIEnv *, jclass, jlong, jfloatArray, jint, jint, jintArray, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createProductQuantizer
+ * Signature: (IIII)J
+ */
+JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createProductQuantizer
+  (JNIEnv *, jclass, jint, jint, jint, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyProductQuantizer
+ * Signature: (J)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_destroyProductQuantizer
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    trainProductQuantizer
+ * Signature: (J[FI)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_trainProductQuantizer
+  (JNIEnv *, jclass, jlong, jfloatArray, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    pqEncode
+ * Signature: (J[F)[B
+ */
+JNIEXPORT jbyteArray JNICALL Java_com_github_eliak_VScoreNative_pqEncode
+  (JNIEnv *, jclass, jlong, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createPqQuery
+ * Signature: (J[F)J
+ */
+JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createPqQuery
+  (JNIEnv *, jclass, jlong, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyPqQuery
+ * Signature: (J)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_destroyPqQuery
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    pqScore
+ * Signature: (J[BI)F
+ */
+JNIEXPORT jfloat JNICALL Java_com_github_eliak_VScoreNative_pqScore
+  (JNIEnv *, jclass, jlong, jbyteArray, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    pqScoreBatch
+ * Signature: (J[BI[F)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_pqScoreBatch
+  (JNIEnv *, jclass, jlong, jbyteArray, jint, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    scoreBytes
+ * Signature: (JJI[BIIZ)F
+ */
+JNIEXPORT jfloat JNICALL Java_com_github_eliak_VScoreNative_scoreBytes
+  (JNIEnv *, jclass, jlong, jlong, jint, jbyteArray, jint, jint, jboolean);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    encodeVector
+ * Signature: ([F)[B
+ */
+JNIEXPORT jbyteArray JNICALL Java_com_github_eliak_VScoreNative_encodeVector
+  (JNIEnv *, jclass, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    cpuFeatures
+ * Signature: ()Ljava/lang/String;
+ */
+JNIEXPORT jstring JNICALL Java_com_github_eliak_VScoreNative_cpuFeatures
+  (JNIEnv *, jclass);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    openVectorStore
+ * Signature: (JLjava/lang/String;)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_openVectorStore
+  (JNIEnv *, jclass, jlong, jstring);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    closeVectorStore
+ * Signature: (J)Z
+ */
+JNIEXPORT jboolean JNICALL Java_com_github_eliak_VScoreNative_closeVectorStore
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createVectorStoreWriter
+ * Signature: (Ljava/lang/String;II)J
+ */
+JNIEXPORT jlong JNICALL Java_com_github_eliak_VScoreNative_createVectorStoreWriter
+  (JNIEnv *, jclass, jstring, jint, jint);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    addToVectorStore
+ * Signature: (JJI[F)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_addToVectorStore
+  (JNIEnv *, jclass, jlong, jlong, jint, jfloatArray);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    finishVectorStore
+ * Signature: (J)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_finishVectorStore
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    abortVectorStore
+ * Signature: (J)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_abortVectorStore
+  (JNIEnv *, jclass, jlong);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    saveSnapshot
+ * Signature: (JLjava/lang/String;)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_saveSnapshot
+  (JNIEnv *, jclass, jlong, jstring);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    loadSnapshot
+ * Signature: (JLjava/lang/String;)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_loadSnapshot
+  (JNIEnv *, jclass, jlong, jstring);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    warmSegment
+ * Signature: (JJLjava/nio/ByteBuffer;[ILcom/github/eliak/VScoreNative/WarmCallback;)I
+ */
+JNIEXPORT jint JNICALL Java_com_github_eliak_VScoreNative_warmSegment
+  (JNIEnv *, jclass, jlong, jlong, jobject, jintArray, jobject);
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    setDebugHandles
+ * Signature: (Z)V
+ */
+JNIEXPORT void JNICALL Java_com_github_eliak_VScoreNative_setDebugHandles
+  (JNIEnv *, jclass, jboolean);
 
 /*
  * Class:     com_github_eliak_VScoreNative
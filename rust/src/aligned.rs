@@ -1,156 +1,210 @@
-use jni::objects::JObject;
-use jni::sys::{jfloatArray, jsize};
+use jni::objects::{JObject, JValue};
+use jni::sys::{jbyteArray, jfloatArray, jint, jsize};
 use jni::JNIEnv;
-use packed_simd::{f32x16, f32x4, f32x8};
-use rand::Rng;
-use std::collections::HashMap;
+use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::path::Path;
 use std::sync::{Arc, RwLock};
 
-const SIZE_VECTOR: usize = 512;
-const SIZE_VECTOR_AS_JSIZE: jsize = SIZE_VECTOR as jsize;
+use crate::binary::BinaryItem;
+use crate::cache::{Cache, CacheKey};
+use crate::error::{Error, Result};
+use crate::half::{ByteOrder, Encoding, F16Item};
+use crate::int8::Int8Item;
+use crate::metric::{Metric, Normalization};
+use crate::pool::ThreadPool;
+use crate::simd;
+use crate::snapshot::{self, SnapshotHeader};
+use crate::store::{StoredValues, StoredVector, VectorStore};
+use crate::topk::{ScoredDoc, TopK};
+use crate::warm::Warmer;
+
+/// Number of `f32` lanes processed by the widest SIMD kernel.
+const LANES: usize = 16;
 
 pub type DocId = i64;
 
-// type Cache = Arc<RwLock<HashMap<i32, Arc<Vec<f32>>, BuildHasherDefault<FNV1aHasher32>>>>;
-// type Cache = Arc<RwLock<HashMap<i32, Arc<Vec<f32>>, BuildHasherDefault<FxHasher32>>>>;
-pub type Cache = Arc<RwLock<HashMap<DocId, Arc<Item>>>>;
+/// Opaque id the Java side assigns to a segment core, see `VScorerNativeFactory.segmentKey`.
+/// Vector stores and snapshots outlive the process, the keys they hold must name the same
+/// segment in every process, which `VScorerNativeFactory.stableSegmentKey` derives from the
+/// segment id. Those keys are positive, `NO_SEGMENT` and negative keys are local to a process.
+pub type SegmentKey = i64;
 
-fn new_cache() -> Cache {
-    // Arc::new(RwLock::new(HashMap::with_capacity_and_hasher(1000, BuildHasherDefault::<FNV1aHasher32>::default())))
-    // Arc::new(RwLock::new(HashMap::with_capacity_and_hasher(1000, BuildHasherDefault::<FxHasher32>::default())))
-    Arc::new(RwLock::new(HashMap::with_capacity(1000)))
+/// Whether `segment` names the same segment in every process, see `SegmentKey`.
+pub fn is_stable(segment: SegmentKey) -> bool {
+    segment > NO_SEGMENT
 }
 
+/// Segment key used by callers that address documents by a global id only.
+pub const NO_SEGMENT: SegmentKey = 0;
+
+/// One 64-byte aligned block of `LANES` floats, the storage unit of `Vector`.
 #[repr(C, align(64))]
-struct Vector([f32; SIZE_VECTOR]);
+#[derive(Clone, Copy)]
+struct Lane([f32; LANES]);
+
+/// Heap allocated vector of runtime dimension. Storage is padded with zeros up to a multiple
+/// of `LANES`, so every full chunk of 16 floats starts at a 64-byte aligned address.
+#[derive(Clone)]
+struct Vector {
+    lanes: Vec<Lane>,
+    len: usize,
+}
 
 impl Deref for Vector {
-    type Target = [f32; SIZE_VECTOR];
+    type Target = [f32];
     fn deref(&self) -> &Self::Target {
-        &self.0
+        unsafe { std::slice::from_raw_parts(self.lanes.as_ptr() as *const f32, self.len) }
     }
 }
 
 impl DerefMut for Vector {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        unsafe { std::slice::from_raw_parts_mut(self.lanes.as_mut_ptr() as *mut f32, self.len) }
     }
 }
 
 impl Vector {
-    pub fn new() -> Vector {
-        Vector([0f32; SIZE_VECTOR])
+    pub fn new(dim: usize) -> Vector {
+        Vector {
+            lanes: vec![Lane([0f32; LANES]); dim.div_ceil(LANES)],
+            len: dim,
+        }
     }
 
+    /// Kernels are dispatched at runtime to the widest instruction set of the CPU, see
+    /// `simd::level`.
     pub fn doc_product(&self, other: &Vector) -> f32 {
-        return self.dot_product_16(other);
+        assert_eq!(self.len, other.len);
+        simd::dot_product(self, other)
     }
 
     pub fn dot_product_with_unaligned(&self, slice: &[f32]) -> f32 {
-        assert_eq!(slice.len(), SIZE_VECTOR);
-        self.chunks_exact(16)
-            .map(f32x16::from_slice_aligned)
-            .zip(slice.chunks_exact(16).map(f32x16::from_slice_unaligned))
-            .map(|(a, b)| a * b)
-            .sum::<f32x16>()
-            .sum()
+        assert_eq!(slice.len(), self.len);
+        simd::dot_product(self, slice)
     }
 
-    #[inline]
-    fn doc_product_base(&self, another: &Vector) -> f32 {
-        let mut dot_product: f32 = 0f32;
-        for i in 0..SIZE_VECTOR {
-            dot_product += self[i] * another[i];
-        }
-        return dot_product;
-    }
-
-    #[inline]
-    fn dot_product_4(&self, another: &Vector) -> f32 {
-        self.chunks_exact(4)
-            .map(f32x4::from_slice_aligned)
-            .zip(another.chunks_exact(4).map(f32x4::from_slice_aligned))
-            .map(|(a, b)| a * b)
-            .sum::<f32x4>()
-            .sum()
-    }
-
-    #[inline]
-    fn dot_product_8(&self, another: &Vector) -> f32 {
-        self.chunks_exact(8)
-            .map(f32x8::from_slice_aligned)
-            .zip(another.chunks_exact(8).map(f32x8::from_slice_aligned))
-            .map(|(a, b)| a * b)
-            .sum::<f32x8>()
-            .sum()
-    }
-
-    #[inline]
-    fn dot_product_16(&self, another: &Vector) -> f32 {
-        self.chunks_exact(16)
-            .map(f32x16::from_slice_aligned)
-            .zip(another.chunks_exact(16).map(f32x16::from_slice_aligned))
-            .map(|(a, b)| a * b)
-            .sum::<f32x16>()
-            .sum()
+    pub fn squared_distance(&self, another: &Vector) -> f32 {
+        assert_eq!(self.len, another.len);
+        simd::squared_distance(self, another)
     }
 }
 
+#[derive(Clone)]
 pub struct Item {
     vector: Vector,
     magnitude: f32,
 }
 
 impl Item {
-    pub fn new() -> Item {
+    pub fn new(dim: usize) -> Item {
         Item {
-            vector: Vector::new(),
+            vector: Vector::new(dim),
             magnitude: 0f32,
         }
     }
 
-    pub fn random() -> Item {
-        let mut item = Item::new();
+    #[cfg(test)]
+    pub fn random(dim: usize) -> Item {
+        let mut item = Item::new(dim);
         item.fill_random();
-        return item;
+        item
     }
 
-    pub fn from_jni_float_array(env: &JNIEnv, array: jfloatArray) -> Item {
-        let len = env.get_array_length(array).unwrap();
-        if len < SIZE_VECTOR_AS_JSIZE {
-            panic!(
+    pub fn from_slice(values: &[f32]) -> Item {
+        let mut item = Item::new(values.len());
+        item.vector.copy_from_slice(values);
+        item.update_magnitude();
+        item
+    }
+
+    /// Item of a vector whose magnitude was computed beforehand.
+    pub fn with_magnitude(values: &[f32], magnitude: f32) -> Item {
+        let mut item = Item::new(values.len());
+        item.vector.copy_from_slice(values);
+        item.magnitude = magnitude;
+        item
+    }
+
+    /// Copies a Java `float[]` of `dim` components, or of `dim + 1` when the magnitude was
+    /// precomputed and appended to the components.
+    pub fn from_jni_float_array(env: &JNIEnv, array: jfloatArray, dim: usize) -> Result<Item> {
+        if array.is_null() {
+            return Err(Error::IllegalArgument("vector is null".to_string()));
+        }
+        let len = env.get_array_length(array)?;
+        let dim_as_jsize = dim as jsize;
+        if len < dim_as_jsize {
+            return Err(Error::IllegalArgument(format!(
                 "array length {:?} is lower then required {:?}",
-                len, SIZE_VECTOR_AS_JSIZE
-            );
+                len, dim_as_jsize
+            )));
         }
-        let mut item = Item::new();
-        env.get_float_array_region(array, 0, item.vector.as_mut())
-            .unwrap();
-        if len == SIZE_VECTOR_AS_JSIZE {
+        let mut item = Item::new(dim);
+        env.get_float_array_region(array, 0, item.vector.as_mut())?;
+        if len == dim_as_jsize {
             let mut dot_product: f64 = 0f64;
-            for i in 0..SIZE_VECTOR {
+            for i in 0..dim {
                 dot_product += (item.vector[i] as f64).powi(2);
             }
             item.magnitude = dot_product.sqrt() as f32;
-        } else if len + 1 == SIZE_VECTOR_AS_JSIZE {
+        } else if len == dim_as_jsize + 1 {
             let mut magnitude = [0f32];
-            env.get_float_array_region(array, SIZE_VECTOR_AS_JSIZE, magnitude.as_mut())
-                .unwrap();
+            env.get_float_array_region(array, dim_as_jsize, magnitude.as_mut())?;
             item.magnitude = magnitude[0];
         } else {
-            panic!(
+            return Err(Error::IllegalArgument(format!(
                 "array length {:?} is greater then required {:?}",
-                len, SIZE_VECTOR_AS_JSIZE
-            );
+                len, dim_as_jsize
+            )));
         }
-        return item;
+        Ok(item)
     }
 
+    pub fn dim(&self) -> usize {
+        self.vector.len()
+    }
+
+    pub fn values(&self) -> &[f32] {
+        &self.vector
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        self.magnitude
+    }
+
+    /// Scales the vector to unit length. A zero vector is left as is.
+    pub fn normalize(&mut self) {
+        if self.magnitude == 0f32 {
+            return;
+        }
+        let scale = 1f32 / self.magnitude;
+        for value in self.vector.iter_mut() {
+            *value *= scale;
+        }
+        self.magnitude = 1f32;
+    }
+
+    /// Memory held by the item, used to account it against the cache budget.
+    pub fn size_in_bytes(&self) -> usize {
+        mem::size_of::<Item>() + self.vector.lanes.len() * mem::size_of::<Lane>()
+    }
+
+    fn update_magnitude(&mut self) {
+        let mut dot_product: f64 = 0f64;
+        for value in self.vector.iter() {
+            dot_product += (*value as f64).powi(2);
+        }
+        self.magnitude = dot_product.sqrt() as f32;
+    }
+
+    #[cfg(test)]
     pub fn fill_random(&mut self) {
+        use rand::Rng;
+
         let mut rng = rand::thread_rng();
         let mut dot_product: f64 = 0f64;
-        for i in 0..SIZE_VECTOR {
+        for i in 0..self.dim() {
             let val = rng.gen::<f32>();
             dot_product += (val as f64).powi(2);
             self.vector[i] = val;
@@ -167,65 +221,684 @@ impl Item {
     }
 
     pub fn cosine_similarity(&self, another: &Item) -> f32 {
-        return self.dot_product(another) / (self.magnitude * another.magnitude);
+        self.dot_product(another) / (self.magnitude * another.magnitude)
+    }
+
+    pub fn squared_distance(&self, another: &Item) -> f32 {
+        self.vector.squared_distance(&another.vector)
+    }
+}
+
+/// How a `ScorerFactory` keeps document vectors in its cache.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Storage {
+    /// Full precision `Item`s.
+    F32,
+    /// `Int8Item`s, a quarter of the memory at a small loss of precision.
+    Int8,
+    /// `BinaryItem`s, one bit per component. Scores are rough estimates meant to pick candidates
+    /// for a full precision re-rank, see `Scorer::top_k_reranked`.
+    Binary,
+    /// `F16Item`s, half the memory, scored with a mixed precision dot product.
+    F16,
+}
+
+impl Storage {
+    /// Maps the ordinal of `com.github.eliak.VectorStorage` to the native storage.
+    pub fn from_ordinal(ordinal: jint) -> Option<Storage> {
+        match ordinal {
+            0 => Some(Storage::F32),
+            1 => Some(Storage::Int8),
+            2 => Some(Storage::Binary),
+            3 => Some(Storage::F16),
+            _ => None,
+        }
+    }
+}
+
+/// A vector whose norm is within this distance of one counts as unit normalized.
+pub const UNIT_NORM_TOLERANCE: f32 = 1e-3;
+
+/// Whether a `ScorerFactory` holds unit normalized vectors only. Cosine of unit vectors is their
+/// dot product, so scorers of such factories skip the division by the magnitudes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnitNorm {
+    /// Vectors of any norm.
+    Off,
+    /// Vectors are declared unit normalized, ones whose norm deviates from one by more than
+    /// `UNIT_NORM_TOLERANCE` are rejected.
+    Validate,
+    /// Vectors are normalized on ingestion, zero vectors are rejected.
+    Normalize,
+}
+
+impl UnitNorm {
+    /// Maps the ordinal of `com.github.eliak.UnitNorm` to the native mode.
+    pub fn from_ordinal(ordinal: jint) -> Option<UnitNorm> {
+        match ordinal {
+            0 => Some(UnitNorm::Off),
+            1 => Some(UnitNorm::Validate),
+            2 => Some(UnitNorm::Normalize),
+            _ => None,
+        }
+    }
+
+    pub fn is_unit(&self) -> bool {
+        *self != UnitNorm::Off
+    }
+
+    /// Validates or normalizes a query or document vector entering the factory.
+    pub fn apply(&self, mut item: Item) -> Result<Item> {
+        match self {
+            UnitNorm::Off => Ok(item),
+            UnitNorm::Validate if (item.magnitude() - 1f32).abs() > UNIT_NORM_TOLERANCE => {
+                Err(Error::IllegalArgument(format!(
+                    "vector norm {:?} is not one within {:?}",
+                    item.magnitude(),
+                    UNIT_NORM_TOLERANCE
+                )))
+            }
+            UnitNorm::Validate => Ok(item),
+            UnitNorm::Normalize if item.magnitude() == 0f32 => Err(Error::IllegalArgument(
+                "zero vector cannot be normalized".to_string(),
+            )),
+            UnitNorm::Normalize => {
+                item.normalize();
+                Ok(item)
+            }
+        }
+    }
+}
+
+/// A document vector as held by the cache, in the representation of the factory `Storage`.
+#[derive(Clone)]
+pub enum DocVector {
+    F32(Item),
+    Int8(Int8Item),
+    Binary(BinaryItem),
+    F16(F16Item),
+}
+
+impl DocVector {
+    pub fn encode(item: Item, storage: Storage) -> DocVector {
+        match storage {
+            Storage::F32 => DocVector::F32(item),
+            Storage::Int8 => DocVector::Int8(Int8Item::quantize(&item)),
+            Storage::Binary => DocVector::Binary(BinaryItem::quantize(&item)),
+            Storage::F16 => DocVector::F16(F16Item::quantize(&item)),
+        }
+    }
+
+    /// Memory held by the vector, used to account it against the cache budget.
+    pub fn size_in_bytes(&self) -> usize {
+        match self {
+            DocVector::F32(item) => item.size_in_bytes(),
+            DocVector::Int8(item) => item.size_in_bytes(),
+            DocVector::Binary(item) => item.size_in_bytes(),
+            DocVector::F16(item) => item.size_in_bytes(),
+        }
+    }
+}
+
+impl From<Item> for DocVector {
+    fn from(item: Item) -> DocVector {
+        DocVector::F32(item)
     }
 }
 
 pub struct ScorerFactory {
-    pub(crate) cache: Cache,
+    dim: usize,
+    storage: Storage,
+    encoding: Encoding,
+    unit_norm: UnitNorm,
+    pub(crate) cache: Arc<Cache>,
+    /// Vectors persisted on disk, looked up on cache misses before the callbacks.
+    store: RwLock<Option<Arc<VectorStore>>>,
+    /// Workers exhaustive `Scorer::top_k` scans are split over, `None` scans on the caller.
+    pool: Option<Arc<ThreadPool>>,
 }
 
 impl ScorerFactory {
-    pub fn new() -> ScorerFactory {
-        ScorerFactory { cache: new_cache() }
+    pub fn new(dim: usize, capacity_bytes: usize) -> ScorerFactory {
+        ScorerFactory::with_storage(dim, capacity_bytes, Storage::F32)
     }
-    pub fn scorer(&self, query_vector: Item) -> Scorer {
-        Scorer {
-            query_vector: Box::new(query_vector),
+
+    pub fn with_storage(dim: usize, capacity_bytes: usize, storage: Storage) -> ScorerFactory {
+        ScorerFactory::with_format(
+            dim,
+            capacity_bytes,
+            storage,
+            Encoding::F32,
+            UnitNorm::Off,
+            0,
+        )
+    }
+
+    /// `encoding` tells how the callbacks of the scorers serialize document vectors, see
+    /// `Scorer::fetch`, `storage` how they are kept once decoded and `unit_norm` whether
+    /// queries and documents are unit normalized. With `scan_threads` above one, scorers split
+    /// segment scans without candidates over a pool of that many threads.
+    pub fn with_format(
+        dim: usize,
+        capacity_bytes: usize,
+        storage: Storage,
+        encoding: Encoding,
+        unit_norm: UnitNorm,
+        scan_threads: usize,
+    ) -> ScorerFactory {
+        ScorerFactory {
+            dim,
+            storage,
+            encoding,
+            unit_norm,
+            cache: Arc::new(Cache::for_items(
+                capacity_bytes,
+                DocVector::encode(Item::new(dim), storage).size_in_bytes(),
+            )),
+            store: RwLock::new(None),
+            pool: if scan_threads > 1 {
+                Some(Arc::new(ThreadPool::new(scan_threads)))
+            } else {
+                None
+            },
+        }
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn invalidate(&self, segment: SegmentKey, doc_id: DocId) -> bool {
+        self.cache.invalidate(&CacheKey::new(segment, doc_id))
+    }
+
+    /// Forgets all vectors of a closed segment.
+    pub fn drop_segment(&self, segment: SegmentKey) -> usize {
+        self.cache.drop_segment(segment)
+    }
+
+    pub fn clear(&self) {
+        self.cache.clear()
+    }
+
+    /// Persists every cached vector with its magnitude to `path`, see `crate::snapshot` for the
+    /// format. Vectors cached under a segment key local to this process are left out, they would
+    /// be loaded for another segment. Returns the number of vectors written.
+    pub fn save_snapshot(&self, path: &Path) -> Result<usize> {
+        let entries = self.cache.entries();
+        let entries: Vec<(CacheKey, &DocVector)> = entries
+            .iter()
+            .filter(|(key, _)| is_stable(key.segment))
+            .map(|(key, doc)| (*key, doc.as_ref()))
+            .collect();
+        snapshot::write(path, self.snapshot_header(), &entries)
+    }
+
+    /// Fills the cache from a snapshot saved by a factory of the same dimension, storage and
+    /// unit norm, also by another process holding a copy of the index since the snapshot only
+    /// holds stable segment keys. The snapshot is verified as a whole before anything
+    /// is cached. Vectors beyond the cache capacity are evicted as usual. Returns the number of
+    /// vectors read.
+    pub fn load_snapshot(&self, path: &Path) -> Result<usize> {
+        let (header, entries) = snapshot::read(path)?;
+        if header != self.snapshot_header() {
+            return Err(Error::IllegalArgument(format!(
+                "snapshot of {:?} does not match the factory {:?}",
+                header,
+                self.snapshot_header()
+            )));
+        }
+        let len = entries.len();
+        for (key, doc) in entries.into_iter() {
+            self.cache.insert(key, Arc::new(doc));
+        }
+        Ok(len)
+    }
+
+    fn snapshot_header(&self) -> SnapshotHeader {
+        SnapshotHeader {
+            storage: self.storage,
+            unit_norm: self.unit_norm,
+            dim: self.dim,
+        }
+    }
+
+    /// Preloads whole segments into the cache in bulk rather than one miss at a time.
+    pub fn warmer(&self) -> Warmer {
+        Warmer {
+            dim: self.dim,
+            storage: self.storage,
+            encoding: self.encoding,
+            unit_norm: self.unit_norm,
             cache: self.cache.clone(),
         }
     }
+
+    /// Maps a vector store written by `StoreWriter` and scores documents found in it in place,
+    /// replacing a store opened before. Scorers created earlier keep the store they started
+    /// with. Returns the number of vectors in the store. Vectors are looked up by the stable
+    /// `SegmentKey` they were written under, whatever factory or process wrote them.
+    pub fn open_store(&self, path: &Path) -> Result<usize> {
+        let store = VectorStore::open(path)?;
+        if store.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "vector store dimension {:?} does not match factory dimension {:?}",
+                store.dim(),
+                self.dim
+            )));
+        }
+        let len = store.len();
+        *self.store.write().unwrap() = Some(Arc::new(store));
+        Ok(len)
+    }
+
+    /// Stops looking up vectors in the store, it is unmapped once the last scorer using it is
+    /// dropped. Returns whether a store was open.
+    pub fn close_store(&self) -> bool {
+        self.store.write().unwrap().take().is_some()
+    }
+
+    /// Fails when the query does not pass the `UnitNorm` of the factory.
+    pub fn scorer(
+        &self,
+        query_vector: Item,
+        metric: Metric,
+        normalization: Normalization,
+    ) -> Result<Scorer> {
+        let query_vector = self.unit_norm.apply(query_vector)?;
+        Ok(Scorer {
+            query_int8: Int8Item::quantize(&query_vector),
+            query_binary: BinaryItem::quantize(&query_vector),
+            query_vector: Box::new(query_vector),
+            metric,
+            normalization,
+            storage: self.storage,
+            encoding: self.encoding,
+            unit_norm: self.unit_norm,
+            cache: self.cache.clone(),
+            store: self.store.read().unwrap().clone(),
+            pool: self.pool.clone(),
+        })
+    }
 }
 
 pub struct Scorer {
     query_vector: Box<Item>,
-    cache: Cache,
+    /// The query quantized like the documents of a `Storage::Int8` factory.
+    query_int8: Int8Item,
+    query_binary: BinaryItem,
+    metric: Metric,
+    normalization: Normalization,
+    storage: Storage,
+    encoding: Encoding,
+    unit_norm: UnitNorm,
+    cache: Arc<Cache>,
+    store: Option<Arc<VectorStore>>,
+    pool: Option<Arc<ThreadPool>>,
 }
 
 impl Scorer {
-    pub fn score(&self, env: &JNIEnv, doc_id: DocId, callback: JObject) -> f32 {
-        self.cosine_similarity(env, doc_id, callback)
+    pub fn score(
+        &self,
+        env: &JNIEnv,
+        segment: SegmentKey,
+        doc_id: DocId,
+        callback: JObject,
+    ) -> Result<f32> {
+        self.score_key(CacheKey::new(segment, doc_id), || {
+            self.fetch(env, callback, None)
+        })
+    }
+
+    /// Scores a document whose serialized vector is at hand, e.g. the `BytesRef` of its binary
+    /// doc value, instead of requesting it from a callback. `bytes` is only called when the
+    /// vector is not cached, it is decoded with the factory `Encoding` in the given byte order.
+    pub fn score_bytes<F>(
+        &self,
+        segment: SegmentKey,
+        doc_id: DocId,
+        order: ByteOrder,
+        bytes: F,
+    ) -> Result<f32>
+    where
+        F: FnOnce() -> Result<Vec<u8>>,
+    {
+        self.score_key(CacheKey::new(segment, doc_id), || {
+            self.encoding
+                .decode(&bytes()?, self.query_vector.dim(), order)
+                .map(Some)
+        })
+    }
+
+    /// Scores documents of one segment in a single call. Vectors missing from the cache are
+    /// requested from the callback in the order of `doc_ids`, see `fetch`.
+    pub fn score_batch(
+        &self,
+        env: &JNIEnv,
+        segment: SegmentKey,
+        doc_ids: &[jint],
+        scores: &mut [f32],
+        callback: JObject,
+    ) -> Result<()> {
+        for (doc_id, score) in doc_ids.iter().zip(scores.iter_mut()) {
+            *score = self.score_key(CacheKey::new(segment, *doc_id as DocId), || {
+                self.fetch(env, callback, Some(*doc_id))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Scores a document from the cache, else in place from the store, else from the vector
+    /// `miss` returns, which is then cached.
+    fn score_key<F>(&self, key: CacheKey, miss: F) -> Result<f32>
+    where
+        F: FnOnce() -> Result<Option<Item>>,
+    {
+        if let Some(doc) = self.cache.get(&key) {
+            return Ok(self.score_doc(doc.as_ref()));
+        }
+        if let Some(stored) = self.stored(&key) {
+            return Ok(self.score_stored(&stored));
+        }
+        let doc = self.load(key, miss()?)?;
+        Ok(self.score_doc(doc.as_ref()))
+    }
+
+    /// Ranks documents of one segment natively and returns the `k` best, best first.
+    /// `candidates` restricts the search to the given documents, without it every vector of the
+    /// segment resident in the cache or the store is scored, split over the scan threads of the
+    /// factory when it has some. Candidates found in neither are skipped.
+    pub fn top_k(
+        &self,
+        segment: SegmentKey,
+        k: usize,
+        candidates: Option<&[DocId]>,
+    ) -> Vec<ScoredDoc> {
+        let sign = self.rank_sign();
+        let mut top_k = TopK::new(k);
+        match (candidates, self.pool.as_ref()) {
+            (Some(doc_ids), _) => {
+                for doc_id in doc_ids.iter() {
+                    let key = CacheKey::new(segment, *doc_id);
+                    if let Some(doc) = self.cache.get(&key) {
+                        top_k.push(*doc_id, sign * self.score_doc(doc.as_ref()));
+                    } else if let Some(stored) = self.stored(&key) {
+                        top_k.push(*doc_id, sign * self.score_stored(&stored));
+                    }
+                }
+            }
+            (None, Some(pool)) => {
+                let parts = pool.size();
+                for part in pool.map(parts, |part| self.scan(segment, k, part, parts)) {
+                    top_k.merge(part);
+                }
+            }
+            (None, None) => top_k = self.scan(segment, k, 0, 1),
+        }
+        self.unsign(top_k)
+    }
+
+    /// Ranks the `part`th of `parts` slices of the segment into a heap of its own, the store is
+    /// sliced by key range and the cache by shard.
+    fn scan(&self, segment: SegmentKey, k: usize, part: usize, parts: usize) -> TopK {
+        let sign = self.rank_sign();
+        let mut top_k = TopK::new(k);
+        // Stored documents are ranked by the store pass only, the cache pass skips them. The
+        // keys of the store never change, so a document cached by a concurrent call while the
+        // parts run is still ranked exactly once. Documents also cached, e.g. before the store
+        // was opened, are scored from the cache.
+        if let Some(store) = self.store.as_ref() {
+            let range = store.segment_range(segment);
+            let len = range.len();
+            let slice = range.start + len * part / parts..range.start + len * (part + 1) / parts;
+            store.for_each_in_range(slice, |doc_id, stored| {
+                let score = match self.cache.peek(&CacheKey::new(segment, doc_id)) {
+                    Some(doc) => self.score_doc(doc.as_ref()),
+                    None => self.score_stored(&stored),
+                };
+                top_k.push(doc_id, sign * score);
+            });
+        }
+        for shard in (part..self.cache.shard_count()).step_by(parts) {
+            self.cache.for_each_in_shard(shard, segment, |doc_id, doc| {
+                let stored = self
+                    .store
+                    .as_ref()
+                    .is_some_and(|store| store.get(segment, doc_id).is_some());
+                if !stored {
+                    top_k.push(doc_id, sign * self.score_doc(doc));
+                }
+            });
+        }
+        top_k
     }
 
-    pub fn dot_product(&self, env: &JNIEnv, doc_id: DocId, callback: JObject) -> f32 {
-        let item: Arc<Item> = self.item(env, doc_id, callback);
-        self.query_vector.dot_product(item.as_ref())
+    /// Two-stage search: ranks the cached vectors like `top_k`, then rescores the best `rerank`
+    /// of them on full precision vectors with `rerank`. Meant for quantized storages, e.g. a
+    /// Hamming distance pre-filter over `Storage::Binary` codes followed by exact cosine.
+    /// Without a callback or with `rerank` not above `k` this is a plain `top_k`.
+    pub fn top_k_reranked(
+        &self,
+        env: &JNIEnv,
+        segment: SegmentKey,
+        k: usize,
+        candidates: Option<&[DocId]>,
+        rerank: usize,
+        callback: JObject,
+    ) -> Result<Vec<ScoredDoc>> {
+        if rerank <= k || callback.is_null() {
+            return Ok(self.top_k(segment, k, candidates));
+        }
+        let hits = self.top_k(segment, rerank, candidates);
+        self.rerank(env, &hits, k, callback)
     }
 
-    pub fn cosine_similarity(&self, env: &JNIEnv, doc_id: DocId, callback: JObject) -> f32 {
-        let item: Arc<Item> = self.item(env, doc_id, callback);
-        self.query_vector.cosine_similarity(item.as_ref())
+    /// Rescores `hits` with the full precision vectors requested from the callback and returns
+    /// the `k` best of them. Meant for candidates ranked on quantized vectors, the vectors
+    /// loaded here are not cached.
+    pub fn rerank(
+        &self,
+        env: &JNIEnv,
+        hits: &[ScoredDoc],
+        k: usize,
+        callback: JObject,
+    ) -> Result<Vec<ScoredDoc>> {
+        let sign = self.rank_sign();
+        let mut top_k = TopK::new(k);
+        for hit in hits.iter() {
+            let item = match self.fetch(env, callback, Some(hit.doc as jint))? {
+                Some(item) => self.unit_norm.apply(item)?,
+                None => {
+                    return Err(Error::Io(format!(
+                        "callback returned no vector for doc {:?}",
+                        hit.doc
+                    )))
+                }
+            };
+            top_k.push(hit.doc, sign * self.score_item(&item));
+        }
+        Ok(self.unsign(top_k))
     }
 
-    fn item(&self, env: &JNIEnv, doc_id: DocId, callback: JObject) -> Arc<Item> {
-        // return VEC_DUMMY.clone();
-        let cache = self.cache.clone();
-        {
-            let guard = cache.read().unwrap();
-            if let Some(v) = guard.get(&doc_id) {
-                return v.clone();
+    /// Sign that makes better documents rank higher in a `TopK`, the heap keeps the highest
+    /// values so distances are flipped.
+    fn rank_sign(&self) -> f32 {
+        if self.normalization.lower_is_better(self.metric) {
+            -1f32
+        } else {
+            1f32
+        }
+    }
+
+    fn unsign(&self, top_k: TopK) -> Vec<ScoredDoc> {
+        let sign = self.rank_sign();
+        let mut hits = top_k.into_sorted_vec();
+        for hit in hits.iter_mut() {
+            hit.score *= sign;
+        }
+        hits
+    }
+
+    #[inline]
+    fn score_doc(&self, doc: &DocVector) -> f32 {
+        let value = match doc {
+            DocVector::F32(item) if self.unit_norm.is_unit() => {
+                self.metric.compute_unit(self.query_vector.as_ref(), item)
             }
+            DocVector::F32(item) => self.metric.compute(self.query_vector.as_ref(), item),
+            DocVector::Int8(item) => self.metric.compute_from_dot_product(
+                self.query_int8.dot_product(item),
+                self.query_int8.magnitude(),
+                item.magnitude(),
+            ),
+            DocVector::Binary(item) => self.metric.compute_from_dot_product(
+                self.dot_product_doc(doc),
+                self.query_binary.magnitude(),
+                item.magnitude(),
+            ),
+            DocVector::F16(item) => self.metric.compute_from_dot_product(
+                item.dot_product(self.query_vector.values()),
+                self.query_vector.magnitude(),
+                item.magnitude(),
+            ),
+        };
+        self.normalization.apply(self.metric, value)
+    }
+
+    #[inline]
+    fn score_item(&self, item: &Item) -> f32 {
+        let value = if self.unit_norm.is_unit() {
+            self.metric.compute_unit(self.query_vector.as_ref(), item)
+        } else {
+            self.metric.compute(self.query_vector.as_ref(), item)
+        };
+        self.normalization.apply(self.metric, value)
+    }
+
+    /// Scores a vector of the store. Stored vectors are taken as they were written, with a
+    /// `UnitNorm` factory they are scaled to unit length by their stored magnitude. The store
+    /// does not validate them, a zero vector has no direction and is scored as one orthogonal
+    /// to the query rather than as NaN.
+    #[inline]
+    fn score_stored(&self, stored: &StoredVector) -> f32 {
+        let query = self.query_vector.as_ref();
+        let value = match stored.values {
+            _ if self.unit_norm.is_unit() => {
+                let dot_product = if stored.magnitude > 0f32 {
+                    stored.dot_product(query.values()) / stored.magnitude
+                } else {
+                    0f32
+                };
+                self.metric
+                    .compute_from_dot_product(dot_product, 1f32, 1f32)
+            }
+            StoredValues::F32(values) if self.metric == Metric::Euclidean => {
+                simd::squared_distance(query.values(), values)
+            }
+            _ => self.metric.compute_from_dot_product(
+                stored.dot_product(query.values()),
+                query.magnitude(),
+                stored.magnitude,
+            ),
+        };
+        self.normalization.apply(self.metric, value)
+    }
+
+    fn stored(&self, key: &CacheKey) -> Option<StoredVector<'_>> {
+        self.store
+            .as_ref()
+            .and_then(|store| store.get(key.segment, key.doc))
+    }
+
+    #[inline]
+    fn dot_product_doc(&self, doc: &DocVector) -> f32 {
+        match doc {
+            DocVector::F32(item) => self.query_vector.dot_product(item),
+            DocVector::Int8(item) => self.query_int8.dot_product(item),
+            DocVector::Binary(item) => {
+                self.query_binary.cosine_similarity(item)
+                    * self.query_binary.magnitude()
+                    * item.magnitude()
+            }
+            DocVector::F16(item) => item.dot_product(self.query_vector.values()),
+        }
+    }
+
+    /// Addresses the document by a global id, as the `com.iqmen` bindings do.
+    pub fn dot_product(&self, env: &JNIEnv, doc_id: DocId, callback: JObject) -> Result<f32> {
+        let doc: Arc<DocVector> = self.item(env, CacheKey::new(NO_SEGMENT, doc_id), callback)?;
+        Ok(self.dot_product_doc(doc.as_ref()))
+    }
+
+    pub fn cosine_similarity(&self, env: &JNIEnv, doc_id: DocId, callback: JObject) -> Result<f32> {
+        let doc: Arc<DocVector> = self.item(env, CacheKey::new(NO_SEGMENT, doc_id), callback)?;
+        Ok(match doc.as_ref() {
+            DocVector::F32(item) if self.unit_norm.is_unit() => self.query_vector.dot_product(item),
+            DocVector::F32(item) => self.query_vector.cosine_similarity(item),
+            DocVector::Int8(item) => {
+                self.query_int8.dot_product(item) / (self.query_int8.magnitude() * item.magnitude())
+            }
+            DocVector::Binary(item) => self.query_binary.cosine_similarity(item),
+            DocVector::F16(item) => {
+                item.dot_product(self.query_vector.values())
+                    / (self.query_vector.magnitude() * item.magnitude())
+            }
+        })
+    }
+
+    fn item(&self, env: &JNIEnv, key: CacheKey, callback: JObject) -> Result<Arc<DocVector>> {
+        if let Some(v) = self.cache.get(&key) {
+            return Ok(v);
         }
-        let result = env.call_method(callback, "binaryValue", "()[F", &[]);
-        if result.is_err() {
-            panic!("receive binaryValue error: {}", result.err().unwrap());
+        let item = self.fetch(env, callback, None)?;
+        self.load(key, item)
+    }
+
+    /// Requests a document vector from the callback: `binaryValue` returning a `float[]` for
+    /// `Encoding::F32`, otherwise `binaryBytes` returning the serialized vector, decoded here.
+    /// Batch callbacks take the doc id as argument. `None` when the callback returned null.
+    fn fetch(&self, env: &JNIEnv, callback: JObject, doc_id: Option<jint>) -> Result<Option<Item>> {
+        let (name, signature) = match (self.encoding, doc_id) {
+            (Encoding::F32, None) => ("binaryValue", "()[F"),
+            (Encoding::F32, Some(_)) => ("binaryValue", "(I)[F"),
+            (_, None) => ("binaryBytes", "()[B"),
+            (_, Some(_)) => ("binaryBytes", "(I)[B"),
+        };
+        // an exception thrown by the callback stays pending and surfaces as Error::JavaException
+        let value = match doc_id {
+            Some(doc_id) => env.call_method(callback, name, signature, &[JValue::Int(doc_id)])?,
+            None => env.call_method(callback, name, signature, &[])?,
+        };
+        let array = value.l()?.into_inner();
+        if array.is_null() {
+            return Ok(None);
         }
-        let b_array = result.unwrap().l().unwrap().into_inner() as jfloatArray;
-        let vec: Arc<Item> = Arc::new(Item::from_jni_float_array(env, b_array));
-        {
-            let mut guard = cache.write().unwrap();
-            guard.insert(doc_id.clone(), vec.clone());
+        let dim = self.query_vector.dim();
+        match self.encoding {
+            Encoding::F32 => Item::from_jni_float_array(env, array as jfloatArray, dim).map(Some),
+            _ => {
+                let bytes = env.convert_byte_array(array as jbyteArray)?;
+                // binaryBytes hands over doc values as written by ByteBuffer
+                self.encoding
+                    .decode(&bytes, dim, ByteOrder::BigEndian)
+                    .map(Some)
+            }
         }
-        return vec;
+    }
+
+    /// Caches a vector returned by the callback in the representation of the storage.
+    fn load(&self, key: CacheKey, item: Option<Item>) -> Result<Arc<DocVector>> {
+        let item = match item {
+            Some(item) => self.unit_norm.apply(item)?,
+            None => {
+                return Err(Error::Io(format!(
+                    "callback returned no vector for doc {:?} of segment {:?}",
+                    key.doc, key.segment
+                )))
+            }
+        };
+        let vec: Arc<DocVector> = Arc::new(DocVector::encode(item, self.storage));
+        self.cache.insert(key, vec.clone());
+        Ok(vec)
     }
 }
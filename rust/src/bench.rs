@@ -0,0 +1,89 @@
+//! Benchmark harness on stable Rust, standing in for the nightly `test::Bencher`.
+//!
+//! Benchmarks are plain functions taking a `&mut Bencher`, registered with `benchmarks!` as
+//! ignored tests so that a regular test run skips them:
+//!
+//! ```text
+//! cargo test --release -- --ignored --nocapture benches::
+//! ```
+pub use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Number of timed samples, each of a batch of iterations.
+const SAMPLES: usize = 50;
+
+/// Time a batch of iterations should take at least, to keep the clock resolution negligible.
+const BATCH_TIME: Duration = Duration::from_millis(2);
+
+#[derive(Default)]
+pub struct Bencher {
+    /// Nanoseconds per iteration of every sample.
+    samples: Vec<f64>,
+}
+
+impl Bencher {
+    /// Times `f`, first doubling the batch size until a batch takes `BATCH_TIME`, then running
+    /// `SAMPLES` batches of that size.
+    pub fn iter<T, F: FnMut() -> T>(&mut self, mut f: F) {
+        let mut batch = 1u64;
+        while time(&mut f, batch) < BATCH_TIME && batch < 1 << 30 {
+            batch *= 2;
+        }
+        self.samples = (0..SAMPLES)
+            .map(|_| time(&mut f, batch).as_nanos() as f64 / batch as f64)
+            .collect();
+    }
+}
+
+fn time<T, F: FnMut() -> T>(f: &mut F, batch: u64) -> Duration {
+    let start = Instant::now();
+    for _ in 0..batch {
+        black_box(f());
+    }
+    start.elapsed()
+}
+
+/// Runs a benchmark and prints the median time per iteration with the median absolute
+/// deviation, in the format of `cargo bench`.
+pub fn run(name: &str, bench: fn(&mut Bencher)) {
+    let mut bencher = Bencher::default();
+    bench(&mut bencher);
+    let mut samples = bencher.samples;
+    if samples.is_empty() {
+        println!("test {} ... no iterations", name);
+        return;
+    }
+    let median = median(&mut samples);
+    let mut deviations: Vec<f64> = samples.iter().map(|s| (s - median).abs()).collect();
+    let deviation = self::median(&mut deviations);
+    println!(
+        "test {} ... bench: {:>14.0} ns/iter (+/- {:.0})",
+        name, median, deviation
+    );
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2f64
+    } else {
+        values[mid]
+    }
+}
+
+/// Registers benchmark functions of the enclosing module as ignored tests of a `benches`
+/// submodule.
+macro_rules! benchmarks {
+    ($($name:ident),* $(,)?) => {
+        mod benches {
+            $(
+                #[test]
+                #[ignore]
+                fn $name() {
+                    crate::bench::run(stringify!($name), super::$name);
+                }
+            )*
+        }
+    };
+}
@@ -0,0 +1,84 @@
+use std::f32::consts::PI;
+use std::mem;
+
+use crate::aligned::Item;
+
+/// Vector reduced to the sign bit of every component, 1/32 of the memory of an `Item`.
+///
+/// The Hamming distance between two codes estimates the angle between the vectors, exactly so
+/// for random projections and well enough to pick candidates for components centred around
+/// zero, such as face embeddings. The magnitude is kept from the original vector.
+#[derive(Clone)]
+pub struct BinaryItem {
+    words: Vec<u64>,
+    dim: usize,
+    magnitude: f32,
+}
+
+impl BinaryItem {
+    pub fn quantize(item: &Item) -> BinaryItem {
+        let values = item.values();
+        let mut words = vec![0u64; values.len().div_ceil(64)];
+        for (i, value) in values.iter().enumerate() {
+            if *value > 0f32 {
+                words[i / 64] |= 1u64 << (i % 64);
+            }
+        }
+        let magnitude = values
+            .iter()
+            .map(|value| (*value as f64).powi(2))
+            .sum::<f64>()
+            .sqrt() as f32;
+        BinaryItem {
+            words,
+            dim: values.len(),
+            magnitude,
+        }
+    }
+
+    /// Rebuilds an item from the sign bits of a `dim` dimensional vector and its magnitude.
+    pub fn from_words(words: Vec<u64>, dim: usize, magnitude: f32) -> BinaryItem {
+        BinaryItem {
+            words,
+            dim,
+            magnitude,
+        }
+    }
+
+    /// Sign bits, component `i` is bit `i % 64` of word `i / 64`.
+    pub fn words(&self) -> &[u64] {
+        &self.words
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        self.magnitude
+    }
+
+    /// Memory held by the item, used to account it against the cache budget.
+    pub fn size_in_bytes(&self) -> usize {
+        mem::size_of::<BinaryItem>() + self.words.len() * mem::size_of::<u64>()
+    }
+
+    /// Number of components whose signs differ.
+    #[inline]
+    pub fn hamming_distance(&self, another: &BinaryItem) -> u32 {
+        self.words
+            .iter()
+            .zip(another.words.iter())
+            .map(|(one, another)| (one ^ another).count_ones())
+            .sum()
+    }
+
+    /// Cosine similarity estimated from the fraction of differing signs.
+    #[inline]
+    pub fn cosine_similarity(&self, another: &BinaryItem) -> f32 {
+        if self.dim == 0 {
+            return 0f32;
+        }
+        (PI * self.hamming_distance(another) as f32 / self.dim as f32).cos()
+    }
+}
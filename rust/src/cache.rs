@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasher, BuildHasherDefault};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use hashers::fx_hash::FxHasher;
+
+use crate::aligned::{DocId, DocVector, SegmentKey};
+
+/// Most independently locked shards of a cache, see `Cache::for_items`.
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// Fewest items each shard of a cache created by `Cache::for_items` has room for, smaller
+/// budgets are split over fewer shards.
+pub const MIN_ITEMS_PER_SHARD: usize = 64;
+
+type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+/// Identity of a cached vector: the segment the document lives in and its segment local id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub segment: SegmentKey,
+    pub doc: DocId,
+}
+
+impl CacheKey {
+    pub fn new(segment: SegmentKey, doc: DocId) -> CacheKey {
+        CacheKey { segment, doc }
+    }
+}
+
+/// Capacity-bounded map of document vectors shared by all scorers of a `ScorerFactory`.
+///
+/// Keys are spread over shards, each with its own lock and an equal part of the byte budget,
+/// so concurrent segment searches rarely wait on each other. Within a shard eviction follows
+/// the CLOCK policy: a hit only sets the reference bit of the entry, so reads stay under the
+/// shared lock, and the write lock taken on insert sweeps the clock hand, giving referenced
+/// entries a second chance before evicting them.
+pub struct Cache {
+    hasher: FxBuildHasher,
+    shards: Vec<RwLock<Shard>>,
+}
+
+struct Shard {
+    capacity_bytes: usize,
+    map: HashMap<CacheKey, usize, FxBuildHasher>,
+    slots: Vec<Option<Slot>>,
+    free: Vec<usize>,
+    hand: usize,
+    used_bytes: usize,
+}
+
+struct Slot {
+    key: CacheKey,
+    item: Arc<DocVector>,
+    bytes: usize,
+    referenced: AtomicBool,
+}
+
+impl Cache {
+    #[cfg(test)]
+    pub fn new(capacity_bytes: usize) -> Cache {
+        Cache::with_shards(capacity_bytes, DEFAULT_SHARDS)
+    }
+
+    /// Cache of up to `DEFAULT_SHARDS` shards for items of about `item_bytes` each, fewer when
+    /// the budget is small so that every shard has room for `MIN_ITEMS_PER_SHARD` of them. A
+    /// budget below that is a single shard, every item fitting the budget is cached.
+    pub fn for_items(capacity_bytes: usize, item_bytes: usize) -> Cache {
+        let mut shards = DEFAULT_SHARDS;
+        while shards > 1 && capacity_bytes / shards < MIN_ITEMS_PER_SHARD * item_bytes {
+            shards /= 2;
+        }
+        Cache::with_shards(capacity_bytes, shards)
+    }
+
+    /// `shards` is rounded up to a power of two.
+    pub fn with_shards(capacity_bytes: usize, shards: usize) -> Cache {
+        let shards = shards.max(1).next_power_of_two();
+        Cache {
+            hasher: FxBuildHasher::default(),
+            shards: (0..shards)
+                .map(|_| {
+                    RwLock::new(Shard {
+                        capacity_bytes: capacity_bytes / shards,
+                        map: HashMap::with_capacity_and_hasher(64, FxBuildHasher::default()),
+                        slots: Vec::with_capacity(64),
+                        free: Vec::new(),
+                        hand: 0,
+                        used_bytes: 0,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<Arc<DocVector>> {
+        let guard = self.shard(key).read().unwrap();
+        guard.map.get(key).and_then(|index| {
+            guard.slots[*index].as_ref().map(|slot| {
+                // skip the store when already set, keeps hot entries' cache lines shared
+                if !slot.referenced.load(Ordering::Relaxed) {
+                    slot.referenced.store(true, Ordering::Relaxed);
+                }
+                slot.item.clone()
+            })
+        })
+    }
+
+    /// Like `get` without marking the entry as referenced, for full scans that must not protect
+    /// what they visit from eviction, see `for_each_in_shard`.
+    pub fn peek(&self, key: &CacheKey) -> Option<Arc<DocVector>> {
+        let guard = self.shard(key).read().unwrap();
+        guard
+            .map
+            .get(key)
+            .and_then(|index| guard.slots[*index].as_ref())
+            .map(|slot| slot.item.clone())
+    }
+
+    /// Stores the item evicting others of the same shard until it fits. Items larger than the
+    /// budget of a shard are not cached at all.
+    pub fn insert(&self, key: CacheKey, item: Arc<DocVector>) {
+        let mut guard = self.shard(&key).write().unwrap();
+        guard.insert(key, item);
+    }
+
+    pub fn invalidate(&self, key: &CacheKey) -> bool {
+        let mut guard = self.shard(key).write().unwrap();
+        match guard.map.get(key).cloned() {
+            Some(index) => {
+                guard.remove_at(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes every vector of the segment, returns how many were dropped.
+    pub fn drop_segment(&self, segment: SegmentKey) -> usize {
+        let mut dropped = 0;
+        for shard in self.shards.iter() {
+            let mut guard = shard.write().unwrap();
+            let indexes: Vec<usize> = guard
+                .map
+                .iter()
+                .filter(|(key, _)| key.segment == segment)
+                .map(|(_, index)| *index)
+                .collect();
+            for index in indexes.iter() {
+                guard.remove_at(*index);
+            }
+            dropped += indexes.len();
+        }
+        dropped
+    }
+
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            let mut guard = shard.write().unwrap();
+            guard.map.clear();
+            guard.slots.clear();
+            guard.free.clear();
+            guard.hand = 0;
+            guard.used_bytes = 0;
+        }
+    }
+
+    /// Every cached entry, as of the time each shard is visited. Like `for_each_in_shard` the
+    /// entries are not marked as referenced.
+    pub fn entries(&self) -> Vec<(CacheKey, Arc<DocVector>)> {
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            let guard = shard.read().unwrap();
+            for slot in guard.slots.iter().flatten() {
+                entries.push((slot.key, slot.item.clone()));
+            }
+        }
+        entries
+    }
+
+    /// Calls `f` with every vector of the segment currently cached in one shard, a full scan of
+    /// the segment visits every shard from 0 to `shard_count`. Unlike `get` the entries are not
+    /// marked as referenced, so a full scan does not protect the whole segment from eviction.
+    pub fn for_each_in_shard<F: FnMut(DocId, &DocVector)>(
+        &self,
+        shard: usize,
+        segment: SegmentKey,
+        mut f: F,
+    ) {
+        let guard = self.shards[shard].read().unwrap();
+        for slot in guard.slots.iter().flatten() {
+            if slot.key.segment == segment {
+                f(slot.key.doc, slot.item.as_ref());
+            }
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().map.len())
+            .sum()
+    }
+
+    #[cfg(test)]
+    pub fn used_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().used_bytes)
+            .sum()
+    }
+
+    #[cfg(test)]
+    pub fn capacity_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().unwrap().capacity_bytes)
+            .sum()
+    }
+
+    #[inline]
+    fn shard(&self, key: &CacheKey) -> &RwLock<Shard> {
+        let hash = self.hasher.hash_one(key) as usize;
+        &self.shards[hash & (self.shards.len() - 1)]
+    }
+}
+
+impl Shard {
+    fn insert(&mut self, key: CacheKey, item: Arc<DocVector>) {
+        let bytes = item.size_in_bytes();
+        if bytes > self.capacity_bytes {
+            return;
+        }
+        if let Some(index) = self.map.get(&key).cloned() {
+            self.remove_at(index);
+        }
+        self.evict(self.capacity_bytes - bytes);
+        // a fresh entry starts unreferenced, so documents touched once by a scan are the first
+        // to go
+        let slot = Some(Slot {
+            key,
+            item,
+            bytes,
+            referenced: AtomicBool::new(false),
+        });
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index] = slot;
+                index
+            }
+            None => {
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        self.map.insert(key, index);
+        self.used_bytes += bytes;
+    }
+
+    /// Sweeps the clock hand until no more than `limit` bytes are in use.
+    fn evict(&mut self, limit: usize) {
+        while self.used_bytes > limit && !self.map.is_empty() {
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
+            let index = self.hand;
+            self.hand += 1;
+            let referenced = match &self.slots[index] {
+                Some(slot) => slot.referenced.swap(false, Ordering::Relaxed),
+                None => continue,
+            };
+            if !referenced {
+                self.remove_at(index);
+            }
+        }
+    }
+
+    fn remove_at(&mut self, index: usize) {
+        if let Some(slot) = self.slots[index].take() {
+            self.map.remove(&slot.key);
+            self.free.push(index);
+            self.used_bytes -= slot.bytes;
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+use jni::errors::ErrorKind;
+use jni::objects::JObject;
+use jni::JNIEnv;
+
+/// Exception thrown for failures that have no better matching Java exception.
+const NATIVE_EXCEPTION: &str = "com/github/eliak/VScoreNativeException";
+const FALLBACK_EXCEPTION: &str = "java/lang/RuntimeException";
+
+#[derive(Debug)]
+pub enum Error {
+    /// Thrown as `IllegalArgumentException`.
+    IllegalArgument(String),
+    /// Thrown as `IOException`.
+    Io(String),
+    /// Thrown as `IllegalStateException`, e.g. for a handle whose object was destroyed.
+    IllegalState(String),
+    /// Failure of a JNI call, thrown as `VScoreNativeException`.
+    Jni(jni::errors::Error),
+    /// A Java exception is already pending, e.g. thrown by a callback, and is left to propagate.
+    JavaException,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<jni::errors::Error> for Error {
+    fn from(error: jni::errors::Error) -> Self {
+        match error.kind() {
+            ErrorKind::JavaException => Error::JavaException,
+            _ => Error::Jni(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error.to_string())
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::IllegalArgument(message) => write!(f, "{}", message),
+            Error::Io(message) => write!(f, "{}", message),
+            Error::IllegalState(message) => write!(f, "{}", message),
+            Error::Jni(error) => write!(f, "jni call failed: {}", error),
+            Error::JavaException => write!(f, "java exception was thrown"),
+        }
+    }
+}
+
+impl Error {
+    /// Java exception class the error is raised as, `None` when one is already pending.
+    fn exception_class(&self) -> Option<&'static str> {
+        match self {
+            Error::IllegalArgument(_) => Some("java/lang/IllegalArgumentException"),
+            Error::Io(_) => Some("java/io/IOException"),
+            Error::IllegalState(_) => Some("java/lang/IllegalStateException"),
+            Error::Jni(_) => Some(NATIVE_EXCEPTION),
+            Error::JavaException => None,
+        }
+    }
+
+    fn throw(&self, env: &JNIEnv) {
+        if let Some(class) = self.exception_class() {
+            throw(env, class, &self.to_string());
+        }
+    }
+
+    /// Builds the exception the error would be raised as without throwing it, to hand it to a
+    /// callback instead, e.g. at the end of work done on a native thread.
+    pub fn to_throwable<'a>(&self, env: &JNIEnv<'a>) -> Result<JObject<'a>> {
+        let class = self.exception_class().unwrap_or(NATIVE_EXCEPTION);
+        let message = env.new_string(self.to_string())?;
+        Ok(env.new_object(
+            class,
+            "(Ljava/lang/String;)V",
+            &[JObject::from(message).into()],
+        )?)
+    }
+}
+
+/// Runs the body of an exported function. Errors and panics are turned into a pending Java
+/// exception and `default` is returned instead, nothing unwinds into the JVM.
+pub fn jni_call<T, F>(env: &JNIEnv, default: T, f: F) -> T
+where
+    F: FnOnce() -> Result<T>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(error)) => {
+            error.throw(env);
+            default
+        }
+        Err(payload) => {
+            throw(env, NATIVE_EXCEPTION, &panic_message(payload));
+            default
+        }
+    }
+}
+
+/// Runs the body of a `JavaCritical_` function, these get no `JNIEnv` to throw with, so any
+/// failure is reported as NaN.
+pub fn critical_call<F>(f: F) -> f32
+where
+    F: FnOnce() -> Option<f32>,
+{
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Some(value)) => value,
+        _ => f32::NAN,
+    }
+}
+
+fn throw(env: &JNIEnv, class: &str, message: &str) {
+    if env.exception_check().unwrap_or(false) {
+        return;
+    }
+    if env.throw_new(class, message).is_err() {
+        // the class could not be loaded, e.g. for the com.iqmen bindings
+        let _ = env.exception_clear();
+        let _ = env.throw_new(FALLBACK_EXCEPTION, message);
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        format!("native panic: {}", message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        format!("native panic: {}", message)
+    } else {
+        "native panic".to_string()
+    }
+}
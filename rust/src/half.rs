@@ -0,0 +1,301 @@
+use std::mem;
+
+use jni::sys::jint;
+
+use crate::aligned::Item;
+use crate::error::{Error, Result};
+use crate::record;
+use crate::simd::{self, Level};
+
+/// Byte order of serialized vectors. `java.nio.ByteBuffer` writes big endian unless told
+/// otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteOrder {
+    BigEndian,
+    LittleEndian,
+}
+
+impl ByteOrder {
+    pub fn from_big_endian(big_endian: bool) -> ByteOrder {
+        if big_endian {
+            ByteOrder::BigEndian
+        } else {
+            ByteOrder::LittleEndian
+        }
+    }
+
+    #[inline]
+    fn u16(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self {
+            ByteOrder::BigEndian => u16::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => u16::from_le_bytes(bytes),
+        }
+    }
+
+    #[inline]
+    fn f32(&self, bytes: &[u8]) -> f32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self {
+            ByteOrder::BigEndian => f32::from_be_bytes(bytes),
+            ByteOrder::LittleEndian => f32::from_le_bytes(bytes),
+        }
+    }
+}
+
+/// Dot product of a query and a vector serialized as f32 components in the given byte order,
+/// read in place whatever the alignment of `bytes`.
+pub fn dot_product_serialized(query: &[f32], bytes: &[u8], order: ByteOrder) -> f32 {
+    query
+        .iter()
+        .zip(bytes.chunks_exact(4))
+        .map(|(value, bytes)| value * order.f32(bytes))
+        .sum()
+}
+
+/// How document vectors are serialized in the doc values handed over by Java.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    /// `float[]` decoded on the Java side.
+    F32,
+    /// IEEE 754 half precision, two bytes per component.
+    F16,
+    /// bfloat16, the upper half of an f32, two bytes per component.
+    BF16,
+    /// Self-describing record of `crate::record`, whatever its element type.
+    Record,
+}
+
+impl Encoding {
+    /// Maps the ordinal of `com.github.eliak.VectorEncoding` to the native encoding.
+    pub fn from_ordinal(ordinal: jint) -> Option<Encoding> {
+        match ordinal {
+            0 => Some(Encoding::F32),
+            1 => Some(Encoding::F16),
+            2 => Some(Encoding::BF16),
+            3 => Some(Encoding::Record),
+            _ => None,
+        }
+    }
+
+    /// Size of a component, `None` for records whose header tells the element type.
+    pub fn bytes_per_component(&self) -> Option<usize> {
+        match self {
+            Encoding::F32 => Some(4),
+            Encoding::F16 | Encoding::BF16 => Some(2),
+            Encoding::Record => None,
+        }
+    }
+
+    /// Decodes a serialized vector of `dim` components into an aligned item. A trailing
+    /// `dim + 1`th component is taken as the magnitude of the vector, like the Java side stores
+    /// it next to the components. Records are always little endian and ignore `order`.
+    pub fn decode(&self, bytes: &[u8], dim: usize, order: ByteOrder) -> Result<Item> {
+        let width = match self.bytes_per_component() {
+            Some(width) => width,
+            None => return record::decode_item(bytes, dim),
+        };
+        if bytes.len() != dim * width && bytes.len() != (dim + 1) * width {
+            return Err(Error::IllegalArgument(format!(
+                "{:?} bytes do not hold a {:?} vector of dimension {:?}",
+                bytes.len(),
+                self,
+                dim
+            )));
+        }
+        let values = self.components(bytes, order);
+        if values.len() > dim {
+            Ok(Item::with_magnitude(&values[..dim], values[dim]))
+        } else {
+            Ok(Item::from_slice(&values))
+        }
+    }
+
+    /// Components of an element encoding, `bytes` holds a whole number of them.
+    pub(crate) fn components(&self, bytes: &[u8], order: ByteOrder) -> Vec<f32> {
+        let width = match self.bytes_per_component() {
+            Some(width) => width,
+            None => unreachable!("records are decoded by crate::record"),
+        };
+        bytes
+            .chunks_exact(width)
+            .map(|b| match self {
+                Encoding::F32 | Encoding::Record => order.f32(b),
+                Encoding::F16 => f16_to_f32(order.u16(b)),
+                Encoding::BF16 => bf16_to_f32(order.u16(b)),
+            })
+            .collect()
+    }
+}
+
+/// Vector stored in half precision, half the memory of an `Item`. Scored against an f32 query
+/// without converting the whole vector back, see `dot_product_f16`.
+#[derive(Clone)]
+pub struct F16Item {
+    bits: Vec<u16>,
+    magnitude: f32,
+}
+
+impl F16Item {
+    pub fn quantize(item: &Item) -> F16Item {
+        let bits: Vec<u16> = item
+            .values()
+            .iter()
+            .map(|value| f32_to_f16(*value))
+            .collect();
+        let magnitude = bits
+            .iter()
+            .map(|bits| (f16_to_f32(*bits) as f64).powi(2))
+            .sum::<f64>()
+            .sqrt() as f32;
+        F16Item { bits, magnitude }
+    }
+
+    /// Rebuilds an item from its half precision components and their magnitude.
+    pub fn from_bits(bits: Vec<u16>, magnitude: f32) -> F16Item {
+        F16Item { bits, magnitude }
+    }
+
+    pub fn bits(&self) -> &[u16] {
+        &self.bits
+    }
+
+    /// Magnitude of the half precision vector.
+    pub fn magnitude(&self) -> f32 {
+        self.magnitude
+    }
+
+    /// Memory held by the item, used to account it against the cache budget.
+    pub fn size_in_bytes(&self) -> usize {
+        mem::size_of::<F16Item>() + self.bits.len() * mem::size_of::<u16>()
+    }
+
+    #[inline]
+    pub fn dot_product(&self, query: &[f32]) -> f32 {
+        dot_product_f16(query, &self.bits)
+    }
+}
+
+/// Mixed precision dot product of an f32 query and a half precision document. Uses F16C to
+/// widen eight components per instruction when the CPU has it.
+#[inline]
+pub fn dot_product_f16(query: &[f32], doc: &[u16]) -> f32 {
+    debug_assert_eq!(query.len(), doc.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if simd::level() >= Level::Avx2 {
+            return unsafe { dot_product_f16_f16c(query, doc) };
+        }
+    }
+    dot_product_f16_base(query, doc)
+}
+
+pub fn dot_product_f16_base(query: &[f32], doc: &[u16]) -> f32 {
+    query
+        .iter()
+        .zip(doc.iter())
+        .map(|(q, d)| q * f16_to_f32(*d))
+        .sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx,f16c,fma")]
+unsafe fn dot_product_f16_f16c(query: &[f32], doc: &[u16]) -> f32 {
+    use std::arch::x86_64::*;
+
+    let len = query.len().min(doc.len());
+    let split = len - len % 8;
+    let mut sum = _mm256_setzero_ps();
+    let mut i = 0;
+    while i < split {
+        let d = _mm256_cvtph_ps(_mm_loadu_si128(doc.as_ptr().add(i) as *const __m128i));
+        let q = _mm256_loadu_ps(query.as_ptr().add(i));
+        sum = _mm256_fmadd_ps(q, d, sum);
+        i += 8;
+    }
+    let mut lanes = [0f32; 8];
+    _mm256_storeu_ps(lanes.as_mut_ptr(), sum);
+    lanes.iter().sum::<f32>() + dot_product_f16_base(&query[split..len], &doc[split..len])
+}
+
+/// Dot product of an f32 query and a bfloat16 document, widening is a shift so no kernel of
+/// its own is needed.
+pub fn dot_product_bf16(query: &[f32], doc: &[u16]) -> f32 {
+    query
+        .iter()
+        .zip(doc.iter())
+        .map(|(q, d)| q * bf16_to_f32(*d))
+        .sum()
+}
+
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits >> 15) as u32) << 31;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+    match exponent {
+        0 => {
+            // zero or subnormal, exactly mantissa * 2^-24
+            let magnitude = mantissa as f32 / (1u32 << 24) as f32;
+            if sign == 0 {
+                magnitude
+            } else {
+                -magnitude
+            }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+/// Rounds to the nearest half precision value, ties to even. Out of range values become
+/// infinities.
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        return sign | round_shift(mantissa, shift) as u16;
+    }
+    // a carry out of the mantissa correctly bumps the exponent, up to infinity
+    sign | round_shift(((exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+pub fn bf16_to_f32(bits: u16) -> f32 {
+    f32::from_bits((bits as u32) << 16)
+}
+
+/// Rounds to the nearest bfloat16 value, ties to even.
+pub fn f32_to_bf16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    if value.is_nan() {
+        return ((bits >> 16) | 0x40) as u16;
+    }
+    (bits.wrapping_add(0x7fff + ((bits >> 16) & 1)) >> 16) as u16
+}
+
+/// `value >> shift` rounded to nearest, ties to even.
+#[inline]
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let half = 1u32 << (shift - 1);
+    let remainder = value & ((1u32 << shift) - 1);
+    let rounded = value >> shift;
+    if remainder > half || (remainder == half && rounded & 1 == 1) {
+        rounded + 1
+    } else {
+        rounded
+    }
+}
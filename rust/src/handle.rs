@@ -0,0 +1,215 @@
+use std::any::{self, Any};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+
+use jni::sys::jlong;
+
+use crate::error::{Error, Result};
+
+/// Opaque id of a native object held by Java, see `Registry`.
+pub type Handle = jlong;
+
+/// Handle of no object, also the owner of objects not created from another one.
+pub const NO_HANDLE: Handle = 0;
+
+lazy_static! {
+    /// Registry of all handles given out by the JNI exports.
+    pub static ref HANDLES: Registry = Registry::new();
+}
+
+/// Table of the native objects Java holds handles to, in place of raw pointers cast to `jlong`.
+///
+/// A handle packs the index of a slot in its low 32 bits and the generation of the slot in its
+/// high 32 bits. Removing an object bumps the generation of its slot, so a handle used after its
+/// object was destroyed, destroyed twice or made up fails validation instead of reading freed
+/// memory, even once the slot is reused. A slot whose generation would wrap is retired rather
+/// than reused, an old handle to it never validates again. Lookups hand out an `Arc`, an object
+/// removed while a call on another thread still uses it is dropped when that call returns.
+pub struct Registry {
+    table: RwLock<Table>,
+    /// Whether `leaked` reports the objects still registered under an owner.
+    debug: AtomicBool,
+}
+
+#[derive(Default)]
+struct Table {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+struct Slot {
+    generation: u32,
+    entry: Option<Entry>,
+}
+
+struct Entry {
+    object: Arc<dyn Any + Send + Sync>,
+    /// Full name of the type of `object`, see `short_type_name`.
+    type_name: &'static str,
+    /// Handle of the object this one was created from, e.g. the factory of a scorer.
+    owner: Handle,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {
+            table: RwLock::new(Table::default()),
+            debug: AtomicBool::new(false),
+        }
+    }
+
+    /// Registers `object` and returns its handle, never `NO_HANDLE`. `owner` is the handle of
+    /// the object it was created from, `NO_HANDLE` for none.
+    pub fn insert<T: Any + Send + Sync>(&self, object: T, owner: Handle) -> Handle {
+        let entry = Entry {
+            object: Arc::new(object),
+            type_name: any::type_name::<T>(),
+            owner,
+        };
+        let mut table = self.table.write().unwrap();
+        let index = match table.free.pop() {
+            Some(index) => index,
+            None => {
+                table.slots.push(Slot {
+                    generation: 1,
+                    entry: None,
+                });
+                (table.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut table.slots[index as usize];
+        slot.entry = Some(entry);
+        ((slot.generation as u64) << 32 | index as u64) as Handle
+    }
+
+    /// Object of a live handle. Fails for `NO_HANDLE`, for a handle whose object was removed
+    /// and for a handle to an object of another type.
+    pub fn get<T: Any + Send + Sync>(&self, handle: Handle) -> Result<Arc<T>> {
+        let table = self.table.read().unwrap();
+        let entry = table.entry::<T>(handle)?;
+        Ok(downcast(entry))
+    }
+
+    /// Unregisters the object of a live handle, the handle is invalid from then on. The object
+    /// is dropped once calls still using it return.
+    pub fn remove<T: Any + Send + Sync>(&self, handle: Handle) -> Result<Arc<T>> {
+        let mut table = self.table.write().unwrap();
+        table.entry::<T>(handle)?;
+        let index = handle as u32;
+        let slot = &mut table.slots[index as usize];
+        let entry = slot.entry.take().unwrap();
+        // a wrapped generation would validate handles of the first objects of the slot again, so
+        // the slot is retired, left empty and off the free list
+        if let Some(generation) = slot.generation.checked_add(1) {
+            slot.generation = generation;
+            table.free.push(index);
+        }
+        Ok(downcast(&entry))
+    }
+
+    /// Turns the leak report of `leaked` on or off.
+    pub fn set_debug(&self, debug: bool) {
+        self.debug.store(debug, Ordering::Relaxed);
+    }
+
+    /// In debug mode, the handles and type names of the objects created from `owner` that are
+    /// still registered, e.g. scorers not destroyed before their factory. Empty otherwise.
+    pub fn leaked(&self, owner: Handle) -> Vec<(Handle, String)> {
+        if owner == NO_HANDLE || !self.debug.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+        let table = self.table.read().unwrap();
+        table
+            .slots
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let entry = slot.entry.as_ref()?;
+                if entry.owner != owner {
+                    return None;
+                }
+                Some((
+                    ((slot.generation as u64) << 32 | index as u64) as Handle,
+                    short_type_name(entry.type_name),
+                ))
+            })
+            .collect()
+    }
+
+    /// Number of live handles.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        let table = self.table.read().unwrap();
+        table
+            .slots
+            .iter()
+            .filter(|slot| slot.entry.is_some())
+            .count()
+    }
+
+    /// Moves the slot of a live handle to `generation` and returns the handle of its object
+    /// from then on, to test slots nearing the wrap of their generation.
+    #[cfg(test)]
+    pub fn set_generation(&self, handle: Handle, generation: u32) -> Handle {
+        let mut table = self.table.write().unwrap();
+        let index = handle as u32;
+        table.slots[index as usize].generation = generation;
+        ((generation as u64) << 32 | index as u64) as Handle
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl Table {
+    fn entry<T: Any>(&self, handle: Handle) -> Result<&Entry> {
+        let expected = || short_type_name(any::type_name::<T>());
+        if handle == NO_HANDLE {
+            return Err(Error::IllegalArgument(format!(
+                "null {} handle",
+                expected()
+            )));
+        }
+        let index = handle as u32 as usize;
+        let generation = (handle as u64 >> 32) as u32;
+        let entry = self
+            .slots
+            .get(index)
+            .filter(|slot| slot.generation == generation)
+            .and_then(|slot| slot.entry.as_ref())
+            .ok_or_else(|| {
+                Error::IllegalState(format!(
+                    "{} handle {:#x} is closed or invalid",
+                    expected(),
+                    handle
+                ))
+            })?;
+        if !entry.object.is::<T>() {
+            return Err(Error::IllegalArgument(format!(
+                "handle {:#x} is of type {}, expected {}",
+                handle,
+                short_type_name(entry.type_name),
+                expected()
+            )));
+        }
+        Ok(entry)
+    }
+}
+
+fn downcast<T: Any + Send + Sync>(entry: &Entry) -> Arc<T> {
+    entry
+        .object
+        .clone()
+        .downcast::<T>()
+        .unwrap_or_else(|_| unreachable!("type checked by Table::entry"))
+}
+
+/// Type name without module paths, also those of type parameters, as reported to Java.
+fn short_type_name(name: &str) -> String {
+    name.split_inclusive(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .map(|part| part.rsplit("::").next().unwrap_or(part))
+        .collect()
+}
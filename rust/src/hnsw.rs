@@ -0,0 +1,363 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::hash::BuildHasherDefault;
+use std::sync::{Arc, RwLock};
+
+use hashers::fx_hash::FxHasher;
+use rand::Rng;
+
+use crate::aligned::{DocId, Item};
+use crate::error::{Error, Result};
+use crate::metric::{Metric, Normalization};
+use crate::topk::ScoredDoc;
+
+type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+/// Build and search parameters of an `HnswIndex`.
+#[derive(Clone, Copy, Debug)]
+pub struct HnswParams {
+    /// Links kept per node on the upper layers, layer 0 keeps twice as many.
+    pub m: usize,
+    /// Size of the candidate list while linking a new node.
+    pub ef_construction: usize,
+    /// Default size of the candidate list of a search.
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> HnswParams {
+        HnswParams {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+/// Approximate nearest neighbour index over `Item`s, a Hierarchical Navigable Small World graph
+/// (Malkov & Yashunin). Inserts are incremental and take the write lock, searches share the
+/// read lock.
+pub struct HnswIndex {
+    dim: usize,
+    metric: Metric,
+    normalization: Normalization,
+    params: HnswParams,
+    graph: RwLock<Graph>,
+}
+
+struct Graph {
+    nodes: Vec<Node>,
+    docs: HashSet<DocId, FxBuildHasher>,
+    entry_point: Option<usize>,
+    max_level: usize,
+}
+
+struct Node {
+    doc: DocId,
+    item: Arc<Item>,
+    /// Neighbours of the node on each layer it lives on, layer 0 first.
+    links: Vec<Vec<u32>>,
+}
+
+/// A node and its distance to the current query, lower is closer.
+#[derive(Clone, Copy)]
+struct Candidate {
+    distance: f32,
+    node: u32,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Candidate) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl HnswIndex {
+    pub fn new(
+        dim: usize,
+        metric: Metric,
+        normalization: Normalization,
+        params: HnswParams,
+    ) -> Result<HnswIndex> {
+        if params.m < 2 || params.ef_construction == 0 || params.ef_search == 0 {
+            return Err(Error::IllegalArgument(format!(
+                "invalid hnsw parameters {:?}",
+                params
+            )));
+        }
+        Ok(HnswIndex {
+            dim,
+            metric,
+            normalization,
+            params,
+            graph: RwLock::new(Graph {
+                nodes: Vec::new(),
+                docs: HashSet::default(),
+                entry_point: None,
+                max_level: 0,
+            }),
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.graph.read().unwrap().nodes.len()
+    }
+
+    /// Links a new document into the graph. A document can be added only once.
+    pub fn add(&self, doc: DocId, item: Arc<Item>) -> Result<()> {
+        if item.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "vector dimension {:?} does not match index dimension {:?}",
+                item.dim(),
+                self.dim
+            )));
+        }
+        let level = self.random_level();
+        let mut graph = self.graph.write().unwrap();
+        if !graph.docs.insert(doc) {
+            return Err(Error::IllegalArgument(format!(
+                "doc {:?} is already indexed",
+                doc
+            )));
+        }
+        let node = graph.nodes.len() as u32;
+        graph.nodes.push(Node {
+            doc,
+            item: item.clone(),
+            links: vec![Vec::new(); level + 1],
+        });
+        let entry_point = match graph.entry_point {
+            Some(entry_point) => entry_point as u32,
+            None => {
+                graph.entry_point = Some(node as usize);
+                graph.max_level = level;
+                return Ok(());
+            }
+        };
+
+        let mut entry = Candidate {
+            distance: self.distance(&item, &graph.nodes[entry_point as usize].item),
+            node: entry_point,
+        };
+        for layer in (level + 1..=graph.max_level).rev() {
+            entry = self.greedy_closest(&graph, &item, entry, layer);
+        }
+        let mut entries = vec![entry];
+        for layer in (0..=level.min(graph.max_level)).rev() {
+            let found =
+                self.search_layer(&graph, &item, &entries, self.params.ef_construction, layer);
+            let neighbours = self.select_neighbours(&graph, &found, self.params.m);
+            graph.nodes[node as usize].links[layer] = neighbours.clone();
+            let max_links = self.max_links(layer);
+            for neighbour in neighbours {
+                graph.nodes[neighbour as usize].links[layer].push(node);
+                if graph.nodes[neighbour as usize].links[layer].len() > max_links {
+                    self.shrink_links(&mut graph, neighbour, layer, max_links);
+                }
+            }
+            entries = found;
+        }
+        if level > graph.max_level {
+            graph.max_level = level;
+            graph.entry_point = Some(node as usize);
+        }
+        Ok(())
+    }
+
+    /// Returns up to `k` approximate nearest documents, best first. `ef` of zero falls back to
+    /// the `ef_search` of the index, it is never lower than `k`.
+    pub fn search(&self, query: &Item, k: usize, ef: usize) -> Result<Vec<ScoredDoc>> {
+        if query.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "query dimension {:?} does not match index dimension {:?}",
+                query.dim(),
+                self.dim
+            )));
+        }
+        let graph = self.graph.read().unwrap();
+        let entry_point = match graph.entry_point {
+            Some(entry_point) => entry_point as u32,
+            None => return Ok(Vec::new()),
+        };
+        let ef = if ef == 0 { self.params.ef_search } else { ef }.max(k);
+        let mut entry = Candidate {
+            distance: self.distance(query, &graph.nodes[entry_point as usize].item),
+            node: entry_point,
+        };
+        for layer in (1..=graph.max_level).rev() {
+            entry = self.greedy_closest(&graph, query, entry, layer);
+        }
+        let found = self.search_layer(&graph, query, &[entry], ef, 0);
+        Ok(found
+            .iter()
+            .take(k)
+            .map(|candidate| {
+                let node = &graph.nodes[candidate.node as usize];
+                let value = self.metric.compute(query, &node.item);
+                ScoredDoc::new(node.doc, self.normalization.apply(self.metric, value))
+            })
+            .collect())
+    }
+
+    #[inline]
+    fn distance(&self, one: &Item, another: &Item) -> f32 {
+        self.metric.distance(one, another)
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m * 2
+        } else {
+            self.params.m
+        }
+    }
+
+    /// Draws the top layer of a new node from an exponentially decaying distribution.
+    fn random_level(&self) -> usize {
+        let level_mult = 1f64 / (self.params.m as f64).ln();
+        let uniform: f64 = rand::thread_rng().gen_range(f64::EPSILON, 1f64);
+        (-uniform.ln() * level_mult) as usize
+    }
+
+    fn greedy_closest(
+        &self,
+        graph: &Graph,
+        query: &Item,
+        mut entry: Candidate,
+        layer: usize,
+    ) -> Candidate {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for neighbour in graph.nodes[entry.node as usize].links[layer].iter() {
+                let distance = self.distance(query, &graph.nodes[*neighbour as usize].item);
+                if distance < entry.distance {
+                    entry = Candidate {
+                        distance,
+                        node: *neighbour,
+                    };
+                    changed = true;
+                }
+            }
+        }
+        entry
+    }
+
+    /// Beam search of one layer, returns up to `ef` closest nodes sorted by distance.
+    fn search_layer(
+        &self,
+        graph: &Graph,
+        query: &Item,
+        entries: &[Candidate],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<u32, FxBuildHasher> = HashSet::default();
+        // closest candidate on top
+        let mut candidates: BinaryHeap<std::cmp::Reverse<Candidate>> = BinaryHeap::new();
+        // farthest result on top
+        let mut results: BinaryHeap<Candidate> = BinaryHeap::with_capacity(ef + 1);
+        for entry in entries.iter() {
+            if visited.insert(entry.node) {
+                candidates.push(std::cmp::Reverse(*entry));
+                results.push(*entry);
+            }
+        }
+        while results.len() > ef {
+            results.pop();
+        }
+        while let Some(std::cmp::Reverse(closest)) = candidates.pop() {
+            if let Some(farthest) = results.peek() {
+                if closest.distance > farthest.distance && results.len() >= ef {
+                    break;
+                }
+            }
+            for neighbour in graph.nodes[closest.node as usize].links[layer].iter() {
+                if !visited.insert(*neighbour) {
+                    continue;
+                }
+                let distance = self.distance(query, &graph.nodes[*neighbour as usize].item);
+                let far_enough = results.len() >= ef
+                    && results
+                        .peek()
+                        .is_some_and(|farthest| distance >= farthest.distance);
+                if far_enough {
+                    continue;
+                }
+                let candidate = Candidate {
+                    distance,
+                    node: *neighbour,
+                };
+                candidates.push(std::cmp::Reverse(candidate));
+                results.push(candidate);
+                if results.len() > ef {
+                    results.pop();
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+
+    /// Neighbour selection heuristic: a candidate is kept only when it is closer to the new node
+    /// than to every neighbour kept so far, which preserves links towards distinct clusters. The
+    /// remaining slots are filled with the closest pruned candidates. `sorted` is ascending.
+    fn select_neighbours(&self, graph: &Graph, sorted: &[Candidate], m: usize) -> Vec<u32> {
+        let mut selected: Vec<u32> = Vec::with_capacity(m);
+        let mut pruned: Vec<u32> = Vec::new();
+        for candidate in sorted.iter() {
+            if selected.len() >= m {
+                break;
+            }
+            let item = &graph.nodes[candidate.node as usize].item;
+            let diverse = selected.iter().all(|kept| {
+                self.distance(item, &graph.nodes[*kept as usize].item) > candidate.distance
+            });
+            if diverse {
+                selected.push(candidate.node);
+            } else {
+                pruned.push(candidate.node);
+            }
+        }
+        for node in pruned {
+            if selected.len() >= m {
+                break;
+            }
+            selected.push(node);
+        }
+        selected
+    }
+
+    fn shrink_links(&self, graph: &mut Graph, node: u32, layer: usize, max_links: usize) {
+        let item = graph.nodes[node as usize].item.clone();
+        let mut sorted: Vec<Candidate> = graph.nodes[node as usize].links[layer]
+            .iter()
+            .map(|neighbour| Candidate {
+                distance: self.distance(&item, &graph.nodes[*neighbour as usize].item),
+                node: *neighbour,
+            })
+            .collect();
+        sorted.sort();
+        let links = self.select_neighbours(graph, &sorted, max_links);
+        graph.nodes[node as usize].links[layer] = links;
+    }
+}
@@ -0,0 +1,141 @@
+use std::mem;
+
+use crate::aligned::Item;
+use crate::simd::{self, Level};
+
+/// Vector stored with one signed byte per component, a quarter of the memory of an `Item`.
+///
+/// Components are quantized with an affine mapping fitted to the vector itself,
+/// `value ≈ offset + scale * code` where `offset = min + 128 * scale` spreads `[min, max]` over
+/// the full `i8` range. The magnitude is the one of the dequantized vector, so metrics derived
+/// from dot products of quantized vectors stay consistent, e.g. a vector is at distance zero of
+/// itself.
+#[derive(Clone)]
+pub struct Int8Item {
+    codes: Vec<i8>,
+    offset: f32,
+    scale: f32,
+    /// Sum of the codes, folds the offsets of both operands into a dot product.
+    code_sum: i32,
+    magnitude: f32,
+}
+
+impl Int8Item {
+    pub fn quantize(item: &Item) -> Int8Item {
+        let values = item.values();
+        let (min, max) = values
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), value| {
+                (min.min(*value), max.max(*value))
+            });
+        let (min, max) = if values.is_empty() {
+            (0f32, 0f32)
+        } else {
+            (min, max)
+        };
+        let scale = if max > min {
+            (max - min) / 255f32
+        } else {
+            1f32
+        };
+        let codes: Vec<i8> = values
+            .iter()
+            .map(|value| (((value - min) / scale).round() - 128f32).clamp(-128f32, 127f32) as i8)
+            .collect();
+        let mut quantized = Int8Item {
+            code_sum: codes.iter().map(|code| *code as i32).sum(),
+            codes,
+            offset: min + 128f32 * scale,
+            scale,
+            magnitude: 0f32,
+        };
+        quantized.magnitude = quantized.dot_product(&quantized).max(0f32).sqrt();
+        quantized
+    }
+
+    /// Rebuilds an item from the parts of `parts`, e.g. read back from a snapshot.
+    pub fn from_parts(codes: Vec<i8>, offset: f32, scale: f32, magnitude: f32) -> Int8Item {
+        Int8Item {
+            code_sum: codes.iter().map(|code| *code as i32).sum(),
+            codes,
+            offset,
+            scale,
+            magnitude,
+        }
+    }
+
+    /// Codes, offset, scale and magnitude, all that is needed to rebuild the item.
+    pub fn parts(&self) -> (&[i8], f32, f32, f32) {
+        (&self.codes, self.offset, self.scale, self.magnitude)
+    }
+
+    pub fn magnitude(&self) -> f32 {
+        self.magnitude
+    }
+
+    /// Memory held by the item, used to account it against the cache budget.
+    pub fn size_in_bytes(&self) -> usize {
+        mem::size_of::<Int8Item>() + self.codes.len()
+    }
+
+    #[cfg(test)]
+    /// Approximate components of the original vector.
+    pub fn dequantize(&self) -> Vec<f32> {
+        self.codes
+            .iter()
+            .map(|code| self.offset + self.scale * *code as f32)
+            .collect()
+    }
+
+    /// Dot product of the dequantized vectors, computed on the codes with integer arithmetic.
+    pub fn dot_product(&self, another: &Int8Item) -> f32 {
+        let n = self.codes.len() as f32;
+        let codes_dot = dot_product_i8(&self.codes, &another.codes) as f32;
+        n * self.offset * another.offset
+            + self.offset * another.scale * another.code_sum as f32
+            + another.offset * self.scale * self.code_sum as f32
+            + self.scale * another.scale * codes_dot
+    }
+}
+
+/// Integer dot product of two code vectors of the same length.
+#[inline]
+pub fn dot_product_i8(one: &[i8], another: &[i8]) -> i32 {
+    debug_assert_eq!(one.len(), another.len());
+    #[cfg(target_arch = "x86_64")]
+    {
+        if simd::level() >= Level::Avx2 {
+            return unsafe { dot_product_i8_avx2(one, another) };
+        }
+    }
+    dot_product_i8_base(one, another)
+}
+
+pub fn dot_product_i8_base(one: &[i8], another: &[i8]) -> i32 {
+    one.iter()
+        .zip(another.iter())
+        .map(|(a, b)| *a as i32 * *b as i32)
+        .sum()
+}
+
+/// Widens 16 codes at a time to `i16` and multiplies pairwise with `vpmaddwd`, which adds
+/// adjacent products into `i32` lanes, so the sums cannot overflow for any practical dimension.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn dot_product_i8_avx2(one: &[i8], another: &[i8]) -> i32 {
+    use std::arch::x86_64::*;
+
+    let len = one.len().min(another.len());
+    let split = len - len % 16;
+    let mut sum = _mm256_setzero_si256();
+    let mut i = 0;
+    while i < split {
+        let a = _mm256_cvtepi8_epi16(_mm_loadu_si128(one.as_ptr().add(i) as *const __m128i));
+        let b = _mm256_cvtepi8_epi16(_mm_loadu_si128(another.as_ptr().add(i) as *const __m128i));
+        sum = _mm256_add_epi32(sum, _mm256_madd_epi16(a, b));
+        i += 16;
+    }
+    let mut lanes = [0i32; 8];
+    _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum);
+    lanes.iter().sum::<i32>() + dot_product_i8_base(&one[split..len], &another[split..len])
+}
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+use std::hash::BuildHasherDefault;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+use hashers::fx_hash::FxHasher;
+
+use crate::aligned::{DocId, Item};
+use crate::error::{Error, Result};
+use crate::kmeans::{kmeans, nearest};
+use crate::metric::{Metric, Normalization};
+use crate::topk::{ScoredDoc, TopK};
+
+type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
+/// Inverted file index with flat storage (IVF-Flat): a k-means coarse quantizer assigns every
+/// vector to its nearest centroid and a search scans only the posting lists of the `nprobe`
+/// centroids nearest to the query, exhaustively and without compression.
+pub struct IvfIndex {
+    dim: usize,
+    metric: Metric,
+    normalization: Normalization,
+    nlist: usize,
+    nprobe: AtomicUsize,
+    lists: RwLock<Lists>,
+}
+
+struct Lists {
+    centroids: Vec<Item>,
+    postings: Vec<Vec<Posting>>,
+    docs: HashSet<DocId, FxBuildHasher>,
+}
+
+struct Posting {
+    doc: DocId,
+    item: Arc<Item>,
+}
+
+impl IvfIndex {
+    /// `nlist` is the number of centroids trained, `nprobe` the default number of lists a search
+    /// scans.
+    pub fn new(
+        dim: usize,
+        metric: Metric,
+        normalization: Normalization,
+        nlist: usize,
+        nprobe: usize,
+    ) -> Result<IvfIndex> {
+        if nlist == 0 || nprobe == 0 {
+            return Err(Error::IllegalArgument(format!(
+                "invalid ivf parameters nlist {:?}, nprobe {:?}",
+                nlist, nprobe
+            )));
+        }
+        Ok(IvfIndex {
+            dim,
+            metric,
+            normalization,
+            nlist,
+            nprobe: AtomicUsize::new(nprobe.min(nlist)),
+            lists: RwLock::new(Lists {
+                centroids: Vec::new(),
+                postings: Vec::new(),
+                docs: HashSet::default(),
+            }),
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.lists.read().unwrap().docs.len()
+    }
+
+    #[cfg(test)]
+    pub fn is_trained(&self) -> bool {
+        !self.lists.read().unwrap().centroids.is_empty()
+    }
+
+    pub fn nprobe(&self) -> usize {
+        self.nprobe.load(Ordering::Relaxed)
+    }
+
+    /// Number of lists scanned by searches that do not pass their own, clamped to `nlist`.
+    pub fn set_nprobe(&self, nprobe: usize) -> Result<()> {
+        if nprobe == 0 {
+            return Err(Error::IllegalArgument(
+                "nprobe must be positive".to_string(),
+            ));
+        }
+        self.nprobe.store(nprobe.min(self.nlist), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Trains the centroids with Lloyd's k-means on `samples`. Documents added before are
+    /// reassigned to the new centroids.
+    pub fn train(&self, samples: &[Item], iterations: usize) -> Result<()> {
+        if samples.len() < self.nlist {
+            return Err(Error::IllegalArgument(format!(
+                "{:?} training vectors are not enough for {:?} centroids",
+                samples.len(),
+                self.nlist
+            )));
+        }
+        if let Some(sample) = samples.iter().find(|sample| sample.dim() != self.dim) {
+            return Err(Error::IllegalArgument(format!(
+                "vector dimension {:?} does not match index dimension {:?}",
+                sample.dim(),
+                self.dim
+            )));
+        }
+        let centroids = kmeans(self.metric, samples, self.nlist, iterations.max(1));
+        let mut lists = self.lists.write().unwrap();
+        let postings: Vec<Posting> = lists.postings.drain(..).flatten().collect();
+        lists.centroids = centroids;
+        lists.postings = (0..self.nlist).map(|_| Vec::new()).collect();
+        for posting in postings {
+            let list = nearest(self.metric, &lists.centroids, &posting.item);
+            lists.postings[list].push(posting);
+        }
+        Ok(())
+    }
+
+    /// Appends the document to the posting list of its nearest centroid. A document can be added
+    /// only once and only after training.
+    pub fn add(&self, doc: DocId, item: Arc<Item>) -> Result<()> {
+        if item.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "vector dimension {:?} does not match index dimension {:?}",
+                item.dim(),
+                self.dim
+            )));
+        }
+        let mut lists = self.lists.write().unwrap();
+        if lists.centroids.is_empty() {
+            return Err(Error::IllegalArgument("index is not trained".to_string()));
+        }
+        if !lists.docs.insert(doc) {
+            return Err(Error::IllegalArgument(format!(
+                "doc {:?} is already indexed",
+                doc
+            )));
+        }
+        let list = nearest(self.metric, &lists.centroids, &item);
+        lists.postings[list].push(Posting { doc, item });
+        Ok(())
+    }
+
+    /// Returns up to `k` nearest documents of the `nprobe` lists closest to the query, best first.
+    /// `nprobe` of zero falls back to the one of the index.
+    pub fn search(&self, query: &Item, k: usize, nprobe: usize) -> Result<Vec<ScoredDoc>> {
+        if query.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "query dimension {:?} does not match index dimension {:?}",
+                query.dim(),
+                self.dim
+            )));
+        }
+        let nprobe = if nprobe == 0 { self.nprobe() } else { nprobe }.min(self.nlist);
+        let lists = self.lists.read().unwrap();
+        let mut probes = TopK::new(nprobe);
+        for (list, centroid) in lists.centroids.iter().enumerate() {
+            probes.push(list as DocId, -self.metric.distance(query, centroid));
+        }
+        // the heap keeps the highest values, flip the sign of distances so the closest win
+        let sign = if self.normalization.lower_is_better(self.metric) {
+            -1f32
+        } else {
+            1f32
+        };
+        let mut top_k = TopK::new(k);
+        for probe in probes.into_sorted_vec() {
+            for posting in lists.postings[probe.doc as usize].iter() {
+                let value = self.metric.compute(query, &posting.item);
+                top_k.push(
+                    posting.doc,
+                    sign * self.normalization.apply(self.metric, value),
+                );
+            }
+        }
+        let mut hits = top_k.into_sorted_vec();
+        for hit in hits.iter_mut() {
+            hit.score *= sign;
+        }
+        Ok(hits)
+    }
+}
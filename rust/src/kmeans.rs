@@ -0,0 +1,64 @@
+use rand::seq::index::sample;
+use rand::Rng;
+
+use crate::aligned::Item;
+use crate::metric::Metric;
+
+/// Index of the centroid closest to the item.
+pub fn nearest(metric: Metric, centroids: &[Item], item: &Item) -> usize {
+    let mut best = 0;
+    let mut best_distance = f32::INFINITY;
+    for (index, centroid) in centroids.iter().enumerate() {
+        let distance = metric.distance(item, centroid);
+        if distance < best_distance {
+            best = index;
+            best_distance = distance;
+        }
+    }
+    best
+}
+
+/// Lloyd's k-means seeded with distinct random samples. A centroid left without members is
+/// reseeded with a random sample.
+pub fn kmeans(metric: Metric, samples: &[Item], k: usize, iterations: usize) -> Vec<Item> {
+    let mut rng = rand::thread_rng();
+    let dim = samples[0].dim();
+    let mut centroids: Vec<Item> = sample(&mut rng, samples.len(), k)
+        .iter()
+        .map(|index| samples[index].clone())
+        .collect();
+    let mut assignment = vec![usize::MAX; samples.len()];
+    for _ in 0..iterations {
+        let mut changed = false;
+        for (sample, assigned) in samples.iter().zip(assignment.iter_mut()) {
+            let list = nearest(metric, &centroids, sample);
+            if *assigned != list {
+                *assigned = list;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+        let mut sums = vec![vec![0f64; dim]; k];
+        let mut counts = vec![0usize; k];
+        for (sample, assigned) in samples.iter().zip(assignment.iter()) {
+            counts[*assigned] += 1;
+            for (sum, value) in sums[*assigned].iter_mut().zip(sample.values()) {
+                *sum += *value as f64;
+            }
+        }
+        for (list, centroid) in centroids.iter_mut().enumerate() {
+            *centroid = if counts[list] == 0 {
+                samples[rng.gen_range(0, samples.len())].clone()
+            } else {
+                let mean: Vec<f32> = sums[list]
+                    .iter()
+                    .map(|sum| (*sum / counts[list] as f64) as f32)
+                    .collect();
+                Item::from_slice(&mean)
+            };
+        }
+    }
+    centroids
+}
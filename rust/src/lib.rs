@@ -1,27 +1,45 @@
-#![feature(test)]
-#![feature(type_name_of_val)]
+// The JNI exports are unsafe because they dereference handles and arrays passed by the JVM,
+// their contract is the Java declaration in `VScoreNative`.
+#![allow(clippy::missing_safety_doc)]
 
 #[macro_use]
 extern crate lazy_static;
-extern crate packed_simd;
-extern crate test;
-
-use std::collections::HashMap;
-use std::hash::BuildHasherDefault;
-use std::ops::{Deref, DerefMut};
-use std::sync::{Arc, RwLock};
-
-use hashers::fnv::FNV1aHasher32;
-use hashers::fx_hash::FxHasher32;
-use jni::objects::{JClass, JObject, ReleaseMode};
-use jni::sys::{jbyte, jbyteArray, jfloat, jfloatArray, jint, jlong, jsize};
-use jni::JNIEnv;
-use packed_simd::{f32x16, f32x4, f32x8};
-use rand::Rng;
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use jni::objects::{GlobalRef, JByteBuffer, JClass, JObject, JString, ReleaseMode};
+use jni::sys::{jboolean, jbyte, jbyteArray, jfloat, jfloatArray, jint, jintArray, jlong, jstring};
+use jni::{JNIEnv, JavaVM};
+
+use crate::error::{critical_call, jni_call, Error};
+use crate::handle::HANDLES;
 
 mod aligned;
+mod binary;
+mod cache;
+mod error;
+mod half;
+mod handle;
+mod hnsw;
+mod int8;
+mod ivf;
+mod kmeans;
+mod metric;
+mod pool;
+mod pq;
+mod record;
+mod simd;
+mod snapshot;
+mod store;
+mod topk;
 mod unaligned;
+mod warm;
 
+#[cfg(test)]
+#[macro_use]
+mod bench;
 #[cfg(test)]
 mod tests;
 
@@ -37,12 +55,15 @@ pub extern "system" fn Java_com_github_eliak_VScoreNative_cosineSimilarity(
     one: jfloatArray,
     another: jfloatArray,
 ) -> f32 {
-    let item1 = aligned::Item::from_jni_float_array(&_env, one);
-    let item2 = aligned::Item::from_jni_float_array(&_env, another);
-    let similarity = item1.cosine_similarity(&item2);
-    drop(item1);
-    drop(item2);
-    return similarity;
+    jni_call(&_env, 0f32, || {
+        let dim = _env.get_array_length(one)? as usize;
+        let item1 = aligned::Item::from_jni_float_array(&_env, one, dim)?;
+        let item2 = aligned::Item::from_jni_float_array(&_env, another, dim)?;
+        let similarity = item1.cosine_similarity(&item2);
+        drop(item1);
+        drop(item2);
+        Ok(similarity)
+    })
 }
 
 /*
@@ -57,11 +78,11 @@ pub extern "system" fn Java_com_github_eliak_VScoreNative_cosineSimilarity2(
     one: jfloatArray,
     two: jfloatArray,
 ) -> f32 {
-    let one_len = _env.get_array_length(one).unwrap();
-    let two_len = _env.get_array_length(two).unwrap();
-    return Java_com_github_eliak_VScoreNative_cosineSimilarityCritical(
-        _env, _class, one_len, one, two_len, two,
-    );
+    jni_call(&_env, 0f32, || {
+        let one_len = _env.get_array_length(one)?;
+        let two_len = _env.get_array_length(two)?;
+        cosine_similarity_critical(&_env, one_len, one, two_len, two)
+    })
 }
 
 /*
@@ -78,14 +99,17 @@ pub extern "system" fn JavaCritical_com_github_eliak_VScoreNative_cosineSimilari
     two_ptr: &jfloat,
 ) -> f32 {
     println!("JavaCritical_");
-    let one_slice =
-        unsafe { std::slice::from_raw_parts(one_ptr as *const _ as *const f32, one_len as usize) };
-    let two_slice =
-        unsafe { std::slice::from_raw_parts(two_ptr as *const _ as *const f32, two_len as usize) };
-    let similarity = unaligned::cosine_similarity(one_slice, two_slice);
-    std::mem::forget(one_slice);
-    std::mem::forget(two_slice);
-    return similarity;
+    critical_call(|| {
+        if one_len != two_len {
+            return None;
+        }
+        let one_slice =
+            unsafe { std::slice::from_raw_parts(one_ptr as *const f32, one_len as usize) };
+        let two_slice =
+            unsafe { std::slice::from_raw_parts(two_ptr as *const f32, two_len as usize) };
+        let similarity = unaligned::cosine_similarity(one_slice, two_slice);
+        Some(similarity)
+    })
 }
 
 /*
@@ -102,28 +126,52 @@ pub extern "system" fn Java_com_github_eliak_VScoreNative_cosineSimilarityCritic
     two_len: jint,
     two: jfloatArray,
 ) -> f32 {
-    assert_eq!(one_len, two_len);
+    jni_call(&_env, 0f32, || {
+        cosine_similarity_critical(&_env, one_len, one, two_len, two)
+    })
+}
+
+fn cosine_similarity_critical(
+    _env: &JNIEnv,
+    one_len: jint,
+    one: jfloatArray,
+    two_len: jint,
+    two: jfloatArray,
+) -> error::Result<f32> {
+    if one_len != two_len {
+        return Err(Error::IllegalArgument(format!(
+            "vector lengths differ: {:?} and {:?}",
+            one_len, two_len
+        )));
+    }
+    check_array_length(_env, one, one_len)?;
+    check_array_length(_env, two, two_len)?;
 
     let len = one_len as usize;
 
-    let one_auto = _env
-        .get_auto_primitive_array_critical(one, ReleaseMode::NoCopyBack)
-        .unwrap();
+    let one_auto = _env.get_auto_primitive_array_critical(one, ReleaseMode::NoCopyBack)?;
     let one_ptr = one_auto.as_ptr() as *mut f32;
     let one_slice = unsafe { std::slice::from_raw_parts(one_ptr, len) };
 
-    let two_auto = _env
-        .get_auto_primitive_array_critical(two, ReleaseMode::NoCopyBack)
-        .unwrap();
+    let two_auto = _env.get_auto_primitive_array_critical(two, ReleaseMode::NoCopyBack)?;
     let two_ptr = two_auto.as_ptr() as *mut f32;
     let two_slice = unsafe { std::slice::from_raw_parts(two_ptr, len) };
 
     let similarity = unaligned::cosine_similarity(one_slice, two_slice);
 
-    std::mem::forget(one_slice);
-    std::mem::forget(two_slice);
+    Ok(similarity)
+}
 
-    return similarity;
+/// Guards slices built from a length passed by the caller against reading past the array.
+fn check_array_length(env: &JNIEnv, array: jfloatArray, len: jint) -> error::Result<()> {
+    let actual = env.get_array_length(array)?;
+    if len < 0 || len > actual {
+        return Err(Error::IllegalArgument(format!(
+            "length {:?} is out of array bounds {:?}",
+            len, actual
+        )));
+    }
+    Ok(())
 }
 
 /*
@@ -140,8 +188,8 @@ pub extern "system" fn Java_com_github_eliak_VScoreNative_cosineSimilarityCritic
 //     println!("one_ptr: {:?}", *one_ptr);
 //     println!("two_len {:?}", two_len);
 //     println!("two_ptr {:?}", two_ptr);
-//     // let one_slice = unsafe { std::slice::from_raw_parts(one_ptr as *const _ as *const f32, one_len as usize) };
-//     // let two_slice = unsafe { std::slice::from_raw_parts(two_ptr as *const _ as *const f32, two_len as usize) };
+//     // let one_slice = unsafe { std::slice::from_raw_parts(one_ptr as *const f32, one_len as usize) };
+//     // let two_slice = unsafe { std::slice::from_raw_parts(two_ptr as *const f32, two_len as usize) };
 //     // println!("one: {:?}\ntwo {:?}", one_slice, two_slice);
 //     // let similarity = cosine_similarity(one_slice, two_slice);
 //     // println!("similarity {:?}", similarity);
@@ -154,17 +202,44 @@ pub extern "system" fn Java_com_github_eliak_VScoreNative_cosineSimilarityCritic
 /*
  * Class:     com_github_eliak_VScoreNative
  * Method:    createScorerFactory
- * Signature: ()J
+ * Signature: (IJIIII)J
  */
 #[no_mangle]
 pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_createScorerFactory(
     _env: JNIEnv,
     _class: JClass,
+    dim: jint,
+    cache_capacity_bytes: jlong,
+    storage: jint,
+    encoding: jint,
+    unit_norm: jint,
+    scan_threads: jint,
 ) -> i64 {
-    let factory = aligned::ScorerFactory::new();
-    let result = Box::into_raw(Box::new(factory)) as jlong;
-    // println!("createScorerFactory: {}", result);
-    result
+    jni_call(&_env, 0, || {
+        let storage = aligned::Storage::from_ordinal(storage)
+            .ok_or_else(|| Error::IllegalArgument(format!("unknown storage {:?}", storage)))?;
+        let encoding = half::Encoding::from_ordinal(encoding)
+            .ok_or_else(|| Error::IllegalArgument(format!("unknown encoding {:?}", encoding)))?;
+        let unit_norm = aligned::UnitNorm::from_ordinal(unit_norm)
+            .ok_or_else(|| Error::IllegalArgument(format!("unknown unit norm {:?}", unit_norm)))?;
+        if scan_threads < 0 {
+            return Err(Error::IllegalArgument(format!(
+                "scan threads must not be negative, got {:?}",
+                scan_threads
+            )));
+        }
+        let factory = aligned::ScorerFactory::with_format(
+            positive(dim, "dimension")? as usize,
+            positive(cache_capacity_bytes, "cache capacity")? as usize,
+            storage,
+            encoding,
+            unit_norm,
+            scan_threads as usize,
+        );
+        let result = HANDLES.insert(factory, handle::NO_HANDLE);
+        // println!("createScorerFactory: {}", result);
+        Ok(result)
+    })
 }
 
 /*
@@ -177,16 +252,302 @@ pub extern "system" fn Java_com_github_eliak_VScoreNative_destroyScorerFactory(
     _env: JNIEnv,
     _class: JClass,
     factory_ptr: jlong,
+) -> jlong {
+    jni_call(&_env, 0, || {
+        // println!("destroyScorerFactory: {}", factory_ptr);
+        destroy_scorer_factory(factory_ptr)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    invalidate
+ * Signature: (JJI)Z
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_invalidate(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+    segment_key: jlong,
+    doc_id: jint,
+) -> jboolean {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        Ok(factory.invalidate(segment_key, doc_id as aligned::DocId) as jboolean)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    dropSegment
+ * Signature: (JJ)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_dropSegment(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+    segment_key: jlong,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        Ok(factory.drop_segment(segment_key) as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    clear
+ * Signature: (J)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_clear(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+) {
+    jni_call(&_env, (), || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        factory.clear();
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    saveSnapshot
+ * Signature: (JLjava/lang/String;)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_saveSnapshot(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+    path: jstring,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        Ok(factory.save_snapshot(&path_of(&_env, path)?)? as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    loadSnapshot
+ * Signature: (JLjava/lang/String;)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_loadSnapshot(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+    path: jstring,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        Ok(factory.load_snapshot(&path_of(&_env, path)?)? as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    warmSegment
+ * Signature: (JJLjava/nio/ByteBuffer;[ILcom/github/eliak/VScoreNative/WarmCallback;)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_warmSegment(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+    segment_key: jlong,
+    packed_vectors: JObject,
+    doc_ids: jintArray,
+    callback: JObject,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        let (bytes, order) = buffer_of(&_env, packed_vectors)?;
+        let doc_id_buf = if doc_ids.is_null() {
+            None
+        } else {
+            let mut buf = vec![0 as jint; _env.get_array_length(doc_ids)? as usize];
+            _env.get_int_array_region(doc_ids, 0, &mut buf)?;
+            Some(buf)
+        };
+        let warmer = factory.warmer();
+        if callback.is_null() {
+            let count = warmer.warm(segment_key, bytes, order, doc_id_buf.as_deref())?;
+            return Ok(count as jint);
+        }
+        let count = warmer.count(bytes.len(), doc_id_buf.as_deref())?;
+        // the buffer may be released once this call returns
+        let bytes = bytes.to_vec();
+        let vm = _env.get_java_vm()?;
+        let callback = _env.new_global_ref(callback)?;
+        thread::Builder::new()
+            .name("vscore-warm".to_string())
+            .spawn(move || {
+                let result = warmer.warm(segment_key, &bytes, order, doc_id_buf.as_deref());
+                complete_warm(&vm, &callback, result);
+            })?;
+        Ok(count as jint)
+    })
+}
+
+/// Reports the outcome of a background warm-up to its `WarmCallback` from the warming thread.
+fn complete_warm(vm: &JavaVM, callback: &GlobalRef, result: error::Result<usize>) {
+    let env = match vm.attach_current_thread() {
+        Ok(env) => env,
+        Err(_) => return,
+    };
+    let completed = || -> error::Result<()> {
+        let (count, failure) = match result {
+            Ok(count) => (count as jint, JObject::null()),
+            Err(error) => (0, error.to_throwable(&env)?),
+        };
+        env.call_method(
+            callback.as_obj(),
+            "completed",
+            "(ILjava/lang/Throwable;)V",
+            &[count.into(), failure.into()],
+        )?;
+        Ok(())
+    };
+    if completed().is_err() && env.exception_check().unwrap_or(false) {
+        // no Java frame on this thread to propagate the exception to
+        let _ = env.exception_describe();
+        let _ = env.exception_clear();
+    }
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    openVectorStore
+ * Signature: (JLjava/lang/String;)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_openVectorStore(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+    path: jstring,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        let path = path_of(&_env, path)?;
+        Ok(factory.open_store(&path)? as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    closeVectorStore
+ * Signature: (J)Z
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_closeVectorStore(
+    _env: JNIEnv,
+    _class: JClass,
+    factory_ptr: jlong,
+) -> jboolean {
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        Ok(factory.close_store() as jboolean)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createVectorStoreWriter
+ * Signature: (Ljava/lang/String;II)J
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_createVectorStoreWriter(
+    _env: JNIEnv,
+    _class: JClass,
+    path: jstring,
+    dim: jint,
+    element: jint,
+) -> jlong {
+    jni_call(&_env, 0, || {
+        let element = half::Encoding::from_ordinal(element)
+            .ok_or_else(|| Error::IllegalArgument(format!("unknown encoding {:?}", element)))?;
+        let writer = store::StoreWriter::create(
+            &path_of(&_env, path)?,
+            positive(dim, "dimension")? as usize,
+            element,
+        )?;
+        Ok(HANDLES.insert(
+            VectorStoreWriter(Mutex::new(Some(writer))),
+            handle::NO_HANDLE,
+        ))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    addToVectorStore
+ * Signature: (JJI[F)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_addToVectorStore(
+    _env: JNIEnv,
+    _class: JClass,
+    writer_ptr: jlong,
+    segment_key: jlong,
+    doc_id: jint,
+    vector: jfloatArray,
+) {
+    jni_call(&_env, (), || {
+        let writer = HANDLES.get::<VectorStoreWriter>(writer_ptr)?;
+        let mut writer = writer.0.lock().unwrap();
+        let writer = writer.as_mut().ok_or_else(finished_store_writer)?;
+        let item = aligned::Item::from_jni_float_array(&_env, vector, writer.dim())?;
+        writer.add(segment_key, doc_id as aligned::DocId, &item)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    finishVectorStore
+ * Signature: (J)I
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_finishVectorStore(
+    _env: JNIEnv,
+    _class: JClass,
+    writer_ptr: jlong,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let writer = HANDLES.remove::<VectorStoreWriter>(writer_ptr)?;
+        let writer = writer.0.lock().unwrap().take();
+        Ok(writer.ok_or_else(finished_store_writer)?.finish()? as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    abortVectorStore
+ * Signature: (J)V
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_abortVectorStore(
+    _env: JNIEnv,
+    _class: JClass,
+    writer_ptr: jlong,
 ) {
-    // println!("destroyScorerFactory: {}", factory_ptr);
-    let _boxed_factory = unsafe { Box::from_raw(factory_ptr as *mut aligned::ScorerFactory) };
-    drop(_boxed_factory);
+    jni_call(&_env, (), || {
+        let writer = HANDLES.remove::<VectorStoreWriter>(writer_ptr)?;
+        drop(writer.0.lock().unwrap().take());
+        Ok(())
+    })
 }
 
 /*
  * Class:     com_github_eliak_VScoreNative
  * Method:    createScorer
- * Signature: (J[F)J
+ * Signature: (J[FII)J
  */
 #[no_mangle]
 pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_createScorer(
@@ -194,12 +555,20 @@ pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_createScorer(
     _class: JClass,
     factory_ptr: jlong,
     query_vector: jfloatArray,
+    metric: jint,
+    normalization: jint,
 ) -> jlong {
-    let factory = &*(factory_ptr as *const aligned::ScorerFactory);
-    let scorer = factory.scorer(aligned::Item::from_jni_float_array(&_env, query_vector));
-    let result = Box::into_raw(Box::new(scorer)) as jlong;
-    // println!("createScorer: {} from factory {}, cache.len={}", result, factory_ptr, factory.cache.clone().read().unwrap().len());
-    result
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        let scorer = factory.scorer(
+            aligned::Item::from_jni_float_array(&_env, query_vector, factory.dim())?,
+            metric_of(metric)?,
+            normalization_of(normalization)?,
+        )?;
+        let result = HANDLES.insert(scorer, factory_ptr);
+        // println!("createScorer: {} from factory {}, cache.len={}", result, factory_ptr, factory.cache.len());
+        Ok(result)
+    })
 }
 
 /*
@@ -213,26 +582,634 @@ pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_destroyScorer(
     _class: JClass,
     scorer_ptr: jlong,
 ) {
-    // println!("destroyScorer: {}", scorer_ptr);
-    let _boxed_scorer = Box::from_raw(scorer_ptr as *mut aligned::Scorer);
-    drop(_boxed_scorer);
+    jni_call(&_env, (), || {
+        // println!("destroyScorer: {}", scorer_ptr);
+        HANDLES.remove::<aligned::Scorer>(scorer_ptr)?;
+        Ok(())
+    })
 }
 
 /*
  * Class:     com_github_eliak_VScoreNative
  * Method:    score
- * Signature: (JILcom/github/eliak/VScoreNative/ScorerCallback;)F
+ * Signature: (JJILcom/github/eliak/VScoreNative/ScorerCallback;)F
  */
 #[no_mangle]
 pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_score(
     _env: JNIEnv,
     _class: JClass,
     scorer_ptr: jlong,
+    segment_key: jlong,
     doc_id: jint,
     callback: JObject,
 ) -> f32 {
-    let scorer = &*(scorer_ptr as *const aligned::Scorer);
-    scorer.score(&_env, doc_id as aligned::DocId, callback)
+    jni_call(&_env, 0f32, || {
+        let scorer = HANDLES.get::<aligned::Scorer>(scorer_ptr)?;
+        scorer.score(&_env, segment_key, doc_id as aligned::DocId, callback)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    scoreBytes
+ * Signature: (JJI[BIIZ)F
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_scoreBytes(
+    _env: JNIEnv,
+    _class: JClass,
+    scorer_ptr: jlong,
+    segment_key: jlong,
+    doc_id: jint,
+    bytes: jbyteArray,
+    offset: jint,
+    length: jint,
+    big_endian: jboolean,
+) -> f32 {
+    jni_call(&_env, 0f32, || {
+        let scorer = HANDLES.get::<aligned::Scorer>(scorer_ptr)?;
+        let length = positive(length, "length")? as usize;
+        scorer.score_bytes(
+            segment_key,
+            doc_id as aligned::DocId,
+            half::ByteOrder::from_big_endian(big_endian != 0),
+            || codes_of(&_env, bytes, offset, length),
+        )
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    scoreBatch
+ * Signature: (JJ[II[FLcom/github/eliak/VScoreNative/BatchCallback;)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_scoreBatch(
+    _env: JNIEnv,
+    _class: JClass,
+    scorer_ptr: jlong,
+    segment_key: jlong,
+    doc_ids: jintArray,
+    count: jint,
+    scores: jfloatArray,
+    callback: JObject,
+) {
+    jni_call(&_env, (), || {
+        let scorer = HANDLES.get::<aligned::Scorer>(scorer_ptr)?;
+        if count < 0
+            || count > _env.get_array_length(doc_ids)?
+            || count > _env.get_array_length(scores)?
+        {
+            return Err(Error::IllegalArgument(format!(
+                "count {:?} is out of array bounds",
+                count
+            )));
+        }
+        let mut doc_id_buf = vec![0 as jint; count as usize];
+        _env.get_int_array_region(doc_ids, 0, &mut doc_id_buf)?;
+        let mut score_buf = vec![0f32; count as usize];
+        scorer.score_batch(&_env, segment_key, &doc_id_buf, &mut score_buf, callback)?;
+        _env.set_float_array_region(scores, 0, &score_buf)?;
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    topK
+ * Signature: (JJI[I[I[FILcom/github/eliak/VScoreNative/BatchCallback;)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_topK(
+    _env: JNIEnv,
+    _class: JClass,
+    scorer_ptr: jlong,
+    segment_key: jlong,
+    k: jint,
+    candidates: jintArray,
+    doc_ids: jintArray,
+    scores: jfloatArray,
+    rerank: jint,
+    callback: JObject,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let scorer = HANDLES.get::<aligned::Scorer>(scorer_ptr)?;
+        let k = check_result_arrays(&_env, k, doc_ids, scores)?;
+        let candidate_buf = if candidates.is_null() {
+            None
+        } else {
+            let mut buf = vec![0 as jint; _env.get_array_length(candidates)? as usize];
+            _env.get_int_array_region(candidates, 0, &mut buf)?;
+            Some(
+                buf.into_iter()
+                    .map(aligned::DocId::from)
+                    .collect::<Vec<_>>(),
+            )
+        };
+        if rerank < 0 {
+            return Err(Error::IllegalArgument(format!(
+                "rerank must not be negative, got {:?}",
+                rerank
+            )));
+        }
+        let hits = scorer.top_k_reranked(
+            &_env,
+            segment_key,
+            k,
+            candidate_buf.as_deref(),
+            rerank as usize,
+            callback,
+        )?;
+        write_hits(&_env, &hits, doc_ids, scores)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createIndex
+ * Signature: (IIIIII)J
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_createIndex(
+    _env: JNIEnv,
+    _class: JClass,
+    dim: jint,
+    metric: jint,
+    normalization: jint,
+    m: jint,
+    ef_construction: jint,
+    ef_search: jint,
+) -> jlong {
+    jni_call(&_env, 0, || {
+        let index = hnsw::HnswIndex::new(
+            positive(dim, "dimension")? as usize,
+            metric_of(metric)?,
+            normalization_of(normalization)?,
+            hnsw::HnswParams {
+                m: positive(m, "M")? as usize,
+                ef_construction: positive(ef_construction, "efConstruction")? as usize,
+                ef_search: positive(ef_search, "efSearch")? as usize,
+            },
+        )?;
+        Ok(HANDLES.insert(index, handle::NO_HANDLE))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyIndex
+ * Signature: (J)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_destroyIndex(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+) {
+    jni_call(&_env, (), || {
+        HANDLES.remove::<hnsw::HnswIndex>(index_ptr)?;
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    addVector
+ * Signature: (JI[F)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_addVector(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+    doc_id: jint,
+    vector: jfloatArray,
+) {
+    jni_call(&_env, (), || {
+        let index = HANDLES.get::<hnsw::HnswIndex>(index_ptr)?;
+        let item = aligned::Item::from_jni_float_array(&_env, vector, index.dim())?;
+        index.add(doc_id as aligned::DocId, Arc::new(item))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    indexSize
+ * Signature: (J)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_indexSize(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let index = HANDLES.get::<hnsw::HnswIndex>(index_ptr)?;
+        Ok(index.len() as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    search
+ * Signature: (J[FII[I[F)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_search(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+    query_vector: jfloatArray,
+    k: jint,
+    ef: jint,
+    doc_ids: jintArray,
+    scores: jfloatArray,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let index = HANDLES.get::<hnsw::HnswIndex>(index_ptr)?;
+        let k = check_result_arrays(&_env, k, doc_ids, scores)?;
+        if ef < 0 {
+            return Err(Error::IllegalArgument(format!(
+                "ef must not be negative, got {:?}",
+                ef
+            )));
+        }
+        let query = aligned::Item::from_jni_float_array(&_env, query_vector, index.dim())?;
+        let hits = index.search(&query, k, ef as usize)?;
+        write_hits(&_env, &hits, doc_ids, scores)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createIvfIndex
+ * Signature: (IIIII)J
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_createIvfIndex(
+    _env: JNIEnv,
+    _class: JClass,
+    dim: jint,
+    metric: jint,
+    normalization: jint,
+    nlist: jint,
+    nprobe: jint,
+) -> jlong {
+    jni_call(&_env, 0, || {
+        let index = ivf::IvfIndex::new(
+            positive(dim, "dimension")? as usize,
+            metric_of(metric)?,
+            normalization_of(normalization)?,
+            positive(nlist, "nlist")? as usize,
+            positive(nprobe, "nprobe")? as usize,
+        )?;
+        Ok(HANDLES.insert(index, handle::NO_HANDLE))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyIvfIndex
+ * Signature: (J)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_destroyIvfIndex(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+) {
+    jni_call(&_env, (), || {
+        HANDLES.remove::<ivf::IvfIndex>(index_ptr)?;
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    trainIvf
+ * Signature: (J[FI)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_trainIvf(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+    samples: jfloatArray,
+    iterations: jint,
+) {
+    jni_call(&_env, (), || {
+        let index = HANDLES.get::<ivf::IvfIndex>(index_ptr)?;
+        let items = samples_of(&_env, samples, index.dim())?;
+        index.train(&items, positive(iterations, "iterations")? as usize)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    addIvfVector
+ * Signature: (JI[F)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_addIvfVector(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+    doc_id: jint,
+    vector: jfloatArray,
+) {
+    jni_call(&_env, (), || {
+        let index = HANDLES.get::<ivf::IvfIndex>(index_ptr)?;
+        let item = aligned::Item::from_jni_float_array(&_env, vector, index.dim())?;
+        index.add(doc_id as aligned::DocId, Arc::new(item))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    ivfSize
+ * Signature: (J)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_ivfSize(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let index = HANDLES.get::<ivf::IvfIndex>(index_ptr)?;
+        Ok(index.len() as jint)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    setNprobe
+ * Signature: (JI)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_setNprobe(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+    nprobe: jint,
+) {
+    jni_call(&_env, (), || {
+        let index = HANDLES.get::<ivf::IvfIndex>(index_ptr)?;
+        index.set_nprobe(positive(nprobe, "nprobe")? as usize)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    searchIvf
+ * Signature: (J[FII[I[F)I
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_searchIvf(
+    _env: JNIEnv,
+    _class: JClass,
+    index_ptr: jlong,
+    query_vector: jfloatArray,
+    k: jint,
+    nprobe: jint,
+    doc_ids: jintArray,
+    scores: jfloatArray,
+) -> jint {
+    jni_call(&_env, 0, || {
+        let index = HANDLES.get::<ivf::IvfIndex>(index_ptr)?;
+        let k = check_result_arrays(&_env, k, doc_ids, scores)?;
+        if nprobe < 0 {
+            return Err(Error::IllegalArgument(format!(
+                "nprobe must not be negative, got {:?}",
+                nprobe
+            )));
+        }
+        let query = aligned::Item::from_jni_float_array(&_env, query_vector, index.dim())?;
+        let hits = index.search(&query, k, nprobe as usize)?;
+        write_hits(&_env, &hits, doc_ids, scores)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createProductQuantizer
+ * Signature: (IIII)J
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_createProductQuantizer(
+    _env: JNIEnv,
+    _class: JClass,
+    dim: jint,
+    m: jint,
+    metric: jint,
+    normalization: jint,
+) -> jlong {
+    jni_call(&_env, 0, || {
+        let quantizer = pq::ProductQuantizer::new(
+            positive(dim, "dimension")? as usize,
+            positive(m, "M")? as usize,
+            metric_of(metric)?,
+            normalization_of(normalization)?,
+        )?;
+        Ok(HANDLES.insert(quantizer, handle::NO_HANDLE))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyProductQuantizer
+ * Signature: (J)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_destroyProductQuantizer(
+    _env: JNIEnv,
+    _class: JClass,
+    quantizer_ptr: jlong,
+) {
+    jni_call(&_env, (), || {
+        HANDLES.remove::<pq::ProductQuantizer>(quantizer_ptr)?;
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    trainProductQuantizer
+ * Signature: (J[FI)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_trainProductQuantizer(
+    _env: JNIEnv,
+    _class: JClass,
+    quantizer_ptr: jlong,
+    samples: jfloatArray,
+    iterations: jint,
+) {
+    jni_call(&_env, (), || {
+        let quantizer = HANDLES.get::<pq::ProductQuantizer>(quantizer_ptr)?;
+        let items = samples_of(&_env, samples, quantizer.dim())?;
+        quantizer.train(&items, positive(iterations, "iterations")? as usize)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    pqEncode
+ * Signature: (J[F)[B
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_pqEncode(
+    _env: JNIEnv,
+    _class: JClass,
+    quantizer_ptr: jlong,
+    vector: jfloatArray,
+) -> jbyteArray {
+    jni_call(&_env, std::ptr::null_mut(), || {
+        let quantizer = HANDLES.get::<pq::ProductQuantizer>(quantizer_ptr)?;
+        let item = aligned::Item::from_jni_float_array(&_env, vector, quantizer.dim())?;
+        let mut code = vec![0u8; quantizer.code_size()];
+        quantizer.encode(item.values(), &mut code)?;
+        Ok(_env.byte_array_from_slice(&code)?)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    createPqQuery
+ * Signature: (J[F)J
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_createPqQuery(
+    _env: JNIEnv,
+    _class: JClass,
+    quantizer_ptr: jlong,
+    query_vector: jfloatArray,
+) -> jlong {
+    jni_call(&_env, 0, || {
+        let quantizer = HANDLES.get::<pq::ProductQuantizer>(quantizer_ptr)?;
+        let query = aligned::Item::from_jni_float_array(&_env, query_vector, quantizer.dim())?;
+        Ok(HANDLES.insert(quantizer.query(&query)?, quantizer_ptr))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    destroyPqQuery
+ * Signature: (J)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_destroyPqQuery(
+    _env: JNIEnv,
+    _class: JClass,
+    query_ptr: jlong,
+) {
+    jni_call(&_env, (), || {
+        HANDLES.remove::<pq::PqQuery>(query_ptr)?;
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    pqScore
+ * Signature: (J[BI)F
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_pqScore(
+    _env: JNIEnv,
+    _class: JClass,
+    query_ptr: jlong,
+    codes: jbyteArray,
+    offset: jint,
+) -> jfloat {
+    jni_call(&_env, 0f32, || {
+        let query = HANDLES.get::<pq::PqQuery>(query_ptr)?;
+        let code = codes_of(&_env, codes, offset, query.code_size())?;
+        Ok(query.score(&code))
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    pqScoreBatch
+ * Signature: (J[BI[F)V
+ */
+#[no_mangle]
+pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_pqScoreBatch(
+    _env: JNIEnv,
+    _class: JClass,
+    query_ptr: jlong,
+    codes: jbyteArray,
+    count: jint,
+    scores: jfloatArray,
+) {
+    jni_call(&_env, (), || {
+        let query = HANDLES.get::<pq::PqQuery>(query_ptr)?;
+        if count < 0 || count > _env.get_array_length(scores)? {
+            return Err(Error::IllegalArgument(format!(
+                "count {:?} is out of array bounds",
+                count
+            )));
+        }
+        let code = codes_of(&_env, codes, 0, count as usize * query.code_size())?;
+        let score_buf: Vec<f32> = code
+            .chunks(query.code_size())
+            .map(|code| query.score(code))
+            .collect();
+        _env.set_float_array_region(scores, 0, &score_buf)?;
+        Ok(())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    encodeVector
+ * Signature: ([F)[B
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_encodeVector(
+    _env: JNIEnv,
+    _class: JClass,
+    vector: jfloatArray,
+) -> jbyteArray {
+    jni_call(&_env, std::ptr::null_mut(), || {
+        if vector.is_null() {
+            return Err(Error::IllegalArgument("vector is null".to_string()));
+        }
+        let dim = _env.get_array_length(vector)? as usize;
+        let item = aligned::Item::from_jni_float_array(&_env, vector, dim)?;
+        let bytes = record::encode(&item, half::Encoding::F32)?;
+        Ok(_env.byte_array_from_slice(&bytes)?)
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    cpuFeatures
+ * Signature: ()Ljava/lang/String;
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_cpuFeatures(
+    _env: JNIEnv,
+    _class: JClass,
+) -> jstring {
+    jni_call(&_env, std::ptr::null_mut(), || {
+        Ok(_env.new_string(simd::level().name())?.into_inner())
+    })
+}
+
+/*
+ * Class:     com_github_eliak_VScoreNative
+ * Method:    setDebugHandles
+ * Signature: (Z)V
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_github_eliak_VScoreNative_setDebugHandles(
+    _env: JNIEnv,
+    _class: JClass,
+    enabled: jboolean,
+) {
+    HANDLES.set_debug(enabled != 0);
 }
 
 /*
@@ -246,13 +1223,170 @@ pub unsafe extern "system" fn Java_com_github_eliak_VScoreNative_identity(
     _class: JClass,
     num: jfloat,
 ) -> f32 {
-    num.clone()
+    num
+}
+
+/// Unregisters a factory and, in debug mode, reports on stderr the scorers and other handles
+/// created from it that are still open. They stay usable, each holds on to the cache it needs.
+/// Returns the number of handles reported.
+fn destroy_scorer_factory(factory_ptr: jlong) -> error::Result<jlong> {
+    HANDLES.remove::<aligned::ScorerFactory>(factory_ptr)?;
+    let leaked = HANDLES.leaked(factory_ptr);
+    for (handle, type_name) in leaked.iter() {
+        eprintln!(
+            "{} handle {:#x} is still open after its ScorerFactory {:#x} was destroyed",
+            type_name, handle, factory_ptr
+        );
+    }
+    Ok(leaked.len() as jlong)
+}
+
+/// Registered store writer, named after the Java methods driving it. Emptied when the writer is
+/// finished or aborted.
+struct VectorStoreWriter(Mutex<Option<store::StoreWriter>>);
+
+fn finished_store_writer() -> Error {
+    Error::IllegalState("vector store writer is finished".to_string())
+}
+
+/// Splits training vectors passed concatenated in one array into items.
+fn samples_of(env: &JNIEnv, samples: jfloatArray, dim: usize) -> error::Result<Vec<aligned::Item>> {
+    if samples.is_null() {
+        return Err(Error::IllegalArgument("samples are null".to_string()));
+    }
+    let len = env.get_array_length(samples)? as usize;
+    if !len.is_multiple_of(dim) {
+        return Err(Error::IllegalArgument(format!(
+            "samples length {:?} is not a multiple of dimension {:?}",
+            len, dim
+        )));
+    }
+    let mut values = vec![0f32; len];
+    env.get_float_array_region(samples, 0, &mut values)?;
+    Ok(values.chunks(dim).map(aligned::Item::from_slice).collect())
+}
+
+/// Copies `len` bytes of encoded vectors starting at `offset`.
+fn codes_of(env: &JNIEnv, codes: jbyteArray, offset: jint, len: usize) -> error::Result<Vec<u8>> {
+    if codes.is_null() {
+        return Err(Error::IllegalArgument("codes are null".to_string()));
+    }
+    let available = env.get_array_length(codes)? as i64 - offset as i64;
+    if offset < 0 || available < len as i64 {
+        return Err(Error::IllegalArgument(format!(
+            "{:?} bytes at offset {:?} are out of array bounds",
+            len, offset
+        )));
+    }
+    let mut buf = vec![0 as jbyte; len];
+    env.get_byte_array_region(codes, offset, &mut buf)?;
+    Ok(buf.into_iter().map(|byte| byte as u8).collect())
+}
+
+/// Bytes between the position and the limit of a direct `ByteBuffer`, read in the byte order
+/// of the buffer.
+fn buffer_of<'e>(env: &'e JNIEnv, buffer: JObject) -> error::Result<(&'e [u8], half::ByteOrder)> {
+    if buffer.is_null() {
+        return Err(Error::IllegalArgument("buffer is null".to_string()));
+    }
+    if !env.call_method(buffer, "isDirect", "()Z", &[])?.z()? {
+        return Err(Error::IllegalArgument(
+            "buffer must be a direct ByteBuffer".to_string(),
+        ));
+    }
+    let position = env.call_method(buffer, "position", "()I", &[])?.i()? as usize;
+    let limit = env.call_method(buffer, "limit", "()I", &[])?.i()? as usize;
+    let order = env
+        .call_method(buffer, "order", "()Ljava/nio/ByteOrder;", &[])?
+        .l()?;
+    let big_endian = env
+        .get_static_field("java/nio/ByteOrder", "BIG_ENDIAN", "Ljava/nio/ByteOrder;")?
+        .l()?;
+    let order = half::ByteOrder::from_big_endian(env.is_same_object(order, big_endian)?);
+    let bytes = env.get_direct_buffer_address(JByteBuffer::from(buffer))?;
+    Ok((&bytes[position..limit], order))
+}
+
+fn path_of(env: &JNIEnv, path: jstring) -> error::Result<PathBuf> {
+    if path.is_null() {
+        return Err(Error::IllegalArgument("path is null".to_string()));
+    }
+    let path: String = env.get_string(JString::from(path))?.into();
+    Ok(PathBuf::from(path))
+}
+
+fn metric_of(ordinal: jint) -> error::Result<metric::Metric> {
+    metric::Metric::from_ordinal(ordinal)
+        .ok_or_else(|| Error::IllegalArgument(format!("unknown metric {:?}", ordinal)))
+}
+
+fn normalization_of(ordinal: jint) -> error::Result<metric::Normalization> {
+    metric::Normalization::from_ordinal(ordinal)
+        .ok_or_else(|| Error::IllegalArgument(format!("unknown normalization {:?}", ordinal)))
+}
+
+/// Copies `hits` into the parallel result arrays of a search and returns how many were written.
+fn write_hits(
+    env: &JNIEnv,
+    hits: &[topk::ScoredDoc],
+    doc_ids: jintArray,
+    scores: jfloatArray,
+) -> error::Result<jint> {
+    let doc_id_buf: Vec<jint> = hits.iter().map(|hit| hit.doc as jint).collect();
+    let score_buf: Vec<f32> = hits.iter().map(|hit| hit.score).collect();
+    env.set_int_array_region(doc_ids, 0, &doc_id_buf)?;
+    env.set_float_array_region(scores, 0, &score_buf)?;
+    Ok(hits.len() as jint)
+}
+
+/// Checks that `k` hits fit into the parallel result arrays of a search.
+fn check_result_arrays(
+    env: &JNIEnv,
+    k: jint,
+    doc_ids: jintArray,
+    scores: jfloatArray,
+) -> error::Result<usize> {
+    let k = positive(k, "k")?;
+    if k > env.get_array_length(doc_ids)? || k > env.get_array_length(scores)? {
+        return Err(Error::IllegalArgument(format!(
+            "k {:?} is out of array bounds",
+            k
+        )));
+    }
+    Ok(k as usize)
+}
+
+fn positive<T: Into<i64> + Copy>(value: T, name: &str) -> error::Result<T> {
+    if value.into() <= 0 {
+        return Err(Error::IllegalArgument(format!(
+            "{} must be positive, got {:?}",
+            name,
+            value.into()
+        )));
+    }
+    Ok(value)
+}
+
+fn item_pair(
+    item_ptr_1: jlong,
+    item_ptr_2: jlong,
+) -> error::Result<(Arc<aligned::Item>, Arc<aligned::Item>)> {
+    let item_1 = HANDLES.get::<aligned::Item>(item_ptr_1)?;
+    let item_2 = HANDLES.get::<aligned::Item>(item_ptr_2)?;
+    if item_1.dim() != item_2.dim() {
+        return Err(Error::IllegalArgument(format!(
+            "item dimensions differ: {:?} and {:?}",
+            item_1.dim(),
+            item_2.dim()
+        )));
+    }
+    Ok((item_1, item_2))
 }
 
 /****************************************************************************************************
 package com.iqmen.iqfacescore;
 public class NativeScorerFactory {
-    public static native long createScorerFactory();
+    public static native long createScorerFactory(int dim, long cacheCapacityBytes);
     public static native long destroyScorerFactory(long factoryPtr);
     public static native long createScorer(long factoryPtr, float[] vector);
     public static native void destroyScorer(long scorerPtr);
@@ -264,22 +1398,31 @@ public class NativeScorerFactory {
     public static native float itemDotProductWithVector(long itemPtr, float[] vector);
     public static native float itemCosineSimilarity(long itemPtr1, long itemPtr2);
     public static native float dotProductVectorAndSerializedVector(float[] vector1, byte[] vector2);
+    public static native float dotProductVectorAndSerializedVectorWithOrder(float[] vector1, byte[] vector2,
+                                                                            boolean bigEndian);
 }
 ****************************************************************************************************/
 /*
  * Class:     com_iqmen_iqfacescore_NativeScorerFactory
  * Method:    createScorerFactory
- * Signature: ()J
+ * Signature: (IJ)J
  */
 #[no_mangle]
 pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_createScorerFactory(
     _env: JNIEnv,
     _class: JClass,
+    dim: jint,
+    cache_capacity_bytes: jlong,
 ) -> i64 {
-    let factory = aligned::ScorerFactory::new();
-    let result = Box::into_raw(Box::new(factory)) as jlong;
-    //println!("create scorer factory: {:?}", result);
-    result
+    jni_call(&_env, 0, || {
+        let factory = aligned::ScorerFactory::new(
+            positive(dim, "dimension")? as usize,
+            positive(cache_capacity_bytes, "cache capacity")? as usize,
+        );
+        let result = HANDLES.insert(factory, handle::NO_HANDLE);
+        //println!("create scorer factory: {:?}", result);
+        Ok(result)
+    })
 }
 
 /*
@@ -292,10 +1435,8 @@ pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_destroySco
     _env: JNIEnv,
     _class: JClass,
     factory_ptr: jlong,
-) {
-    println!("drop scorer factory: {:?}", factory_ptr);
-    let _boxed_factory = unsafe { Box::from_raw(factory_ptr as *mut aligned::ScorerFactory) };
-    drop(_boxed_factory);
+) -> jlong {
+    jni_call(&_env, 0, || destroy_scorer_factory(factory_ptr))
 }
 
 /*
@@ -310,11 +1451,17 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_cre
     factory_ptr: jlong,
     query_vector: jfloatArray,
 ) -> jlong {
-    let factory = &*(factory_ptr as *const aligned::ScorerFactory);
-    let scorer = factory.scorer(aligned::Item::from_jni_float_array(&_env, query_vector));
-    let result = Box::into_raw(Box::new(scorer)) as jlong;
-    //println!("create scorer {:?} by factory: {:?}", result, factory_ptr);
-    result
+    jni_call(&_env, 0, || {
+        let factory = HANDLES.get::<aligned::ScorerFactory>(factory_ptr)?;
+        let scorer = factory.scorer(
+            aligned::Item::from_jni_float_array(&_env, query_vector, factory.dim())?,
+            metric::Metric::Cosine,
+            metric::Normalization::None,
+        )?;
+        let result = HANDLES.insert(scorer, factory_ptr);
+        //println!("create scorer {:?} by factory: {:?}", result, factory_ptr);
+        Ok(result)
+    })
 }
 
 /*
@@ -328,9 +1475,11 @@ pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_destroySco
     _class: JClass,
     scorer_ptr: jlong,
 ) {
-    //println!("drop scorer: {:?}", scorer_ptr);
-    let _boxed_scorer = unsafe { Box::from_raw(scorer_ptr as *mut aligned::Scorer) };
-    drop(_boxed_scorer);
+    jni_call(&_env, (), || {
+        //println!("drop scorer: {:?}", scorer_ptr);
+        HANDLES.remove::<aligned::Scorer>(scorer_ptr)?;
+        Ok(())
+    })
 }
 
 /*
@@ -346,8 +1495,10 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_dot
     doc_id: jlong,
     callback: JObject,
 ) -> f32 {
-    let scorer = &*(scorer_ptr as *const aligned::Scorer);
-    scorer.dot_product(&_env, doc_id as aligned::DocId, callback)
+    jni_call(&_env, 0f32, || {
+        let scorer = HANDLES.get::<aligned::Scorer>(scorer_ptr)?;
+        scorer.dot_product(&_env, doc_id as aligned::DocId, callback)
+    })
 }
 
 /*
@@ -363,8 +1514,10 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_cos
     doc_id: jlong,
     callback: JObject,
 ) -> f32 {
-    let scorer = &*(scorer_ptr as *const aligned::Scorer);
-    scorer.cosine_similarity(&_env, doc_id as aligned::DocId, callback)
+    jni_call(&_env, 0f32, || {
+        let scorer = HANDLES.get::<aligned::Scorer>(scorer_ptr)?;
+        scorer.cosine_similarity(&_env, doc_id as aligned::DocId, callback)
+    })
 }
 
 /*
@@ -378,10 +1531,16 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_cre
     _class: JClass,
     query_vector: jfloatArray,
 ) -> jlong {
-    let boxed_item = Box::new(aligned::Item::from_jni_float_array(&_env, query_vector));
-    let result = Box::into_raw(boxed_item) as jlong;
-    //println!("create item {:?} by factory: {:?}", result, factory_ptr);
-    result
+    jni_call(&_env, 0, || {
+        if query_vector.is_null() {
+            return Err(Error::IllegalArgument("vector is null".to_string()));
+        }
+        let dim = _env.get_array_length(query_vector)? as usize;
+        let item = aligned::Item::from_jni_float_array(&_env, query_vector, dim)?;
+        let result = HANDLES.insert(item, handle::NO_HANDLE);
+        //println!("create item {:?} by factory: {:?}", result, factory_ptr);
+        Ok(result)
+    })
 }
 
 /*
@@ -395,9 +1554,11 @@ pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_destroyIte
     _class: JClass,
     item_ptr: jlong,
 ) {
-    //println!("drop scorer: {:?}", scorer_ptr);
-    let _boxed_item = unsafe { Box::from_raw(item_ptr as *mut aligned::Item) };
-    drop(_boxed_item);
+    jni_call(&_env, (), || {
+        //println!("drop scorer: {:?}", scorer_ptr);
+        HANDLES.remove::<aligned::Item>(item_ptr)?;
+        Ok(())
+    })
 }
 
 /*
@@ -412,9 +1573,10 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_ite
     item_ptr_1: jlong,
     item_ptr_2: jlong,
 ) -> f32 {
-    let item_1 = &*(item_ptr_1 as *const aligned::Item);
-    let item_2 = &*(item_ptr_2 as *const aligned::Item);
-    item_1.dot_product(item_2)
+    jni_call(&_env, 0f32, || {
+        let (item_1, item_2) = item_pair(item_ptr_1, item_ptr_2)?;
+        Ok(item_1.dot_product(&item_2))
+    })
 }
 
 /*
@@ -429,16 +1591,23 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_ite
     item_ptr: jlong,
     vector: jfloatArray,
 ) -> f32 {
-    let item = &*(item_ptr as *const aligned::Item);
-    let len = _env.get_array_length(vector).unwrap() as usize;
-    let vector_auto = _env
-        .get_auto_primitive_array_critical(vector, ReleaseMode::NoCopyBack)
-        .unwrap();
-    let vector_ptr = vector_auto.as_ptr() as *mut f32;
-    let vector_slice = unsafe { std::slice::from_raw_parts(vector_ptr, len) };
-    let similarity = item.dot_product_with_unaligned(vector_slice);
-    std::mem::forget(vector_slice);
-    return similarity;
+    jni_call(&_env, 0f32, || {
+        let item = HANDLES.get::<aligned::Item>(item_ptr)?;
+        let len = _env.get_array_length(vector)? as usize;
+        if len != item.dim() {
+            return Err(Error::IllegalArgument(format!(
+                "vector length {:?} differs from item dimension {:?}",
+                len,
+                item.dim()
+            )));
+        }
+        let vector_auto =
+            _env.get_auto_primitive_array_critical(vector, ReleaseMode::NoCopyBack)?;
+        let vector_ptr = vector_auto.as_ptr() as *mut f32;
+        let vector_slice = unsafe { std::slice::from_raw_parts(vector_ptr, len) };
+        let similarity = item.dot_product_with_unaligned(vector_slice);
+        Ok(similarity)
+    })
 }
 
 /*
@@ -452,13 +1621,16 @@ pub unsafe extern "system" fn JavaCritical_com_iqmen_iqfacescore_NativeScorerFac
     vector_len: jint,
     vector_ptr: &jfloat,
 ) -> f32 {
-    let item = &*(item_ptr as *const aligned::Item);
-    let slice = unsafe {
-        std::slice::from_raw_parts(vector_ptr as *const _ as *const f32, vector_len as usize)
-    };
-    let dot_product = item.dot_product_with_unaligned(slice);
-    std::mem::forget(slice);
-    return dot_product;
+    critical_call(|| {
+        let item = HANDLES.get::<aligned::Item>(item_ptr).ok()?;
+        if vector_len as usize != item.dim() {
+            return None;
+        }
+        let slice =
+            unsafe { std::slice::from_raw_parts(vector_ptr as *const f32, vector_len as usize) };
+        let dot_product = item.dot_product_with_unaligned(slice);
+        Some(dot_product)
+    })
 }
 
 /*
@@ -473,9 +1645,10 @@ pub unsafe extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_ite
     item_ptr_1: jlong,
     item_ptr_2: jlong,
 ) -> f32 {
-    let item_1 = &*(item_ptr_1 as *const aligned::Item);
-    let item_2 = &*(item_ptr_2 as *const aligned::Item);
-    item_1.cosine_similarity(item_2)
+    jni_call(&_env, 0f32, || {
+        let (item_1, item_2) = item_pair(item_ptr_1, item_ptr_2)?;
+        Ok(item_1.cosine_similarity(&item_2))
+    })
 }
 
 /*
@@ -490,29 +1663,29 @@ pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_dotProduct
     one: jfloatArray,
     two: jfloatArray,
 ) -> f32 {
-    let one_len = _env.get_array_length(one).unwrap();
-    let two_len = _env.get_array_length(two).unwrap();
-
-    assert_eq!(one_len, two_len);
+    jni_call(&_env, 0f32, || {
+        let one_len = _env.get_array_length(one)?;
+        let two_len = _env.get_array_length(two)?;
 
-    let one_auto = _env
-        .get_auto_primitive_array_critical(one, ReleaseMode::NoCopyBack)
-        .unwrap();
-    let one_ptr = one_auto.as_ptr() as *mut f32;
-    let one_slice = unsafe { std::slice::from_raw_parts(one_ptr, one_len as usize) };
+        if one_len != two_len {
+            return Err(Error::IllegalArgument(format!(
+                "vector lengths differ: {:?} and {:?}",
+                one_len, two_len
+            )));
+        }
 
-    let two_auto = _env
-        .get_auto_primitive_array_critical(two, ReleaseMode::NoCopyBack)
-        .unwrap();
-    let two_ptr = two_auto.as_ptr() as *mut f32;
-    let two_slice = unsafe { std::slice::from_raw_parts(two_ptr, two_len as usize) };
+        let one_auto = _env.get_auto_primitive_array_critical(one, ReleaseMode::NoCopyBack)?;
+        let one_ptr = one_auto.as_ptr() as *mut f32;
+        let one_slice = unsafe { std::slice::from_raw_parts(one_ptr, one_len as usize) };
 
-    let similarity = unaligned::dot_prod(one_slice, two_slice);
+        let two_auto = _env.get_auto_primitive_array_critical(two, ReleaseMode::NoCopyBack)?;
+        let two_ptr = two_auto.as_ptr() as *mut f32;
+        let two_slice = unsafe { std::slice::from_raw_parts(two_ptr, two_len as usize) };
 
-    std::mem::forget(one_slice);
-    std::mem::forget(two_slice);
+        let similarity = unaligned::dot_prod(one_slice, two_slice);
 
-    return similarity;
+        Ok(similarity)
+    })
 }
 
 /*
@@ -528,20 +1701,24 @@ pub extern "system" fn JavaCritical_com_iqmen_iqfacescore_NativeScorerFactory_do
     two_len: jint,
     two_ptr: &jfloat,
 ) -> f32 {
-    let one_slice =
-        unsafe { std::slice::from_raw_parts(one_ptr as *const _ as *const f32, one_len as usize) };
-    let two_slice =
-        unsafe { std::slice::from_raw_parts(two_ptr as *const _ as *const f32, two_len as usize) };
-    let similarity = unaligned::dot_prod(one_slice, two_slice);
-    std::mem::forget(one_slice);
-    std::mem::forget(two_slice);
-    return similarity;
+    critical_call(|| {
+        if one_len != two_len {
+            return None;
+        }
+        let one_slice =
+            unsafe { std::slice::from_raw_parts(one_ptr as *const f32, one_len as usize) };
+        let two_slice =
+            unsafe { std::slice::from_raw_parts(two_ptr as *const f32, two_len as usize) };
+        let similarity = unaligned::dot_prod(one_slice, two_slice);
+        Some(similarity)
+    })
 }
 
 /*
  * Class:     com_iqmen_iqfacescore_NativeScorerFactory
  * Method:    dotProductVectorAndSerializedVector
  * Signature: ([F[B)F
+ * the serialized vector is read big-endian, as written by java.nio.ByteBuffer
  */
 #[no_mangle]
 pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_dotProductVectorAndSerializedVector(
@@ -550,30 +1727,9 @@ pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_dotProduct
     one: jfloatArray,
     two: jbyteArray,
 ) -> f32 {
-    let one_len = _env.get_array_length(one).unwrap();
-    let two_len = _env.get_array_length(two).unwrap();
-
-    assert_eq!(two_len % 4, 0);
-    assert_eq!(one_len, two_len / 4);
-
-    let one_auto = _env
-        .get_auto_primitive_array_critical(one, ReleaseMode::NoCopyBack)
-        .unwrap();
-    let one_ptr = one_auto.as_ptr() as *mut f32;
-    let one_slice = unsafe { std::slice::from_raw_parts(one_ptr, one_len as usize) };
-
-    let two_auto = _env
-        .get_auto_primitive_array_critical(two, ReleaseMode::NoCopyBack)
-        .unwrap();
-    let two_ptr = two_auto.as_ptr() as *mut f32;
-    let two_slice = unsafe { std::slice::from_raw_parts(two_ptr, (two_len / 4) as usize) };
-
-    let similarity = unaligned::dot_prod(one_slice, two_slice);
-
-    std::mem::forget(one_slice);
-    std::mem::forget(two_slice);
-
-    return similarity;
+    jni_call(&_env, 0f32, || {
+        serialized_dot_product(&_env, one, two, half::ByteOrder::BigEndian)
+    })
 }
 
 /*
@@ -589,16 +1745,70 @@ pub extern "system" fn JavaCritical_com_iqmen_iqfacescore_NativeScorerFactory_do
     two_len: jint,
     two_ptr: &jbyte,
 ) -> f32 {
-    assert_eq!(two_len % 4, 0);
-    assert_eq!(one_len, two_len / 4);
+    critical_call(|| {
+        if two_len % 4 != 0 || one_len != two_len / 4 {
+            return None;
+        }
+        let one_slice =
+            unsafe { std::slice::from_raw_parts(one_ptr as *const f32, one_len as usize) };
+        let two_slice = unsafe {
+            std::slice::from_raw_parts(two_ptr as *const _ as *const u8, two_len as usize)
+        };
+        Some(half::dot_product_serialized(
+            one_slice,
+            two_slice,
+            half::ByteOrder::BigEndian,
+        ))
+    })
+}
 
+/*
+ * Class:     com_iqmen_iqfacescore_NativeScorerFactory
+ * Method:    dotProductVectorAndSerializedVectorWithOrder
+ * Signature: ([F[BZ)F
+ */
+#[no_mangle]
+pub extern "system" fn Java_com_iqmen_iqfacescore_NativeScorerFactory_dotProductVectorAndSerializedVectorWithOrder(
+    _env: JNIEnv,
+    _class: JClass,
+    one: jfloatArray,
+    two: jbyteArray,
+    big_endian: jboolean,
+) -> f32 {
+    jni_call(&_env, 0f32, || {
+        serialized_dot_product(
+            &_env,
+            one,
+            two,
+            half::ByteOrder::from_big_endian(big_endian != 0),
+        )
+    })
+}
+
+fn serialized_dot_product(
+    env: &JNIEnv,
+    one: jfloatArray,
+    two: jbyteArray,
+    order: half::ByteOrder,
+) -> error::Result<f32> {
+    let one_len = env.get_array_length(one)?;
+    let two_len = env.get_array_length(two)?;
+
+    if two_len % 4 != 0 || one_len != two_len / 4 {
+        return Err(Error::IllegalArgument(format!(
+            "serialized vector of {:?} bytes does not match vector length {:?}",
+            two_len, one_len
+        )));
+    }
+
+    let one_auto = env.get_auto_primitive_array_critical(one, ReleaseMode::NoCopyBack)?;
     let one_slice =
-        unsafe { std::slice::from_raw_parts(one_ptr as *const _ as *const f32, one_len as usize) };
-    let two_slice = unsafe {
-        std::slice::from_raw_parts(two_ptr as *const _ as *const f32, (two_len / 4) as usize)
-    };
-    let similarity = unaligned::dot_prod(one_slice, two_slice);
-    std::mem::forget(one_slice);
-    std::mem::forget(two_slice);
-    return similarity;
+        unsafe { std::slice::from_raw_parts(one_auto.as_ptr() as *const f32, one_len as usize) };
+
+    // bytes of a Java array are not aligned for f32, decode them in place
+    let two_auto = env.get_auto_primitive_array_critical(two, ReleaseMode::NoCopyBack)?;
+    let two_slice =
+        unsafe { std::slice::from_raw_parts(two_auto.as_ptr() as *const u8, two_len as usize) };
+
+    Ok(half::dot_product_serialized(one_slice, two_slice, order))
 }
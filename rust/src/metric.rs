@@ -0,0 +1,145 @@
+use std::f32::consts::PI;
+
+use jni::sys::jint;
+
+use crate::aligned::Item;
+
+/// Similarity metric a `Scorer` computes between the query and a document vector.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    /// Squared euclidean distance, lower is closer.
+    Euclidean,
+    /// Maximum inner product, the raw dot product of both vectors.
+    InnerProduct,
+    /// Cosine of the angle between both vectors.
+    Cosine,
+    /// Angle between both vectors scaled to `[0, 1]`, lower is closer.
+    Angular,
+}
+
+impl Metric {
+    /// Maps the ordinal of `com.github.eliak.Metric` to the native metric.
+    pub fn from_ordinal(ordinal: jint) -> Option<Metric> {
+        match ordinal {
+            0 => Some(Metric::Euclidean),
+            1 => Some(Metric::InnerProduct),
+            2 => Some(Metric::Cosine),
+            3 => Some(Metric::Angular),
+            _ => None,
+        }
+    }
+
+    /// Raw value of the metric, not transformed in any way.
+    #[inline]
+    pub fn compute(&self, one: &Item, another: &Item) -> f32 {
+        match self {
+            Metric::Euclidean => one.squared_distance(another),
+            Metric::InnerProduct => one.dot_product(another),
+            Metric::Cosine => one.cosine_similarity(another),
+            Metric::Angular => angular_distance(one.cosine_similarity(another)),
+        }
+    }
+
+    /// Like `compute` for unit normalized vectors: cosine is the dot product, no division by
+    /// the magnitudes.
+    #[inline]
+    pub fn compute_unit(&self, one: &Item, another: &Item) -> f32 {
+        match self {
+            Metric::Cosine => one.dot_product(another),
+            Metric::Angular => angular_distance(one.dot_product(another)),
+            _ => self.compute(one, another),
+        }
+    }
+
+    /// Dissimilarity derived from the metric where lower is closer for every metric, what
+    /// indexes are built and searched on.
+    #[inline]
+    pub fn distance(&self, one: &Item, another: &Item) -> f32 {
+        match self {
+            Metric::Euclidean => one.squared_distance(another),
+            Metric::InnerProduct => -one.dot_product(another),
+            Metric::Cosine => 1f32 - one.cosine_similarity(another),
+            Metric::Angular => angular_distance(one.cosine_similarity(another)),
+        }
+    }
+
+    /// Raw value of the metric from the dot product and the magnitudes of both vectors, for
+    /// representations without a direct kernel of every metric.
+    #[inline]
+    pub fn compute_from_dot_product(
+        &self,
+        dot_product: f32,
+        one_magnitude: f32,
+        another_magnitude: f32,
+    ) -> f32 {
+        match self {
+            Metric::Euclidean => (one_magnitude * one_magnitude
+                + another_magnitude * another_magnitude
+                - 2f32 * dot_product)
+                .max(0f32),
+            Metric::InnerProduct => dot_product,
+            Metric::Cosine => dot_product / (one_magnitude * another_magnitude),
+            Metric::Angular => angular_distance(dot_product / (one_magnitude * another_magnitude)),
+        }
+    }
+
+    /// Whether a smaller raw value means a closer document.
+    pub fn lower_is_closer(&self) -> bool {
+        match self {
+            Metric::Euclidean | Metric::Angular => true,
+            Metric::InnerProduct | Metric::Cosine => false,
+        }
+    }
+}
+
+/// How the raw metric value is turned into a score.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    /// Score is the raw metric value.
+    None,
+    /// Score follows Lucene's `VectorSimilarityFunction`: non-negative and higher is better.
+    Lucene,
+}
+
+impl Normalization {
+    /// Maps the ordinal of `com.github.eliak.ScoreNormalization` to the native mode.
+    pub fn from_ordinal(ordinal: jint) -> Option<Normalization> {
+        match ordinal {
+            0 => Some(Normalization::None),
+            1 => Some(Normalization::Lucene),
+            _ => None,
+        }
+    }
+
+    /// Whether scores produced for the metric rank better documents lower.
+    pub fn lower_is_better(&self, metric: Metric) -> bool {
+        match self {
+            Normalization::None => metric.lower_is_closer(),
+            Normalization::Lucene => false,
+        }
+    }
+
+    #[inline]
+    pub fn apply(&self, metric: Metric, value: f32) -> f32 {
+        match self {
+            Normalization::None => value,
+            Normalization::Lucene => match metric {
+                Metric::Euclidean => 1f32 / (1f32 + value),
+                Metric::InnerProduct => {
+                    if value < 0f32 {
+                        1f32 / (1f32 - value)
+                    } else {
+                        value + 1f32
+                    }
+                }
+                Metric::Cosine => ((1f32 + value) / 2f32).max(0f32),
+                Metric::Angular => 1f32 - value,
+            },
+        }
+    }
+}
+
+#[inline]
+pub fn angular_distance(cosine: f32) -> f32 {
+    cosine.clamp(-1f32, 1f32).acos() / PI
+}
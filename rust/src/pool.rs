@@ -0,0 +1,95 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Fixed set of native worker threads a `ScorerFactory` splits exhaustive scans over, so that
+/// one query can use all cores when the searcher runs segments on a single thread.
+pub struct ThreadPool {
+    sender: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(size: usize) -> ThreadPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("vscore-scan-{}", i))
+                    .spawn(move || work(&receiver))
+                    .expect("cannot spawn scan worker")
+            })
+            .collect();
+        ThreadPool {
+            sender: Some(sender),
+            workers,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs `f(0)` to `f(tasks - 1)` on the workers and returns the results in task order. Blocks
+    /// until every task is done, which is what lets the tasks borrow from the caller. A panic in
+    /// a task is resumed on the calling thread once the others finished. Must not be called from
+    /// a task of the same pool, it could wait for a worker that waits for it.
+    pub fn map<T, F>(&self, tasks: usize, f: F) -> Vec<T>
+    where
+        T: Send + 'static,
+        F: Fn(usize) -> T + Sync,
+    {
+        let (results, received) = mpsc::channel::<(usize, thread::Result<T>)>();
+        let f: &(dyn Fn(usize) -> T + Sync) = &f;
+        // Safety: `f` outlives its uses, this function does not return before every job has sent
+        // its result, panics included, and no job calls `f` after sending.
+        let f: &'static (dyn Fn(usize) -> T + Sync) = unsafe { mem::transmute(f) };
+        let sender = self.sender.as_ref().expect("pool is running until dropped");
+        for task in 0..tasks {
+            let results = results.clone();
+            let job: Job = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(|| f(task)));
+                let _ = results.send((task, result));
+            });
+            sender.send(job).expect("scan workers outlive the pool");
+        }
+        drop(results);
+        let mut slots: Vec<Option<thread::Result<T>>> = (0..tasks).map(|_| None).collect();
+        for (task, result) in received.iter().take(tasks) {
+            slots[task] = Some(result);
+        }
+        slots
+            .into_iter()
+            .map(|slot| match slot.expect("every task reports") {
+                Ok(value) => value,
+                Err(payload) => panic::resume_unwind(payload),
+            })
+            .collect()
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Closes the queue and waits for the workers to exit.
+    fn drop(&mut self) {
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::sync::RwLock;
+
+use crate::aligned::Item;
+use crate::error::{Error, Result};
+use crate::kmeans::kmeans;
+use crate::metric::{angular_distance, Metric, Normalization};
+use crate::simd;
+
+/// Number of centroids of every sub-quantizer, a code byte addresses one of them.
+pub const CENTROIDS: usize = 256;
+
+/// Product quantizer: splits vectors into `m` sub-vectors and encodes each by the nearest of
+/// `CENTROIDS` centroids trained on that subspace, so a vector is stored in `m` bytes. Queries
+/// are scored against codes with asymmetric distance computation, the uncompressed query is
+/// compared to every centroid once and a code is scored by `m` table lookups.
+pub struct ProductQuantizer {
+    dim: usize,
+    m: usize,
+    metric: Metric,
+    normalization: Normalization,
+    /// One flat `CENTROIDS x sub_dim` table per subspace, empty until trained.
+    codebooks: RwLock<Vec<Vec<f32>>>,
+}
+
+/// Lookup tables of one query, see `ProductQuantizer::query`.
+pub struct PqQuery {
+    m: usize,
+    metric: Metric,
+    normalization: Normalization,
+    /// Per subspace and centroid: the dot product with the query, or the squared distance to it
+    /// for `Metric::Euclidean`.
+    table: Vec<f32>,
+    /// Per subspace and centroid the squared norm of the centroid, used for cosine metrics.
+    norms: Vec<f32>,
+    query_magnitude: f32,
+}
+
+impl ProductQuantizer {
+    pub fn new(
+        dim: usize,
+        m: usize,
+        metric: Metric,
+        normalization: Normalization,
+    ) -> Result<ProductQuantizer> {
+        if m == 0 || !dim.is_multiple_of(m) {
+            return Err(Error::IllegalArgument(format!(
+                "dimension {:?} is not divisible into {:?} sub-quantizers",
+                dim, m
+            )));
+        }
+        Ok(ProductQuantizer {
+            dim,
+            m,
+            metric,
+            normalization,
+            codebooks: RwLock::new(Vec::new()),
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Length of a code in bytes.
+    pub fn code_size(&self) -> usize {
+        self.m
+    }
+
+    fn sub_dim(&self) -> usize {
+        self.dim / self.m
+    }
+
+    #[cfg(test)]
+    pub fn is_trained(&self) -> bool {
+        !self.codebooks.read().unwrap().is_empty()
+    }
+
+    /// Trains every sub-quantizer with euclidean k-means on the matching slice of `samples`.
+    pub fn train(&self, samples: &[Item], iterations: usize) -> Result<()> {
+        if samples.len() < CENTROIDS {
+            return Err(Error::IllegalArgument(format!(
+                "{:?} training vectors are not enough for {:?} centroids",
+                samples.len(),
+                CENTROIDS
+            )));
+        }
+        if let Some(sample) = samples.iter().find(|sample| sample.dim() != self.dim) {
+            return Err(Error::IllegalArgument(format!(
+                "vector dimension {:?} does not match quantizer dimension {:?}",
+                sample.dim(),
+                self.dim
+            )));
+        }
+        let sub_dim = self.sub_dim();
+        let codebooks: Vec<Vec<f32>> = (0..self.m)
+            .map(|sub| {
+                let range = sub * sub_dim..(sub + 1) * sub_dim;
+                let sub_samples: Vec<Item> = samples
+                    .iter()
+                    .map(|sample| Item::from_slice(&sample.values()[range.clone()]))
+                    .collect();
+                kmeans(
+                    Metric::Euclidean,
+                    &sub_samples,
+                    CENTROIDS,
+                    iterations.max(1),
+                )
+                .iter()
+                .flat_map(|centroid| centroid.values().to_vec())
+                .collect()
+            })
+            .collect();
+        *self.codebooks.write().unwrap() = codebooks;
+        Ok(())
+    }
+
+    /// Encodes the vector into `code`, one centroid index per subspace.
+    pub fn encode(&self, values: &[f32], code: &mut [u8]) -> Result<()> {
+        if values.len() != self.dim || code.len() != self.m {
+            return Err(Error::IllegalArgument(format!(
+                "vector of {:?} and code of {:?} do not match quantizer {:?}x{:?}",
+                values.len(),
+                code.len(),
+                self.dim,
+                self.m
+            )));
+        }
+        let codebooks = self.codebooks.read().unwrap();
+        if codebooks.is_empty() {
+            return Err(Error::IllegalArgument(
+                "quantizer is not trained".to_string(),
+            ));
+        }
+        let sub_dim = self.sub_dim();
+        for (sub, codebook) in codebooks.iter().enumerate() {
+            let sub_vector = &values[sub * sub_dim..(sub + 1) * sub_dim];
+            let mut best = 0;
+            let mut best_distance = f32::INFINITY;
+            for (centroid, values) in codebook.chunks(sub_dim).enumerate() {
+                let distance = simd::squared_distance(sub_vector, values);
+                if distance < best_distance {
+                    best = centroid;
+                    best_distance = distance;
+                }
+            }
+            code[sub] = best as u8;
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    /// Reconstructs the approximate vector a code stands for.
+    pub fn decode(&self, code: &[u8]) -> Result<Vec<f32>> {
+        let codebooks = self.codebooks.read().unwrap();
+        if codebooks.is_empty() || code.len() != self.m {
+            return Err(Error::IllegalArgument(format!(
+                "code of {:?} bytes cannot be decoded",
+                code.len()
+            )));
+        }
+        let sub_dim = self.sub_dim();
+        let mut values = Vec::with_capacity(self.dim);
+        for (codebook, centroid) in codebooks.iter().zip(code.iter()) {
+            let start = *centroid as usize * sub_dim;
+            values.extend_from_slice(&codebook[start..start + sub_dim]);
+        }
+        Ok(values)
+    }
+
+    /// Precomputes the asymmetric distance tables of the query.
+    pub fn query(&self, query: &Item) -> Result<PqQuery> {
+        if query.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "query dimension {:?} does not match quantizer dimension {:?}",
+                query.dim(),
+                self.dim
+            )));
+        }
+        let codebooks = self.codebooks.read().unwrap();
+        if codebooks.is_empty() {
+            return Err(Error::IllegalArgument(
+                "quantizer is not trained".to_string(),
+            ));
+        }
+        let sub_dim = self.sub_dim();
+        let mut table = Vec::with_capacity(self.m * CENTROIDS);
+        let mut norms = Vec::with_capacity(self.m * CENTROIDS);
+        for (sub, codebook) in codebooks.iter().enumerate() {
+            let sub_query = &query.values()[sub * sub_dim..(sub + 1) * sub_dim];
+            for centroid in codebook.chunks(sub_dim) {
+                table.push(match self.metric {
+                    Metric::Euclidean => simd::squared_distance(sub_query, centroid),
+                    _ => simd::dot_product(sub_query, centroid),
+                });
+                norms.push(simd::dot_product(centroid, centroid));
+            }
+        }
+        Ok(PqQuery {
+            m: self.m,
+            metric: self.metric,
+            normalization: self.normalization,
+            table,
+            norms,
+            query_magnitude: simd::dot_product(query.values(), query.values()).sqrt(),
+        })
+    }
+}
+
+impl PqQuery {
+    /// Scores the code like `Scorer` scores an uncompressed vector with the same metric and
+    /// normalization, up to the quantization error.
+    #[inline]
+    pub fn score(&self, code: &[u8]) -> f32 {
+        let mut sum = 0f32;
+        for (sub, centroid) in code.iter().take(self.m).enumerate() {
+            sum += self.table[sub * CENTROIDS + *centroid as usize];
+        }
+        let value = match self.metric {
+            Metric::Euclidean | Metric::InnerProduct => sum,
+            Metric::Cosine | Metric::Angular => {
+                let mut norm = 0f32;
+                for (sub, centroid) in code.iter().take(self.m).enumerate() {
+                    norm += self.norms[sub * CENTROIDS + *centroid as usize];
+                }
+                let cosine = sum / (self.query_magnitude * norm.sqrt());
+                if self.metric == Metric::Cosine {
+                    cosine
+                } else {
+                    angular_distance(cosine)
+                }
+            }
+        };
+        self.normalization.apply(self.metric, value)
+    }
+
+    /// Length of the codes the query scores.
+    pub fn code_size(&self) -> usize {
+        self.m
+    }
+}
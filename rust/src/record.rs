@@ -0,0 +1,142 @@
+use crate::aligned::{Item, UNIT_NORM_TOLERANCE};
+use crate::error::{Error, Result};
+use crate::half::{f32_to_bf16, f32_to_f16, ByteOrder, Encoding};
+
+/// First bytes of every record.
+pub const MAGIC: [u8; 2] = *b"VR";
+
+/// Version written by `encode`. Readers reject records of a newer version.
+pub const VERSION: u8 = 1;
+
+/// Size of the header preceding the components.
+pub const HEADER_LEN: usize = 16;
+
+const FLAG_NORMALIZED: u8 = 1;
+
+/// Header of a serialized vector record, the layout shared by indexers writing binary doc values
+/// and the scorers reading them. All fields are little endian:
+///
+/// | offset | size            | field                                               |
+/// |--------|-----------------|-----------------------------------------------------|
+/// | 0      | 2               | magic `b"VR"`                                       |
+/// | 2      | 1               | version                                             |
+/// | 3      | 1               | element type, ordinal of `Encoding`: f32, f16, bf16 |
+/// | 4      | 1               | flags, bit 0 set when the vector is unit normalized |
+/// | 5      | 3               | reserved, zero                                      |
+/// | 8      | 4               | dimension                                           |
+/// | 12     | 4               | norm of the vector before narrowing to the element  |
+/// | 16     | dim x elem size | components                                          |
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordHeader {
+    pub version: u8,
+    pub element: Encoding,
+    pub normalized: bool,
+    pub dim: usize,
+    pub norm: f32,
+}
+
+impl RecordHeader {
+    /// Size of the whole record the header describes.
+    pub fn record_len(&self) -> usize {
+        HEADER_LEN + self.dim * element_size(self.element)
+    }
+}
+
+/// Serializes the vector with components of type `element`. The normalized flag is set when
+/// the norm of the vector is one up to `UNIT_NORM_TOLERANCE`.
+pub fn encode(item: &Item, element: Encoding) -> Result<Vec<u8>> {
+    if element.bytes_per_component().is_none() {
+        return Err(Error::IllegalArgument(format!(
+            "{:?} is not a record element type",
+            element
+        )));
+    }
+    let norm = item.magnitude();
+    let flags = if (norm - 1f32).abs() <= UNIT_NORM_TOLERANCE {
+        FLAG_NORMALIZED
+    } else {
+        0
+    };
+    let mut bytes = Vec::with_capacity(HEADER_LEN + item.dim() * element_size(element));
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.push(element as u8);
+    bytes.push(flags);
+    bytes.extend_from_slice(&[0u8; 3]);
+    bytes.extend_from_slice(&(item.dim() as u32).to_le_bytes());
+    bytes.extend_from_slice(&norm.to_le_bytes());
+    for value in item.values() {
+        match element {
+            Encoding::F16 => bytes.extend_from_slice(&f32_to_f16(*value).to_le_bytes()),
+            Encoding::BF16 => bytes.extend_from_slice(&f32_to_bf16(*value).to_le_bytes()),
+            _ => bytes.extend_from_slice(&value.to_le_bytes()),
+        }
+    }
+    Ok(bytes)
+}
+
+/// Reads and validates the header, including that `bytes` holds exactly the record it describes.
+pub fn decode_header(bytes: &[u8]) -> Result<RecordHeader> {
+    if bytes.len() < HEADER_LEN || bytes[0..2] != MAGIC {
+        return Err(Error::IllegalArgument(format!(
+            "{:?} bytes are not a vector record",
+            bytes.len()
+        )));
+    }
+    let version = bytes[2];
+    if version == 0 || version > VERSION {
+        return Err(Error::IllegalArgument(format!(
+            "unsupported vector record version {:?}",
+            version
+        )));
+    }
+    let element = match Encoding::from_ordinal(bytes[3] as i32) {
+        Some(element) if element.bytes_per_component().is_some() => element,
+        _ => {
+            return Err(Error::IllegalArgument(format!(
+                "unknown vector record element type {:?}",
+                bytes[3]
+            )))
+        }
+    };
+    let header = RecordHeader {
+        version,
+        element,
+        normalized: bytes[4] & FLAG_NORMALIZED != 0,
+        dim: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+        norm: f32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+    };
+    if bytes.len() != header.record_len() {
+        return Err(Error::IllegalArgument(format!(
+            "vector record of {:?} bytes does not match its header {:?}",
+            bytes.len(),
+            header
+        )));
+    }
+    Ok(header)
+}
+
+/// Decodes the record into an aligned item carrying the stored norm as its magnitude.
+pub fn decode(bytes: &[u8]) -> Result<(RecordHeader, Item)> {
+    let header = decode_header(bytes)?;
+    let values = header
+        .element
+        .components(&bytes[HEADER_LEN..], ByteOrder::LittleEndian);
+    Ok((header, Item::with_magnitude(&values, header.norm)))
+}
+
+/// Like `decode`, checking that the record has the expected dimension.
+pub fn decode_item(bytes: &[u8], dim: usize) -> Result<Item> {
+    let (header, item) = decode(bytes)?;
+    if header.dim != dim {
+        return Err(Error::IllegalArgument(format!(
+            "vector record dimension {:?} does not match required {:?}",
+            header.dim, dim
+        )));
+    }
+    Ok(item)
+}
+
+fn element_size(element: Encoding) -> usize {
+    element.bytes_per_component().unwrap_or(0)
+}
@@ -0,0 +1,223 @@
+/// Instruction set of the vector kernels, from the most portable to the widest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Scalar,
+    /// 4 lanes, baseline of every x86_64 CPU.
+    Sse,
+    /// 8 lanes with fused multiply-add. F16C is required too, all CPUs with AVX2 have it.
+    Avx2,
+    /// 16 lanes with fused multiply-add, AVX-512F.
+    Avx512,
+}
+
+lazy_static! {
+    static ref LEVEL: Level = Level::detect();
+}
+
+impl Level {
+    /// Widest level the running CPU supports.
+    pub fn detect() -> Level {
+        #[cfg(target_arch = "x86_64")]
+        {
+            let avx2 = is_x86_feature_detected!("avx2")
+                && is_x86_feature_detected!("fma")
+                && is_x86_feature_detected!("f16c");
+            if avx2 && is_x86_feature_detected!("avx512f") {
+                return Level::Avx512;
+            }
+            if avx2 {
+                return Level::Avx2;
+            }
+            return Level::Sse;
+        }
+        #[allow(unreachable_code)]
+        Level::Scalar
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Scalar => "scalar",
+            Level::Sse => "sse",
+            Level::Avx2 => "avx2+fma",
+            Level::Avx512 => "avx512f",
+        }
+    }
+}
+
+/// Level selected for this process, detected once when first used.
+#[inline]
+pub fn level() -> Level {
+    *LEVEL
+}
+
+#[inline]
+pub fn dot_product(one: &[f32], another: &[f32]) -> f32 {
+    unsafe { dot_product_at(level(), one, another) }
+}
+
+#[inline]
+pub fn squared_distance(one: &[f32], another: &[f32]) -> f32 {
+    unsafe { squared_distance_at(level(), one, another) }
+}
+
+/// Dot product with the kernel of `level`, which the CPU must support.
+#[inline]
+pub unsafe fn dot_product_at(level: Level, one: &[f32], another: &[f32]) -> f32 {
+    debug_assert_eq!(one.len(), another.len());
+    let len = one.len().min(another.len());
+    let (one, another) = (&one[..len], &another[..len]);
+    match level {
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx512 => x86::dot_product_avx512(one, another),
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx2 => x86::dot_product_avx2(one, another),
+        #[cfg(target_arch = "x86_64")]
+        Level::Sse => x86::dot_product_sse(one, another),
+        _ => dot_product_scalar(one, another),
+    }
+}
+
+/// Squared euclidean distance with the kernel of `level`, which the CPU must support.
+#[inline]
+pub unsafe fn squared_distance_at(level: Level, one: &[f32], another: &[f32]) -> f32 {
+    debug_assert_eq!(one.len(), another.len());
+    let len = one.len().min(another.len());
+    let (one, another) = (&one[..len], &another[..len]);
+    match level {
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx512 => x86::squared_distance_avx512(one, another),
+        #[cfg(target_arch = "x86_64")]
+        Level::Avx2 => x86::squared_distance_avx2(one, another),
+        #[cfg(target_arch = "x86_64")]
+        Level::Sse => x86::squared_distance_sse(one, another),
+        _ => squared_distance_scalar(one, another),
+    }
+}
+
+pub fn dot_product_scalar(one: &[f32], another: &[f32]) -> f32 {
+    one.iter().zip(another.iter()).map(|(a, b)| a * b).sum()
+}
+
+pub fn squared_distance_scalar(one: &[f32], another: &[f32]) -> f32 {
+    one.iter()
+        .zip(another.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum()
+}
+
+/// Kernels of equal length slices, loads are unaligned so any slice can be passed. Remainders
+/// that do not fill a register are summed by the scalar kernels.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::{dot_product_scalar, squared_distance_scalar};
+
+    #[target_feature(enable = "sse")]
+    pub unsafe fn dot_product_sse(one: &[f32], another: &[f32]) -> f32 {
+        let split = one.len() - one.len() % 4;
+        let mut sum = _mm_setzero_ps();
+        let mut i = 0;
+        while i < split {
+            let a = _mm_loadu_ps(one.as_ptr().add(i));
+            let b = _mm_loadu_ps(another.as_ptr().add(i));
+            sum = _mm_add_ps(sum, _mm_mul_ps(a, b));
+            i += 4;
+        }
+        sum_sse(sum) + dot_product_scalar(&one[split..], &another[split..])
+    }
+
+    #[target_feature(enable = "sse")]
+    pub unsafe fn squared_distance_sse(one: &[f32], another: &[f32]) -> f32 {
+        let split = one.len() - one.len() % 4;
+        let mut sum = _mm_setzero_ps();
+        let mut i = 0;
+        while i < split {
+            let d = _mm_sub_ps(
+                _mm_loadu_ps(one.as_ptr().add(i)),
+                _mm_loadu_ps(another.as_ptr().add(i)),
+            );
+            sum = _mm_add_ps(sum, _mm_mul_ps(d, d));
+            i += 4;
+        }
+        sum_sse(sum) + squared_distance_scalar(&one[split..], &another[split..])
+    }
+
+    /// Two independent accumulators hide the latency of the fused multiply-add.
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn dot_product_avx2(one: &[f32], another: &[f32]) -> f32 {
+        let split = one.len() - one.len() % 16;
+        let mut sum0 = _mm256_setzero_ps();
+        let mut sum1 = _mm256_setzero_ps();
+        let mut i = 0;
+        while i < split {
+            let (a, b) = (one.as_ptr().add(i), another.as_ptr().add(i));
+            sum0 = _mm256_fmadd_ps(_mm256_loadu_ps(a), _mm256_loadu_ps(b), sum0);
+            sum1 = _mm256_fmadd_ps(_mm256_loadu_ps(a.add(8)), _mm256_loadu_ps(b.add(8)), sum1);
+            i += 16;
+        }
+        sum_avx(_mm256_add_ps(sum0, sum1)) + dot_product_sse(&one[split..], &another[split..])
+    }
+
+    #[target_feature(enable = "avx2,fma")]
+    pub unsafe fn squared_distance_avx2(one: &[f32], another: &[f32]) -> f32 {
+        let split = one.len() - one.len() % 8;
+        let mut sum = _mm256_setzero_ps();
+        let mut i = 0;
+        while i < split {
+            let d = _mm256_sub_ps(
+                _mm256_loadu_ps(one.as_ptr().add(i)),
+                _mm256_loadu_ps(another.as_ptr().add(i)),
+            );
+            sum = _mm256_fmadd_ps(d, d, sum);
+            i += 8;
+        }
+        sum_avx(sum) + squared_distance_sse(&one[split..], &another[split..])
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn dot_product_avx512(one: &[f32], another: &[f32]) -> f32 {
+        let split = one.len() - one.len() % 16;
+        let mut sum = _mm512_setzero_ps();
+        let mut i = 0;
+        while i < split {
+            let a = _mm512_loadu_ps(one.as_ptr().add(i));
+            let b = _mm512_loadu_ps(another.as_ptr().add(i));
+            sum = _mm512_fmadd_ps(a, b, sum);
+            i += 16;
+        }
+        _mm512_reduce_add_ps(sum) + dot_product_sse(&one[split..], &another[split..])
+    }
+
+    #[target_feature(enable = "avx512f")]
+    pub unsafe fn squared_distance_avx512(one: &[f32], another: &[f32]) -> f32 {
+        let split = one.len() - one.len() % 16;
+        let mut sum = _mm512_setzero_ps();
+        let mut i = 0;
+        while i < split {
+            let d = _mm512_sub_ps(
+                _mm512_loadu_ps(one.as_ptr().add(i)),
+                _mm512_loadu_ps(another.as_ptr().add(i)),
+            );
+            sum = _mm512_fmadd_ps(d, d, sum);
+            i += 16;
+        }
+        _mm512_reduce_add_ps(sum) + squared_distance_sse(&one[split..], &another[split..])
+    }
+
+    #[inline]
+    #[target_feature(enable = "sse")]
+    unsafe fn sum_sse(sum: __m128) -> f32 {
+        let mut lanes = [0f32; 4];
+        _mm_storeu_ps(lanes.as_mut_ptr(), sum);
+        lanes.iter().sum()
+    }
+
+    #[inline]
+    #[target_feature(enable = "avx")]
+    unsafe fn sum_avx(sum: __m256) -> f32 {
+        let mut lanes = [0f32; 8];
+        _mm256_storeu_ps(lanes.as_mut_ptr(), sum);
+        lanes.iter().sum()
+    }
+}
@@ -0,0 +1,307 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::aligned::{DocVector, Item, Storage, UnitNorm};
+use crate::binary::BinaryItem;
+use crate::cache::CacheKey;
+use crate::error::{Error, Result};
+use crate::half::F16Item;
+use crate::int8::Int8Item;
+
+/// First bytes of every snapshot.
+pub const MAGIC: [u8; 4] = *b"VSNP";
+
+/// Version written by `write`. Readers reject snapshots of a newer version.
+pub const VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 24;
+
+/// Size of the checksum closing the file.
+const FOOTER_LEN: usize = 4;
+
+/// Settings of the factory a snapshot was taken from, only a factory with the same ones can
+/// load it. The header of the file, all fields little endian:
+///
+/// | offset | size | field                                        |
+/// |--------|------|----------------------------------------------|
+/// | 0      | 4    | magic `b"VSNP"`                              |
+/// | 4      | 1    | version                                      |
+/// | 5      | 1    | storage, ordinal of `Storage`                |
+/// | 6      | 1    | unit norm, ordinal of `UnitNorm`             |
+/// | 7      | 1    | reserved, zero                               |
+/// | 8      | 4    | dimension                                    |
+/// | 12     | 4    | reserved, zero                               |
+/// | 16     | 8    | number of entries                            |
+///
+/// Entries follow: segment key (i64), doc id (i64) and the vector in the representation of the
+/// storage, its magnitude included so nothing is recomputed on load:
+///
+/// - `F32`: magnitude (f32), `dim` components (f32)
+/// - `F16`: magnitude (f32), `dim` components (u16)
+/// - `Int8`: offset (f32), scale (f32), magnitude (f32), `dim` codes (i8)
+/// - `Binary`: magnitude (f32), `dim / 64` rounded up sign words (u64)
+///
+/// The file ends with the CRC-32 of everything before it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SnapshotHeader {
+    pub storage: Storage,
+    pub unit_norm: UnitNorm,
+    pub dim: usize,
+}
+
+/// Writes the entries to `path` through a temporary file renamed into place, a crash never
+/// leaves a truncated snapshot behind. Returns the number of entries written.
+pub fn write(
+    path: &Path,
+    header: SnapshotHeader,
+    entries: &[(CacheKey, &DocVector)],
+) -> Result<usize> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let result = write_file(Path::new(&temp_path), header, entries)
+        .and_then(|_| fs::rename(&temp_path, path).map_err(Error::from));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map(|_| entries.len())
+}
+
+fn write_file(
+    path: &Path,
+    header: SnapshotHeader,
+    entries: &[(CacheKey, &DocVector)],
+) -> Result<()> {
+    let file = File::create(path)
+        .map_err(|e| Error::Io(format!("cannot create snapshot {:?}: {}", path, e)))?;
+    let mut out = ChecksumWriter {
+        inner: BufWriter::new(file),
+        hasher: crc32fast::Hasher::new(),
+    };
+    let mut bytes = Vec::with_capacity(HEADER_LEN);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.push(header.storage as u8);
+    bytes.push(header.unit_norm as u8);
+    bytes.push(0);
+    bytes.extend_from_slice(&(header.dim as u32).to_le_bytes());
+    bytes.extend_from_slice(&[0u8; 4]);
+    bytes.extend_from_slice(&(entries.len() as u64).to_le_bytes());
+    out.write_all(&bytes)?;
+    for (key, doc) in entries.iter() {
+        bytes.clear();
+        bytes.extend_from_slice(&key.segment.to_le_bytes());
+        bytes.extend_from_slice(&key.doc.to_le_bytes());
+        encode(&mut bytes, header, key, doc)?;
+        out.write_all(&bytes)?;
+    }
+    let checksum = out.hasher.finalize();
+    let mut file = out.inner;
+    file.write_all(&checksum.to_le_bytes())?;
+    let file = file
+        .into_inner()
+        .map_err(|e| Error::Io(format!("cannot write snapshot {:?}: {}", path, e)))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+fn encode(
+    out: &mut Vec<u8>,
+    header: SnapshotHeader,
+    key: &CacheKey,
+    doc: &DocVector,
+) -> Result<()> {
+    let dim = match doc {
+        DocVector::F32(item) => item.dim(),
+        DocVector::Int8(item) => item.parts().0.len(),
+        DocVector::Binary(item) => item.dim(),
+        DocVector::F16(item) => item.bits().len(),
+    };
+    if dim != header.dim {
+        return Err(Error::IllegalArgument(format!(
+            "doc {:?} of segment {:?} has dimension {:?} instead of {:?}",
+            key.doc, key.segment, dim, header.dim
+        )));
+    }
+    match (header.storage, doc) {
+        (Storage::F32, DocVector::F32(item)) => {
+            out.extend_from_slice(&item.magnitude().to_le_bytes());
+            for value in item.values() {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        (Storage::F16, DocVector::F16(item)) => {
+            out.extend_from_slice(&item.magnitude().to_le_bytes());
+            for bits in item.bits() {
+                out.extend_from_slice(&bits.to_le_bytes());
+            }
+        }
+        (Storage::Int8, DocVector::Int8(item)) => {
+            let (codes, offset, scale, magnitude) = item.parts();
+            out.extend_from_slice(&offset.to_le_bytes());
+            out.extend_from_slice(&scale.to_le_bytes());
+            out.extend_from_slice(&magnitude.to_le_bytes());
+            out.extend(codes.iter().map(|code| *code as u8));
+        }
+        (Storage::Binary, DocVector::Binary(item)) => {
+            out.extend_from_slice(&item.magnitude().to_le_bytes());
+            for word in item.words() {
+                out.extend_from_slice(&word.to_le_bytes());
+            }
+        }
+        _ => {
+            return Err(Error::IllegalArgument(format!(
+                "doc {:?} of segment {:?} is not held as {:?}",
+                key.doc, key.segment, header.storage
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Reads a whole snapshot, verifying its checksum before decoding any entry.
+pub fn read(path: &Path) -> Result<(SnapshotHeader, Vec<(CacheKey, DocVector)>)> {
+    let bytes =
+        fs::read(path).map_err(|e| Error::Io(format!("cannot read snapshot {:?}: {}", path, e)))?;
+    if bytes.len() < HEADER_LEN + FOOTER_LEN || bytes[0..4] != MAGIC {
+        return Err(Error::Io(format!("{:?} is not a snapshot", path)));
+    }
+    let (content, footer) = bytes.split_at(bytes.len() - FOOTER_LEN);
+    let checksum = u32::from_le_bytes([footer[0], footer[1], footer[2], footer[3]]);
+    if crc32fast::hash(content) != checksum {
+        return Err(Error::Io(format!(
+            "snapshot {:?} is corrupt, checksum mismatch",
+            path
+        )));
+    }
+    let mut reader = Reader {
+        bytes: content,
+        position: 4,
+    };
+    let version = reader.u8()?;
+    if version == 0 || version > VERSION {
+        return Err(Error::Io(format!(
+            "unsupported snapshot version {:?}",
+            version
+        )));
+    }
+    let storage = reader.u8()?;
+    let unit_norm = reader.u8()?;
+    reader.take(1)?;
+    let dim = reader.u32()? as usize;
+    reader.take(4)?;
+    let header = SnapshotHeader {
+        storage: Storage::from_ordinal(storage as i32)
+            .ok_or_else(|| Error::Io(format!("unknown snapshot storage {:?}", storage)))?,
+        unit_norm: UnitNorm::from_ordinal(unit_norm as i32)
+            .ok_or_else(|| Error::Io(format!("unknown snapshot unit norm {:?}", unit_norm)))?,
+        dim,
+    };
+    let count = reader.u64()? as usize;
+    let mut entries = Vec::with_capacity(count.min(content.len() / 16));
+    for _ in 0..count {
+        let key = CacheKey::new(reader.u64()? as i64, reader.u64()? as i64);
+        entries.push((key, decode(&mut reader, header)?));
+    }
+    if reader.position != content.len() {
+        return Err(Error::Io(format!(
+            "snapshot {:?} has {:?} trailing bytes",
+            path,
+            content.len() - reader.position
+        )));
+    }
+    Ok((header, entries))
+}
+
+fn decode(reader: &mut Reader, header: SnapshotHeader) -> Result<DocVector> {
+    let dim = header.dim;
+    Ok(match header.storage {
+        Storage::F32 => {
+            let magnitude = reader.f32()?;
+            let values = reader
+                .take(dim * 4)?
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect::<Vec<f32>>();
+            DocVector::F32(Item::with_magnitude(&values, magnitude))
+        }
+        Storage::F16 => {
+            let magnitude = reader.f32()?;
+            let bits = reader
+                .take(dim * 2)?
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+                .collect();
+            DocVector::F16(F16Item::from_bits(bits, magnitude))
+        }
+        Storage::Int8 => {
+            let (offset, scale, magnitude) = (reader.f32()?, reader.f32()?, reader.f32()?);
+            let codes = reader.take(dim)?.iter().map(|code| *code as i8).collect();
+            DocVector::Int8(Int8Item::from_parts(codes, offset, scale, magnitude))
+        }
+        Storage::Binary => {
+            let magnitude = reader.f32()?;
+            let words = reader
+                .take(dim.div_ceil(64) * 8)?
+                .chunks_exact(8)
+                .map(|b| u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+                .collect();
+            DocVector::Binary(BinaryItem::from_words(words, dim, magnitude))
+        }
+    })
+}
+
+/// Feeds everything written through it to the checksum.
+struct ChecksumWriter<W: Write> {
+    inner: W,
+    hasher: crc32fast::Hasher,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bounds checked cursor over the content of a snapshot.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if len > self.bytes.len() - self.position {
+            return Err(Error::Io("snapshot is truncated".to_string()));
+        }
+        let bytes = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let b = self.take(8)?;
+        Ok(u64::from_le_bytes([
+            b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7],
+        ]))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+}
@@ -0,0 +1,458 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use memmap2::Mmap;
+
+use crate::aligned::{is_stable, DocId, Item, SegmentKey};
+use crate::error::{Error, Result};
+use crate::half::{self, Encoding};
+use crate::simd;
+
+/// First bytes of every store file.
+pub const MAGIC: [u8; 4] = *b"VSTO";
+
+/// Version written by `StoreWriter`. Readers reject stores of a newer version.
+pub const VERSION: u8 = 1;
+
+/// Size of the header, vectors start right after it.
+pub const HEADER_LEN: usize = 64;
+
+/// Every vector slot starts at a multiple of this offset.
+pub const ALIGNMENT: usize = 64;
+
+/// Size of an entry of the key table.
+const KEY_LEN: usize = 24;
+
+/// Header of a vector store file. All fields are little endian:
+///
+/// | offset | size | field                                                   |
+/// |--------|------|---------------------------------------------------------|
+/// | 0      | 4    | magic `b"VSTO"`                                         |
+/// | 4      | 1    | version                                                 |
+/// | 5      | 1    | element type, ordinal of `Encoding`: f32, f16, bf16     |
+/// | 6      | 2    | reserved, zero                                          |
+/// | 8      | 4    | dimension                                               |
+/// | 12     | 4    | stride, size of a vector slot, a multiple of 64         |
+/// | 16     | 8    | number of vectors                                       |
+/// | 24     | 8    | offset of the key table                                 |
+/// | 32     | 32   | reserved, zero                                          |
+///
+/// The header is followed by `count` slots of `stride` bytes, each holding the components of one
+/// vector padded with zeros, then by the key table: `count` entries of segment key (i64), doc id
+/// (i64), slot number (u32) and magnitude (f32), sorted by segment key and doc id.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StoreHeader {
+    pub version: u8,
+    pub element: Encoding,
+    pub dim: usize,
+    pub stride: usize,
+    pub count: usize,
+    pub keys_offset: usize,
+}
+
+impl StoreHeader {
+    fn new(element: Encoding, dim: usize, count: usize) -> StoreHeader {
+        let stride = stride(element, dim);
+        StoreHeader {
+            version: VERSION,
+            element,
+            dim,
+            stride,
+            count,
+            keys_offset: HEADER_LEN + count * stride,
+        }
+    }
+
+    /// Size of the whole file the header describes.
+    pub fn file_len(&self) -> usize {
+        self.keys_offset + self.count * KEY_LEN
+    }
+
+    fn encode(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.element as u8;
+        bytes[8..12].copy_from_slice(&(self.dim as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&(self.stride as u32).to_le_bytes());
+        bytes[16..24].copy_from_slice(&(self.count as u64).to_le_bytes());
+        bytes[24..32].copy_from_slice(&(self.keys_offset as u64).to_le_bytes());
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Result<StoreHeader> {
+        if bytes.len() < HEADER_LEN || bytes[0..4] != MAGIC {
+            return Err(Error::Io("not a vector store".to_string()));
+        }
+        let version = bytes[4];
+        if version == 0 || version > VERSION {
+            return Err(Error::Io(format!(
+                "unsupported vector store version {:?}",
+                version
+            )));
+        }
+        let element = element_of(bytes[5] as i32)
+            .ok_or_else(|| Error::Io(format!("unknown vector store element {:?}", bytes[5])))?;
+        let dim = u32_at(bytes, 8) as usize;
+        let count = u64_at(bytes, 16) as usize;
+        if count > u32::MAX as usize || count.checked_mul(stride(element, dim) + KEY_LEN).is_none()
+        {
+            return Err(Error::Io(format!(
+                "vector store of {:?} vectors of dimension {:?} is corrupt",
+                count, dim
+            )));
+        }
+        let header = StoreHeader::new(element, dim, count);
+        if u32_at(bytes, 12) as usize != header.stride
+            || u64_at(bytes, 24) as usize != header.keys_offset
+        {
+            return Err(Error::Io(format!(
+                "vector store layout does not match its header {:?}",
+                header
+            )));
+        }
+        Ok(header)
+    }
+}
+
+/// Read-only vector store mapped in memory. Vectors are scored in place from the mapping,
+/// the page cache rather than the heap holds them and they survive restarts of the JVM.
+pub struct VectorStore {
+    mmap: Mmap,
+    header: StoreHeader,
+}
+
+/// Components of a stored vector, borrowed from the mapping.
+#[derive(Clone, Copy)]
+pub enum StoredValues<'a> {
+    F32(&'a [f32]),
+    F16(&'a [u16]),
+    BF16(&'a [u16]),
+}
+
+#[derive(Clone, Copy)]
+pub struct StoredVector<'a> {
+    pub values: StoredValues<'a>,
+    pub magnitude: f32,
+}
+
+impl StoredVector<'_> {
+    #[inline]
+    pub fn dot_product(&self, query: &[f32]) -> f32 {
+        match self.values {
+            StoredValues::F32(values) => simd::dot_product(query, values),
+            StoredValues::F16(bits) => half::dot_product_f16(query, bits),
+            StoredValues::BF16(bits) => half::dot_product_bf16(query, bits),
+        }
+    }
+}
+
+impl VectorStore {
+    /// Maps a store written by `StoreWriter` and validates its header and size.
+    pub fn open(path: &Path) -> Result<VectorStore> {
+        if cfg!(target_endian = "big") {
+            return Err(Error::Io(
+                "vector stores are little endian and cannot be mapped on this platform".to_string(),
+            ));
+        }
+        let file = File::open(path)
+            .map_err(|e| Error::Io(format!("cannot open vector store {:?}: {}", path, e)))?;
+        // the file is never written once finished, StoreWriter renames a new one into place
+        let mmap = unsafe { Mmap::map(&file) }
+            .map_err(|e| Error::Io(format!("cannot map vector store {:?}: {}", path, e)))?;
+        let header = StoreHeader::decode(&mmap)?;
+        if mmap.len() != header.file_len() {
+            return Err(Error::Io(format!(
+                "vector store {:?} of {:?} bytes does not match its header {:?}",
+                path,
+                mmap.len(),
+                header
+            )));
+        }
+        let store = VectorStore { mmap, header };
+        for i in 0..store.len() {
+            if store.slot(i) as usize >= store.len() || (i > 0 && store.key(i - 1) >= store.key(i))
+            {
+                return Err(Error::Io(format!(
+                    "vector store {:?} has a corrupt key table at entry {:?}",
+                    path, i
+                )));
+            }
+        }
+        Ok(store)
+    }
+
+    pub fn dim(&self) -> usize {
+        self.header.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.count
+    }
+
+    pub fn get(&self, segment: SegmentKey, doc: DocId) -> Option<StoredVector<'_>> {
+        let i = self.lower_bound((segment, doc));
+        if i < self.len() && self.key(i) == (segment, doc) {
+            Some(self.vector(i))
+        } else {
+            None
+        }
+    }
+
+    /// Calls `f` for every vector of the segment, in doc id order.
+    #[cfg(test)]
+    pub fn for_each_in_segment<F: FnMut(DocId, StoredVector<'_>)>(
+        &self,
+        segment: SegmentKey,
+        f: F,
+    ) {
+        self.for_each_in_range(self.segment_range(segment), f);
+    }
+
+    /// Positions in the key table of the vectors of the segment, a scan can split them up and
+    /// pass the parts to `for_each_in_range`.
+    pub fn segment_range(&self, segment: SegmentKey) -> Range<usize> {
+        let start = self.lower_bound((segment, DocId::MIN));
+        let end = match segment.checked_add(1) {
+            Some(next) => self.lower_bound((next, DocId::MIN)),
+            None => self.len(),
+        };
+        start..end
+    }
+
+    /// Calls `f` with the doc id and vector of every key table position in `range`.
+    pub fn for_each_in_range<F: FnMut(DocId, StoredVector<'_>)>(
+        &self,
+        range: Range<usize>,
+        mut f: F,
+    ) {
+        for i in range {
+            f(self.key(i).1, self.vector(i));
+        }
+    }
+
+    /// First entry of the key table not less than `key`.
+    fn lower_bound(&self, key: (SegmentKey, DocId)) -> usize {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.key(mid) < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        low
+    }
+
+    #[inline]
+    fn entry(&self, i: usize) -> &[u8] {
+        let offset = self.header.keys_offset + i * KEY_LEN;
+        &self.mmap[offset..offset + KEY_LEN]
+    }
+
+    #[inline]
+    fn key(&self, i: usize) -> (SegmentKey, DocId) {
+        let entry = self.entry(i);
+        (u64_at(entry, 0) as SegmentKey, u64_at(entry, 8) as DocId)
+    }
+
+    #[inline]
+    fn slot(&self, i: usize) -> u32 {
+        u32_at(self.entry(i), 16)
+    }
+
+    fn vector(&self, i: usize) -> StoredVector<'_> {
+        let entry = self.entry(i);
+        let magnitude = f32::from_le_bytes([entry[20], entry[21], entry[22], entry[23]]);
+        let offset = HEADER_LEN + self.slot(i) as usize * self.header.stride;
+        let dim = self.header.dim;
+        // slots are 64 byte aligned within a page aligned mapping and validated against its size
+        let values = unsafe {
+            let ptr = self.mmap.as_ptr().add(offset);
+            match self.header.element {
+                Encoding::F16 => {
+                    StoredValues::F16(std::slice::from_raw_parts(ptr as *const u16, dim))
+                }
+                Encoding::BF16 => {
+                    StoredValues::BF16(std::slice::from_raw_parts(ptr as *const u16, dim))
+                }
+                _ => StoredValues::F32(std::slice::from_raw_parts(ptr as *const f32, dim)),
+            }
+        };
+        StoredVector { values, magnitude }
+    }
+}
+
+/// Builds a vector store from a stream of vectors in any order. The file is written next to
+/// `path` and renamed into place by `finish`, readers never see a partial store. Vectors are
+/// added under a stable `SegmentKey`, one naming the same segment once another process reopens
+/// the store.
+pub struct StoreWriter {
+    path: PathBuf,
+    temp_path: PathBuf,
+    file: Option<BufWriter<File>>,
+    element: Encoding,
+    dim: usize,
+    keys: Vec<(SegmentKey, DocId, u32, f32)>,
+}
+
+impl StoreWriter {
+    /// `element` is the type components are stored as: `F32`, `F16` or `BF16`.
+    pub fn create(path: &Path, dim: usize, element: Encoding) -> Result<StoreWriter> {
+        if element_of(element as i32).is_none() {
+            return Err(Error::IllegalArgument(format!(
+                "{:?} is not a vector store element type",
+                element
+            )));
+        }
+        if dim == 0 {
+            return Err(Error::IllegalArgument(
+                "vector store dimension must be positive".to_string(),
+            ));
+        }
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+        let mut file = File::create(&temp_path)
+            .map(BufWriter::new)
+            .map_err(|e| Error::Io(format!("cannot create {:?}: {}", temp_path, e)))?;
+        // the header is written last, once the number of vectors is known
+        file.write_all(&[0u8; HEADER_LEN])?;
+        Ok(StoreWriter {
+            path: path.to_path_buf(),
+            temp_path,
+            file: Some(file),
+            element,
+            dim,
+            keys: Vec::new(),
+        })
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Appends the vector of a document. Its magnitude is the one of the stored components,
+    /// which for f16 and bf16 differs slightly from the magnitude of `item`. Fails for segment
+    /// keys local to the process, see `aligned::is_stable`.
+    pub fn add(&mut self, segment: SegmentKey, doc: DocId, item: &Item) -> Result<()> {
+        if !is_stable(segment) {
+            return Err(Error::IllegalArgument(format!(
+                "segment key {:?} is local to the process, a store needs stable keys",
+                segment
+            )));
+        }
+        if item.dim() != self.dim {
+            return Err(Error::IllegalArgument(format!(
+                "vector dimension {:?} does not match store dimension {:?}",
+                item.dim(),
+                self.dim
+            )));
+        }
+        if self.keys.len() >= u32::MAX as usize {
+            return Err(Error::IllegalArgument("vector store is full".to_string()));
+        }
+        let mut slot = vec![0u8; stride(self.element, self.dim)];
+        let magnitude = match self.element {
+            Encoding::F16 => narrow(item.values(), half::f32_to_f16, half::f16_to_f32, &mut slot),
+            Encoding::BF16 => narrow(
+                item.values(),
+                half::f32_to_bf16,
+                half::bf16_to_f32,
+                &mut slot,
+            ),
+            _ => {
+                for (bytes, value) in slot.chunks_exact_mut(4).zip(item.values()) {
+                    bytes.copy_from_slice(&value.to_le_bytes());
+                }
+                item.magnitude()
+            }
+        };
+        let file = self.file.as_mut().expect("writer is open until finished");
+        file.write_all(&slot)?;
+        self.keys
+            .push((segment, doc, self.keys.len() as u32, magnitude));
+        Ok(())
+    }
+
+    /// Writes the key table and the header, then moves the store to its path. Fails when a
+    /// document was added twice. Returns the number of vectors.
+    pub fn finish(mut self) -> Result<usize> {
+        self.keys
+            .sort_by_key(|(segment, doc, _, _)| (*segment, *doc));
+        if let Some(pair) = self
+            .keys
+            .windows(2)
+            .find(|pair| (pair[0].0, pair[0].1) == (pair[1].0, pair[1].1))
+        {
+            return Err(Error::IllegalArgument(format!(
+                "doc {:?} of segment {:?} was added twice",
+                pair[0].1, pair[0].0
+            )));
+        }
+        let mut file = self.file.take().expect("writer is open until finished");
+        for (segment, doc, slot, magnitude) in self.keys.iter() {
+            file.write_all(&segment.to_le_bytes())?;
+            file.write_all(&doc.to_le_bytes())?;
+            file.write_all(&slot.to_le_bytes())?;
+            file.write_all(&magnitude.to_le_bytes())?;
+        }
+        let header = StoreHeader::new(self.element, self.dim, self.keys.len());
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header.encode())?;
+        let file = file
+            .into_inner()
+            .map_err(|e| Error::Io(format!("cannot write {:?}: {}", self.temp_path, e)))?;
+        file.sync_all()?;
+        fs::rename(&self.temp_path, &self.path)?;
+        Ok(header.count)
+    }
+}
+
+impl Drop for StoreWriter {
+    /// A writer dropped before `finish` leaves nothing behind.
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+/// Element types a store can hold, `Encoding::Record` is not one of them.
+fn element_of(ordinal: i32) -> Option<Encoding> {
+    Encoding::from_ordinal(ordinal).filter(|element| element.bytes_per_component().is_some())
+}
+
+fn stride(element: Encoding, dim: usize) -> usize {
+    let bytes = dim * element.bytes_per_component().unwrap_or(0);
+    bytes.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+/// Writes the components narrowed to 16 bits little endian to `slot` and returns the magnitude
+/// of the narrowed vector.
+fn narrow(values: &[f32], to_bits: fn(f32) -> u16, to_f32: fn(u16) -> f32, slot: &mut [u8]) -> f32 {
+    let mut sum = 0f64;
+    for (bytes, value) in slot.chunks_exact_mut(2).zip(values) {
+        let bits = to_bits(*value);
+        bytes.copy_from_slice(&bits.to_le_bytes());
+        sum += (to_f32(bits) as f64).powi(2);
+    }
+    sum.sqrt() as f32
+}
+
+#[inline]
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_le_bytes(value)
+}
+
+#[inline]
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[offset..offset + 8]);
+    u64::from_le_bytes(value)
+}
@@ -1,15 +1,32 @@
-use test::Bencher;
-
 use hashers::fx_hash::FxHasher32;
 
 use rand::Rng;
-use std::any::type_name_of_val;
 use std::collections::HashMap;
 use std::hash::BuildHasherDefault;
 
-use crate::aligned::{Item, ScorerFactory};
+use crate::aligned::{DocVector, Item, Scorer, ScorerFactory, Storage, UnitNorm, NO_SEGMENT};
+use crate::bench::{black_box, Bencher};
+use crate::binary::BinaryItem;
+use crate::cache::{Cache, CacheKey, DEFAULT_SHARDS, MIN_ITEMS_PER_SHARD};
+use crate::error::{critical_call, Error};
+use crate::half::{
+    bf16_to_f32, dot_product_f16, dot_product_f16_base, dot_product_serialized, f16_to_f32,
+    f32_to_bf16, f32_to_f16, ByteOrder, Encoding, F16Item,
+};
+use crate::handle::{Registry, NO_HANDLE};
+use crate::hnsw::{HnswIndex, HnswParams};
+use crate::int8::{dot_product_i8, dot_product_i8_base, Int8Item};
+use crate::ivf::IvfIndex;
+use crate::metric::{Metric, Normalization};
+use crate::pool::ThreadPool;
+use crate::pq::ProductQuantizer;
+use crate::record;
+use crate::simd;
+use crate::store::{self, StoreWriter, StoredValues, VectorStore};
+use crate::topk::TopK;
 use crate::unaligned;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::thread;
 
 fn generate_array(size: usize) -> Vec<f32> {
     let mut vec = Vec::new();
@@ -21,7 +38,7 @@ fn generate_array(size: usize) -> Vec<f32> {
         vec.push(val);
     }
     vec.push(dot_product.sqrt() as f32);
-    return vec;
+    vec
 }
 
 #[test]
@@ -35,24 +52,59 @@ fn test_cosine_similarity_vec() {
 
 #[test]
 fn test_cosine_similarity_item() {
-    let item = Item::random();
-    // unsafe {
-    //     let x1 = item.vector.get_unchecked(0);
-    //     let target_ptr = x1 as *const f32;
-    //     let i = mem::align_of::<f32x8>();
-    //     let i1 = target_ptr.align_offset(i);
-    //     assert_eq!(i1, 0);
-    // }
+    let item = Item::random(512);
     let similarity = item.cosine_similarity(&item);
     assert_eq!((similarity * 10000f32).round(), 10000f32);
 }
 
+#[test]
+fn test_cosine_similarity_item_dims() {
+    for dim in [1, 15, 16, 100, 128, 513, 768].iter() {
+        let one = Item::random(*dim);
+        let two = Item::random(*dim);
+        assert_eq!(one.dim(), *dim);
+        assert_eq!((one.cosine_similarity(&one) * 10000f32).round(), 10000f32);
+        let expected: f32 = (0..*dim).map(|i| one.values()[i] * two.values()[i]).sum();
+        assert!((one.dot_product(&two) - expected).abs() < 1e-3);
+        assert!((one.dot_product_with_unaligned(two.values()) - expected).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn test_metrics() {
+    let one = Item::random(100);
+    let two = Item::random(100);
+    let dot_product: f32 = (0..100).map(|i| one.values()[i] * two.values()[i]).sum();
+    let distance: f32 = (0..100)
+        .map(|i| (one.values()[i] - two.values()[i]).powi(2))
+        .sum();
+
+    assert!((Metric::InnerProduct.compute(&one, &two) - dot_product).abs() < 1e-3);
+    assert!((Metric::Euclidean.compute(&one, &two) - distance).abs() < 1e-3);
+    assert!(Metric::Euclidean.compute(&one, &one).abs() < 1e-6);
+    assert!(Metric::Angular.compute(&one, &one).abs() < 1e-2);
+    assert!((Metric::Cosine.compute(&one, &two) - one.cosine_similarity(&two)).abs() < 1e-6);
+}
+
+#[test]
+fn test_lucene_normalization() {
+    let lucene = Normalization::Lucene;
+    assert_eq!(lucene.apply(Metric::Euclidean, 0f32), 1f32);
+    assert_eq!(lucene.apply(Metric::Euclidean, 3f32), 0.25f32);
+    assert_eq!(lucene.apply(Metric::InnerProduct, 1f32), 2f32);
+    assert_eq!(lucene.apply(Metric::InnerProduct, -1f32), 0.5f32);
+    assert_eq!(lucene.apply(Metric::Cosine, 1f32), 1f32);
+    assert_eq!(lucene.apply(Metric::Cosine, -1f32), 0f32);
+    assert_eq!(lucene.apply(Metric::Angular, 0f32), 1f32);
+    assert_eq!(Normalization::None.apply(Metric::Euclidean, 3f32), 3f32);
+}
+
 #[test]
 fn test_cosine_similarity_item2() {
     let len = 10000;
     let mut vec = Vec::with_capacity(len);
     for _ in 0..len {
-        let item = Item::random();
+        let item = Item::random(512);
         vec.push(item);
     }
     let size = 1000000;
@@ -71,24 +123,22 @@ fn test_cosine_similarity_item2() {
 *   test result: ok. 0 passed; 0 failed; 0 ignored; 1 measured; 2 filtered out
 * по этому написал такое:
 */
-#[bench]
 fn bench_cosine_similarity(b: &mut Bencher) {
     let len = 10000;
     let mut vec = Vec::with_capacity(len);
     for _ in 0..len {
-        vec.push(Item::random());
+        vec.push(Item::random(512));
     }
     b.iter(|| {
-        let size = test::black_box(1000000);
+        let size = black_box(1000000);
         let mut similarity: f32 = 0f32;
         for i in 0..size {
             similarity += &vec[i % len].cosine_similarity(&vec[len - 1 - (i % len)]);
         }
-        test::black_box(similarity);
+        black_box(similarity);
     });
 }
 
-#[bench]
 fn bench_cosine_similarity2(b: &mut Bencher) {
     let len = 10000;
     let mut vec = Vec::with_capacity(len);
@@ -96,67 +146,1812 @@ fn bench_cosine_similarity2(b: &mut Bencher) {
         vec.push(generate_array(512));
     }
     b.iter(|| {
-        let size = test::black_box(1000000);
+        let size = black_box(1000000);
         let mut similarity: f32 = 0f32;
         for i in 0..size {
             similarity += unaligned::cosine_similarity(&vec[i % len], &vec[len - 1 - (i % len)]);
         }
-        test::black_box(similarity);
+        black_box(similarity);
     });
 }
 
-#[bench]
+fn bench_hnsw_search(b: &mut Bencher) {
+    let items: Vec<Arc<Item>> = (0..10000).map(|_| Arc::new(Item::random(128))).collect();
+    let index = build_hnsw(Metric::Cosine, &items);
+    let query = Item::random(128);
+    b.iter(|| index.search(&query, 10, 0).unwrap());
+}
+
+fn bench_ivf_search(b: &mut Bencher) {
+    let items: Vec<Arc<Item>> = (0..10000).map(|_| Arc::new(Item::random(128))).collect();
+    let index = build_ivf(Metric::Cosine, &items, 64);
+    let query = Item::random(128);
+    b.iter(|| index.search(&query, 10, 8).unwrap());
+}
+
+fn bench_pq_score(b: &mut Bencher) {
+    let quantizer = train_pq(Metric::Cosine, 512, 64);
+    let codes: Vec<Vec<u8>> = (0..1000)
+        .map(|_| {
+            let mut code = vec![0u8; quantizer.code_size()];
+            quantizer
+                .encode(Item::random(512).values(), &mut code)
+                .unwrap();
+            code
+        })
+        .collect();
+    let query = quantizer.query(&Item::random(512)).unwrap();
+    b.iter(|| codes.iter().map(|code| query.score(code)).sum::<f32>());
+}
+
+fn bench_top_k_f32(b: &mut Bencher) {
+    bench_top_k(b, Storage::F32, 0);
+}
+
+fn bench_top_k_f32_4_threads(b: &mut Bencher) {
+    bench_top_k(b, Storage::F32, 4);
+}
+
+fn bench_top_k_int8(b: &mut Bencher) {
+    bench_top_k(b, Storage::Int8, 0);
+}
+
+fn bench_top_k_binary(b: &mut Bencher) {
+    bench_top_k(b, Storage::Binary, 0);
+}
+
+fn bench_top_k(b: &mut Bencher, storage: Storage, scan_threads: usize) {
+    let factory = ScorerFactory::with_format(
+        512,
+        1 << 30,
+        storage,
+        Encoding::F32,
+        UnitNorm::Off,
+        scan_threads,
+    );
+    for i in 0..10000 {
+        factory.cache.insert(
+            CacheKey::new(1, i),
+            Arc::new(DocVector::encode(Item::random(512), storage)),
+        );
+    }
+    let scorer = factory
+        .scorer(Item::random(512), Metric::Cosine, Normalization::None)
+        .unwrap();
+    b.iter(|| scorer.top_k(1, 10, None));
+}
+
 fn bench_scorer_factory_cache(b: &mut Bencher) {
-    let factory = ScorerFactory::new();
-    {
-        let mut guard = factory.cache.write().unwrap();
-        for i in 0..100 {
-            guard.insert(i.clone(), Arc::new(Item::new()));
-        }
+    let factory = ScorerFactory::new(512, 1 << 20);
+    for i in 0..100 {
+        factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(Item::new(512).into()));
     }
 
     b.iter(|| {
-        let size = test::black_box(100000);
+        let size = black_box(100000);
         for i in 0..size {
-            let guard = factory.cache.read().unwrap();
-            if let Some(v) = guard.get(&(i % 100)) {
-                test::black_box(v.clone());
+            if let Some(v) = factory.cache.get(&CacheKey::new(1, i % 100)) {
+                black_box(v);
             }
         }
     });
 }
 
-#[bench]
+/// Every thread reads 100000 cached vectors, so with a scalable cache the time per iteration
+/// stays flat while the thread count grows.
+fn bench_cache_threads(b: &mut Bencher, cache: Cache, threads: usize) {
+    for i in 0..1000 {
+        cache.insert(CacheKey::new(1, i), Arc::new(Item::new(512).into()));
+    }
+    b.iter(|| {
+        thread::scope(|scope| {
+            for t in 0..threads {
+                let cache = &cache;
+                scope.spawn(move || {
+                    let size = black_box(100000);
+                    for i in 0..size {
+                        let key = CacheKey::new(1, ((i * 7 + t) % 1000) as i64);
+                        if let Some(v) = cache.get(&key) {
+                            black_box(v);
+                        }
+                    }
+                });
+            }
+        });
+    });
+}
+
+fn bench_scorer_factory_cache_1_thread(b: &mut Bencher) {
+    bench_cache_threads(b, Cache::new(1 << 24), 1);
+}
+
+fn bench_scorer_factory_cache_4_threads(b: &mut Bencher) {
+    bench_cache_threads(b, Cache::new(1 << 24), 4);
+}
+
+fn bench_scorer_factory_cache_8_threads(b: &mut Bencher) {
+    bench_cache_threads(b, Cache::new(1 << 24), 8);
+}
+
+/// Single shard, i.e. one global lock, for comparison with the sharded runs above.
+fn bench_scorer_factory_cache_8_threads_1_shard(b: &mut Bencher) {
+    bench_cache_threads(b, Cache::with_shards(1 << 24, 1), 8);
+}
+
 fn bench_scorer_factory_map(b: &mut Bencher) {
     let mut map =
         HashMap::with_capacity_and_hasher(1000, BuildHasherDefault::<FxHasher32>::default());
     {
         for i in 0..100 {
-            map.insert(i.clone(), Arc::new(vec![i as f32]));
+            map.insert(i, Arc::new(vec![i as f32]));
         }
     }
 
     b.iter(|| {
-        let size = test::black_box(100000);
+        let size = black_box(100000);
         for i in 0..size {
             if let Some(v) = map.get(&(i % 100)) {
-                test::black_box(v.clone());
+                black_box(v.clone());
             }
         }
     });
 }
 
+benchmarks!(
+    bench_cosine_similarity,
+    bench_cosine_similarity2,
+    bench_hnsw_search,
+    bench_ivf_search,
+    bench_pq_score,
+    bench_top_k_f32,
+    bench_top_k_f32_4_threads,
+    bench_top_k_int8,
+    bench_top_k_binary,
+    bench_scorer_factory_cache,
+    bench_scorer_factory_cache_1_thread,
+    bench_scorer_factory_cache_4_threads,
+    bench_scorer_factory_cache_8_threads,
+    bench_scorer_factory_cache_8_threads_1_shard,
+    bench_scorer_factory_map,
+);
+
 #[test]
 fn test_scorer_factory_cache() {
-    let factory = ScorerFactory::new();
+    let factory = ScorerFactory::new(512, 1 << 20);
+    for i in 0..100 {
+        factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(Item::new(512).into()));
+    }
+    assert_eq!(factory.cache.len(), 100);
+    for i in 0..10 {
+        factory
+            .cache
+            .insert(CacheKey::new(2, i), Arc::new(Item::new(512).into()));
+    }
+    assert_eq!(factory.cache.len(), 110);
+    assert!(factory.invalidate(1, 42));
+    assert!(!factory.invalidate(1, 42));
+    assert!(factory.cache.get(&CacheKey::new(1, 42)).is_none());
+    assert!(factory.cache.get(&CacheKey::new(1, 43)).is_some());
+    assert!(factory.cache.get(&CacheKey::new(2, 5)).is_some());
+    assert!(factory.cache.get(&CacheKey::new(2, 42)).is_none());
+
+    assert_eq!(factory.drop_segment(2), 10);
+    assert!(factory.cache.get(&CacheKey::new(2, 5)).is_none());
+    assert!(factory.cache.get(&CacheKey::new(1, 5)).is_some());
+    assert_eq!(factory.cache.len(), 99);
+
+    factory.clear();
+    assert_eq!(factory.cache.len(), 0);
+    assert_eq!(factory.cache.used_bytes(), 0);
+}
+
+#[test]
+fn test_cache_eviction() {
+    let item_bytes = Item::new(512).size_in_bytes();
+    let cache = Cache::with_shards(item_bytes * 10, 1);
+    for i in 0..10 {
+        cache.insert(CacheKey::new(1, i), Arc::new(Item::new(512).into()));
+    }
+    // touch the even documents, the clock hand has to spare them
+    for i in (0..10).step_by(2) {
+        assert!(cache.get(&CacheKey::new(1, i)).is_some());
+    }
+    for i in 10..15 {
+        cache.insert(CacheKey::new(1, i), Arc::new(Item::new(512).into()));
+    }
+    assert_eq!(cache.len(), 10);
+    assert!(cache.used_bytes() <= cache.capacity_bytes());
+    for i in (0..10).step_by(2) {
+        assert!(
+            cache.get(&CacheKey::new(1, i)).is_some(),
+            "referenced doc {} was evicted",
+            i
+        );
+    }
+    for i in (1..10).step_by(2) {
+        assert!(
+            cache.get(&CacheKey::new(1, i)).is_none(),
+            "unreferenced doc {} survived",
+            i
+        );
+    }
+
+    cache.insert(CacheKey::new(1, 3), Arc::new(Item::new(8192).into()));
+    assert!(cache.get(&CacheKey::new(1, 3)).is_none());
+}
+
+#[test]
+fn test_top_k_heap() {
+    let mut rng = rand::thread_rng();
+    let scores: Vec<f32> = (0..1000).map(|_| rng.gen::<f32>()).collect();
+    let mut top_k = TopK::new(10);
+    for (doc, score) in scores.iter().enumerate() {
+        top_k.push(doc as i64, *score);
+    }
+    let mut expected: Vec<(i64, f32)> = scores
+        .iter()
+        .enumerate()
+        .map(|(doc, score)| (doc as i64, *score))
+        .collect();
+    expected.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    let hits: Vec<(i64, f32)> = top_k
+        .into_sorted_vec()
+        .iter()
+        .map(|hit| (hit.doc, hit.score))
+        .collect();
+    assert_eq!(hits, expected[..10].to_vec());
+
+    // ties go to the lower doc id
+    let mut top_k = TopK::new(2);
+    for doc in (0..5).rev() {
+        top_k.push(doc, 1f32);
+    }
+    let docs: Vec<i64> = top_k.into_sorted_vec().iter().map(|hit| hit.doc).collect();
+    assert_eq!(docs, vec![0, 1]);
+    assert!(TopK::new(0).is_empty());
+}
+
+#[test]
+fn test_scorer_top_k() {
+    let factory = ScorerFactory::new(512, 1 << 24);
+    let query = Item::random(512);
+    factory
+        .cache
+        .insert(CacheKey::new(1, 42), Arc::new(query.clone().into()));
+    for i in 0..100 {
+        if i != 42 {
+            factory
+                .cache
+                .insert(CacheKey::new(1, i), Arc::new(Item::random(512).into()));
+        }
+        factory
+            .cache
+            .insert(CacheKey::new(2, i), Arc::new(Item::random(512).into()));
+    }
+
+    for metric in [Metric::Euclidean, Metric::Cosine, Metric::Angular].iter() {
+        for normalization in [Normalization::None, Normalization::Lucene].iter() {
+            let scorer = factory
+                .scorer(query.clone(), *metric, *normalization)
+                .unwrap();
+            let hits = scorer.top_k(1, 5, None);
+            assert_eq!(hits.len(), 5);
+            assert_eq!(hits[0].doc, 42, "{:?} {:?}", metric, normalization);
+            for pair in hits.windows(2) {
+                if normalization.lower_is_better(*metric) {
+                    assert!(pair[0].score <= pair[1].score);
+                } else {
+                    assert!(pair[0].score >= pair[1].score);
+                }
+            }
+        }
+    }
+
+    let scorer = factory
+        .scorer(query.clone(), Metric::Cosine, Normalization::None)
+        .unwrap();
+    let hits = scorer.top_k(1, 10, Some(&[3, 42, 7, 1000]));
+    assert_eq!(hits.len(), 3);
+    assert_eq!(hits[0].doc, 42);
+    assert!(scorer.top_k(3, 10, None).is_empty());
+}
+
+fn build_hnsw(metric: Metric, items: &[Arc<Item>]) -> HnswIndex {
+    let index = HnswIndex::new(
+        items[0].dim(),
+        metric,
+        Normalization::None,
+        HnswParams::default(),
+    )
+    .unwrap();
+    for (doc, item) in items.iter().enumerate() {
+        index.add(doc as i64, item.clone()).unwrap();
+    }
+    index
+}
+
+#[test]
+fn test_hnsw_recall() {
+    let items: Vec<Arc<Item>> = (0..2000).map(|_| Arc::new(Item::random(32))).collect();
+    for metric in [Metric::Euclidean, Metric::Cosine].iter() {
+        let index = build_hnsw(*metric, &items);
+        assert_eq!(index.len(), items.len());
+        let mut found = 0;
+        for _ in 0..20 {
+            let query = Item::random(32);
+            let mut top_k = TopK::new(10);
+            for (doc, item) in items.iter().enumerate() {
+                let value = metric.compute(&query, item);
+                top_k.push(
+                    doc as i64,
+                    if metric.lower_is_closer() {
+                        -value
+                    } else {
+                        value
+                    },
+                );
+            }
+            let expected: Vec<i64> = top_k.into_sorted_vec().iter().map(|hit| hit.doc).collect();
+            let hits = index.search(&query, 10, 0).unwrap();
+            assert_eq!(hits.len(), 10);
+            found += hits
+                .iter()
+                .filter(|hit| expected.contains(&hit.doc))
+                .count();
+        }
+        assert!(found >= 180, "{:?} recall {:?} of 200", metric, found);
+    }
+}
+
+#[test]
+fn test_hnsw_exact_match() {
+    let items: Vec<Arc<Item>> = (0..500).map(|_| Arc::new(Item::random(64))).collect();
+    let index = build_hnsw(Metric::Cosine, &items);
+    for doc in (0..500).step_by(50) {
+        let hits = index.search(&items[doc], 3, 0).unwrap();
+        assert_eq!(hits[0].doc, doc as i64);
+        assert!((hits[0].score - 1f32).abs() < 1e-4);
+    }
+    assert!(index.add(7, items[7].clone()).is_err());
+    assert!(index.add(1000, Arc::new(Item::random(32))).is_err());
+    assert!(index.search(&Item::random(32), 3, 0).is_err());
+
+    let empty = HnswIndex::new(
+        8,
+        Metric::Cosine,
+        Normalization::None,
+        HnswParams::default(),
+    );
+    assert!(empty
+        .unwrap()
+        .search(&Item::random(8), 3, 0)
+        .unwrap()
+        .is_empty());
+}
+
+fn build_ivf(metric: Metric, items: &[Arc<Item>], nlist: usize) -> IvfIndex {
+    let index = IvfIndex::new(items[0].dim(), metric, Normalization::None, nlist, 1).unwrap();
+    let samples: Vec<Item> = items
+        .iter()
+        .take(nlist * 20)
+        .map(|item| (**item).clone())
+        .collect();
+    index.train(&samples, 10).unwrap();
+    for (doc, item) in items.iter().enumerate() {
+        index.add(doc as i64, item.clone()).unwrap();
+    }
+    index
+}
+
+#[test]
+fn test_ivf_search() {
+    let items: Vec<Arc<Item>> = (0..2000).map(|_| Arc::new(Item::random(32))).collect();
+    let index = build_ivf(Metric::Euclidean, &items, 16);
+    assert_eq!(index.len(), items.len());
+    let query = Item::random(32);
+    let mut top_k = TopK::new(10);
+    for (doc, item) in items.iter().enumerate() {
+        top_k.push(doc as i64, -query.squared_distance(item));
+    }
+    let expected: Vec<i64> = top_k.into_sorted_vec().iter().map(|hit| hit.doc).collect();
+
+    // probing every list is exhaustive
+    let hits = index.search(&query, 10, 16).unwrap();
+    let docs: Vec<i64> = hits.iter().map(|hit| hit.doc).collect();
+    assert_eq!(docs, expected);
+    for pair in hits.windows(2) {
+        assert!(pair[0].score <= pair[1].score);
+    }
+    assert_eq!(index.search(&query, 10, 1000).unwrap(), hits);
+
+    // a stored vector is found in the list of its own centroid
+    for doc in (0..2000).step_by(100) {
+        let hits = index.search(&items[doc], 1, 1).unwrap();
+        assert_eq!(hits[0].doc, doc as i64);
+    }
+    index.set_nprobe(4).unwrap();
+    assert_eq!(index.nprobe(), 4);
+    assert!(index.set_nprobe(0).is_err());
+    assert!(index.add(3, items[3].clone()).is_err());
+}
+
+#[test]
+fn test_ivf_untrained() {
+    let index = IvfIndex::new(8, Metric::Cosine, Normalization::None, 4, 2).unwrap();
+    assert!(!index.is_trained());
+    assert!(index.add(1, Arc::new(Item::random(8))).is_err());
+    assert!(index.search(&Item::random(8), 3, 0).unwrap().is_empty());
+    let samples: Vec<Item> = (0..3).map(|_| Item::random(8)).collect();
+    assert!(index.train(&samples, 10).is_err());
+    let samples: Vec<Item> = (0..40).map(|_| Item::random(8)).collect();
+    index.train(&samples, 10).unwrap();
+    assert!(index.is_trained());
+    assert!(index.add(1, Arc::new(Item::random(8))).is_ok());
+    assert_eq!(index.search(&Item::random(8), 3, 4).unwrap().len(), 1);
+}
+
+fn train_pq(metric: Metric, dim: usize, m: usize) -> ProductQuantizer {
+    let quantizer = ProductQuantizer::new(dim, m, metric, Normalization::None).unwrap();
+    let samples: Vec<Item> = (0..1000).map(|_| Item::random(dim)).collect();
+    quantizer.train(&samples, 10).unwrap();
+    quantizer
+}
+
+#[test]
+fn test_pq_asymmetric_distance() {
+    for metric in [
+        Metric::Euclidean,
+        Metric::InnerProduct,
+        Metric::Cosine,
+        Metric::Angular,
+    ]
+    .iter()
+    {
+        let quantizer = train_pq(*metric, 32, 8);
+        let query = Item::random(32);
+        let pq_query = quantizer.query(&query).unwrap();
+        for _ in 0..20 {
+            let doc = Item::random(32);
+            let mut code = vec![0u8; quantizer.code_size()];
+            quantizer.encode(doc.values(), &mut code).unwrap();
+            let decoded = Item::from_slice(&quantizer.decode(&code).unwrap());
+            // the lookup tables score exactly the reconstructed vector
+            let expected = metric.compute(&query, &decoded);
+            assert!(
+                (pq_query.score(&code) - expected).abs() < 1e-3,
+                "{:?}: {:?} != {:?}",
+                metric,
+                pq_query.score(&code),
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+fn test_pq_encode() {
+    let quantizer = ProductQuantizer::new(32, 8, Metric::Euclidean, Normalization::None).unwrap();
+    assert!(!quantizer.is_trained());
+    let mut code = vec![0u8; 8];
+    assert!(quantizer
+        .encode(Item::random(32).values(), &mut code)
+        .is_err());
+    let samples: Vec<Item> = (0..100).map(|_| Item::random(32)).collect();
+    assert!(quantizer.train(&samples, 10).is_err());
+    assert!(ProductQuantizer::new(32, 5, Metric::Cosine, Normalization::None).is_err());
+
+    let quantizer = train_pq(Metric::Euclidean, 32, 8);
+    let doc = Item::random(32);
+    quantizer.encode(doc.values(), &mut code).unwrap();
+    let decoded = Item::from_slice(&quantizer.decode(&code).unwrap());
+    // reconstruction is much closer than an unrelated vector
+    assert!(doc.squared_distance(&decoded) * 4f32 < doc.squared_distance(&Item::random(32)));
+    assert!(quantizer.encode(doc.values(), &mut code[..4]).is_err());
+}
+
+#[test]
+fn test_simd_levels() {
+    let detected = simd::Level::detect();
+    assert_eq!(simd::level(), detected);
+    let levels: Vec<simd::Level> = [
+        simd::Level::Scalar,
+        simd::Level::Sse,
+        simd::Level::Avx2,
+        simd::Level::Avx512,
+    ]
+    .iter()
+    .cloned()
+    .filter(|level| *level <= detected)
+    .collect();
+    for dim in [0, 1, 3, 4, 7, 8, 15, 16, 17, 33, 100, 512].iter() {
+        let one = random_centered(*dim);
+        let another = random_centered(*dim);
+        let dot: f64 = one
+            .values()
+            .iter()
+            .zip(another.values())
+            .map(|(a, b)| *a as f64 * *b as f64)
+            .sum();
+        let distance: f64 = one
+            .values()
+            .iter()
+            .zip(another.values())
+            .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+            .sum();
+        // f32 rounding grows with the sum of the magnitudes of the terms
+        let dot_tolerance = 1e-5
+            * (1f64
+                + one
+                    .values()
+                    .iter()
+                    .zip(another.values())
+                    .map(|(a, b)| (*a as f64 * *b as f64).abs())
+                    .sum::<f64>());
+        let distance_tolerance = 1e-5 * (1f64 + distance);
+        for level in levels.iter() {
+            let (actual_dot, actual_distance) = unsafe {
+                (
+                    simd::dot_product_at(*level, one.values(), another.values()),
+                    simd::squared_distance_at(*level, one.values(), another.values()),
+                )
+            };
+            assert!(
+                (actual_dot as f64 - dot).abs() < dot_tolerance,
+                "{:?} {:?}",
+                level,
+                dim
+            );
+            assert!(
+                (actual_distance as f64 - distance).abs() < distance_tolerance,
+                "{:?} {:?}",
+                level,
+                dim
+            );
+        }
+    }
+}
+
+/// Components that are small integers keep every partial sum exact, so all kernels and the
+/// aligned and unaligned entry points must agree bit for bit whatever their summation order.
+#[test]
+fn test_simd_exact() {
+    let detected = simd::Level::detect();
+    let mut rng = rand::thread_rng();
+    for dim in [1, 7, 16, 33, 128, 513].iter() {
+        let one: Vec<f32> = (0..*dim).map(|_| rng.gen_range(-8, 8) as f32).collect();
+        let another: Vec<f32> = (0..*dim).map(|_| rng.gen_range(-8, 8) as f32).collect();
+        let dot = simd::dot_product_scalar(&one, &another);
+        let distance = simd::squared_distance_scalar(&one, &another);
+        for level in [simd::Level::Sse, simd::Level::Avx2, simd::Level::Avx512]
+            .iter()
+            .filter(|level| **level <= detected)
+        {
+            unsafe {
+                assert_eq!(simd::dot_product_at(*level, &one, &another), dot);
+                assert_eq!(simd::squared_distance_at(*level, &one, &another), distance);
+            }
+        }
+        let (item, another_item) = (Item::from_slice(&one), Item::from_slice(&another));
+        assert_eq!(item.dot_product(&another_item), dot);
+        assert_eq!(item.dot_product_with_unaligned(&another), dot);
+        assert_eq!(unaligned::dot_prod(&one, &another), dot);
+    }
+}
+
+#[test]
+fn test_int8_dot_product() {
+    let mut rng = rand::thread_rng();
+    for dim in [1, 15, 16, 17, 100, 512].iter() {
+        let one: Vec<i8> = (0..*dim).map(|_| rng.gen::<i8>()).collect();
+        let another: Vec<i8> = (0..*dim).map(|_| rng.gen::<i8>()).collect();
+        assert_eq!(
+            dot_product_i8(&one, &another),
+            dot_product_i8_base(&one, &another)
+        );
+    }
+    let extreme = vec![-128i8; 4096];
+    assert_eq!(dot_product_i8(&extreme, &extreme), 4096 * 128 * 128);
+
+    let one = Item::random(512);
+    let another = Item::random(512);
+    let quantized = Int8Item::quantize(&one);
+    assert!(quantized.size_in_bytes() * 3 < one.size_in_bytes());
+    for (value, dequantized) in one.values().iter().zip(quantized.dequantize()) {
+        assert!((value - dequantized).abs() <= 0.5 / 255.0 + 1e-6);
+    }
+    let exact = one.dot_product(&another);
+    let approximate = quantized.dot_product(&Int8Item::quantize(&another));
+    assert!(
+        (exact - approximate).abs() / exact < 1e-3,
+        "{:?} {:?}",
+        exact,
+        approximate
+    );
+}
+
+#[test]
+fn test_int8_storage() {
+    let f32_factory = ScorerFactory::new(512, 1 << 24);
+    let int8_factory = ScorerFactory::with_storage(512, 1 << 24, Storage::Int8);
+    let query = Item::random(512);
+    for i in 0..100 {
+        let item = if i == 42 {
+            query.clone()
+        } else {
+            Item::random(512)
+        };
+        f32_factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(item.clone().into()));
+        int8_factory.cache.insert(
+            CacheKey::new(1, i),
+            Arc::new(DocVector::encode(item, Storage::Int8)),
+        );
+    }
+    assert!(int8_factory.cache.used_bytes() * 3 < f32_factory.cache.used_bytes());
+    for metric in [
+        Metric::Euclidean,
+        Metric::InnerProduct,
+        Metric::Cosine,
+        Metric::Angular,
+    ]
+    .iter()
     {
-        let mut guard = factory.cache.write().unwrap();
-        println!(
-            "type_name_of_val(&guard.hasher()) = {}",
-            type_name_of_val(&guard.hasher())
+        let exact = f32_factory
+            .scorer(query.clone(), *metric, Normalization::Lucene)
+            .unwrap()
+            .top_k(1, 100, None);
+        let approximate = int8_factory
+            .scorer(query.clone(), *metric, Normalization::Lucene)
+            .unwrap()
+            .top_k(1, 100, None);
+        assert_eq!(approximate[0].doc, 42, "{:?}", metric);
+        for hit in approximate.iter() {
+            let expected = exact.iter().find(|e| e.doc == hit.doc).unwrap();
+            assert!(
+                (hit.score - expected.score).abs() <= expected.score.abs() * 0.01 + 1e-3,
+                "{:?}: {:?} != {:?}",
+                metric,
+                hit.score,
+                expected.score
+            );
+        }
+    }
+}
+
+/// Components centred around zero, like embeddings, so that sign bits carry information.
+fn random_centered(dim: usize) -> Item {
+    let mut rng = rand::thread_rng();
+    let values: Vec<f32> = (0..dim).map(|_| rng.gen_range(-1f32, 1f32)).collect();
+    Item::from_slice(&values)
+}
+
+fn perturbed(item: &Item, noise: f32) -> Item {
+    let mut rng = rand::thread_rng();
+    let values: Vec<f32> = item
+        .values()
+        .iter()
+        .map(|value| value + rng.gen_range(-noise, noise))
+        .collect();
+    Item::from_slice(&values)
+}
+
+#[test]
+fn test_binary_quantization() {
+    let item = random_centered(512);
+    let code = BinaryItem::quantize(&item);
+    assert_eq!(
+        code.size_in_bytes(),
+        BinaryItem::quantize(&Item::new(0)).size_in_bytes() + 64
+    );
+    assert_eq!(code.hamming_distance(&code), 0);
+    assert!((code.cosine_similarity(&code) - 1f32).abs() < 1e-6);
+    let negated: Vec<f32> = item.values().iter().map(|value| -value).collect();
+    let negated = BinaryItem::quantize(&Item::from_slice(&negated));
+    assert_eq!(code.hamming_distance(&negated), 512);
+    assert!((code.cosine_similarity(&negated) + 1f32).abs() < 1e-6);
+
+    let near = BinaryItem::quantize(&perturbed(&item, 0.1));
+    let far = BinaryItem::quantize(&random_centered(512));
+    assert!(code.hamming_distance(&near) < code.hamming_distance(&far));
+    assert!(code.cosine_similarity(&near) > 0.9);
+    assert!(code.cosine_similarity(&far).abs() < 0.3);
+}
+
+#[test]
+fn test_binary_storage() {
+    let factory = ScorerFactory::with_storage(512, 1 << 24, Storage::Binary);
+    let query = random_centered(512);
+    for i in 0..1000 {
+        let item = if i == 42 {
+            perturbed(&query, 0.2)
+        } else {
+            random_centered(512)
+        };
+        factory.cache.insert(
+            CacheKey::new(1, i),
+            Arc::new(DocVector::encode(item, Storage::Binary)),
         );
-        for i in 0..100 {
-            guard.insert(i.clone(), Arc::new(Item::new()));
+    }
+    for metric in [Metric::Euclidean, Metric::Cosine, Metric::Angular].iter() {
+        let hits = factory
+            .scorer(query.clone(), *metric, Normalization::Lucene)
+            .unwrap()
+            .top_k(1, 10, None);
+        assert_eq!(hits.len(), 10);
+        assert_eq!(hits[0].doc, 42, "{:?}", metric);
+    }
+}
+
+#[test]
+fn test_half_conversion() {
+    for (value, bits) in [
+        (0f32, 0x0000u16),
+        (-0f32, 0x8000),
+        (1f32, 0x3c00),
+        (-2f32, 0xc000),
+        (65504f32, 0x7bff),
+        (6.1035156e-5, 0x0400),
+        (5.9604645e-8, 0x0001),
+        (f32::INFINITY, 0x7c00),
+    ]
+    .iter()
+    {
+        assert_eq!(f32_to_f16(*value), *bits, "{:?}", value);
+        assert_eq!(f16_to_f32(*bits), *value, "{:?}", bits);
+    }
+    // ties round to even, overflow to infinity, underflow to zero
+    assert_eq!(f32_to_f16(1f32 + 1f32 / 2048f32), 0x3c00);
+    assert_eq!(f32_to_f16(1f32 + 3f32 / 2048f32), 0x3c02);
+    assert_eq!(f32_to_f16(65520f32), 0x7c00);
+    assert_eq!(f32_to_f16(1e-9), 0x0000);
+    assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    for bits in 0..0x7c00u16 {
+        assert_eq!(f32_to_f16(f16_to_f32(bits)), bits);
+    }
+
+    assert_eq!(f32_to_bf16(1f32), 0x3f80);
+    assert_eq!(bf16_to_f32(0xc040), -3f32);
+    assert_eq!(f32_to_bf16(f32::from_bits(0x3f80_8000)), 0x3f80);
+    assert_eq!(f32_to_bf16(f32::from_bits(0x3f81_8000)), 0x3f82);
+    assert!(bf16_to_f32(f32_to_bf16(f32::NAN)).is_nan());
+}
+
+#[test]
+fn test_half_decode() {
+    let values = [1f32, -0.5, 3.25];
+    for order in [ByteOrder::BigEndian, ByteOrder::LittleEndian].iter() {
+        for encoding in [Encoding::F32, Encoding::F16, Encoding::BF16].iter() {
+            let bytes: Vec<u8> = values
+                .iter()
+                .flat_map(|value| match (encoding, order) {
+                    (Encoding::F32, ByteOrder::BigEndian) => value.to_be_bytes().to_vec(),
+                    (Encoding::F32, ByteOrder::LittleEndian) => value.to_le_bytes().to_vec(),
+                    (Encoding::F16, ByteOrder::BigEndian) => {
+                        f32_to_f16(*value).to_be_bytes().to_vec()
+                    }
+                    (Encoding::F16, ByteOrder::LittleEndian) => {
+                        f32_to_f16(*value).to_le_bytes().to_vec()
+                    }
+                    (Encoding::BF16, ByteOrder::BigEndian) => {
+                        f32_to_bf16(*value).to_be_bytes().to_vec()
+                    }
+                    (Encoding::BF16, ByteOrder::LittleEndian) => {
+                        f32_to_bf16(*value).to_le_bytes().to_vec()
+                    }
+                    (Encoding::Record, _) => unreachable!(),
+                })
+                .collect();
+            let item = encoding.decode(&bytes, 3, *order).unwrap();
+            assert_eq!(item.values(), &values, "{:?} {:?}", encoding, order);
+            // the last component is read as the magnitude of the first two
+            let item = encoding.decode(&bytes, 2, *order).unwrap();
+            assert_eq!(item.values(), &values[..2]);
+            assert_eq!(item.magnitude(), 3.25);
+            assert!(encoding.decode(&bytes, 4, *order).is_err());
+        }
+    }
+    let big_endian = Encoding::F32
+        .decode(&1f32.to_be_bytes(), 1, ByteOrder::BigEndian)
+        .unwrap();
+    let swapped = Encoding::F32
+        .decode(&1f32.to_be_bytes(), 1, ByteOrder::LittleEndian)
+        .unwrap();
+    assert_eq!(big_endian.values(), &[1f32]);
+    assert_ne!(swapped.values(), &[1f32]);
+}
+
+#[test]
+fn test_serialized_dot_product() {
+    let query = random_centered(19);
+    let doc = random_centered(19);
+    let big_endian: Vec<u8> = doc
+        .values()
+        .iter()
+        .flat_map(|value| value.to_be_bytes().to_vec())
+        .collect();
+    // odd offset, the bytes of a Java array are not f32 aligned
+    let mut little_endian = vec![0u8];
+    little_endian.extend(
+        doc.values()
+            .iter()
+            .flat_map(|value| value.to_le_bytes().to_vec()),
+    );
+    let expected = query.dot_product(&doc);
+    for (bytes, order) in [
+        (&big_endian[..], ByteOrder::BigEndian),
+        (&little_endian[1..], ByteOrder::LittleEndian),
+    ]
+    .iter()
+    {
+        let actual = dot_product_serialized(query.values(), bytes, *order);
+        assert!((actual - expected).abs() < 1e-5, "{:?}", order);
+    }
+    assert_ne!(
+        dot_product_serialized(&[1f32], &1f32.to_be_bytes(), ByteOrder::LittleEndian),
+        1f32
+    );
+}
+
+#[test]
+fn test_vector_record() {
+    let item = random_centered(33);
+    for element in [Encoding::F32, Encoding::F16, Encoding::BF16].iter() {
+        let bytes = record::encode(&item, *element).unwrap();
+        let (header, decoded) = record::decode(&bytes).unwrap();
+        assert_eq!(bytes.len(), header.record_len());
+        assert_eq!(header.version, record::VERSION);
+        assert_eq!(header.element, *element);
+        assert_eq!(header.dim, 33);
+        assert!(!header.normalized);
+        assert_eq!(decoded.magnitude(), item.magnitude());
+        let tolerance = match element {
+            Encoding::F32 => 0f32,
+            Encoding::F16 => 1e-3,
+            _ => 1e-2,
+        };
+        for (value, expected) in decoded.values().iter().zip(item.values()) {
+            assert!((value - expected).abs() <= tolerance, "{:?}", element);
+        }
+        // records decode the same through the factory encoding, whatever the byte order
+        let through_encoding = Encoding::Record
+            .decode(&bytes, 33, ByteOrder::BigEndian)
+            .unwrap();
+        assert_eq!(through_encoding.values(), decoded.values());
+        assert!(Encoding::Record
+            .decode(&bytes, 32, ByteOrder::LittleEndian)
+            .is_err());
+    }
+    assert!(record::encode(&item, Encoding::Record).is_err());
+
+    let unit: Vec<f32> = item
+        .values()
+        .iter()
+        .map(|value| value / item.magnitude())
+        .collect();
+    let bytes = record::encode(&Item::from_slice(&unit), Encoding::F32).unwrap();
+    assert!(record::decode_header(&bytes).unwrap().normalized);
+
+    let mut corrupt = bytes.clone();
+    corrupt[0] = b'X';
+    assert!(record::decode(&corrupt).is_err());
+    let mut corrupt = bytes.clone();
+    corrupt[2] = record::VERSION + 1;
+    assert!(record::decode(&corrupt).is_err());
+    let mut corrupt = bytes.clone();
+    corrupt[3] = Encoding::Record as u8;
+    assert!(record::decode(&corrupt).is_err());
+    assert!(record::decode(&bytes[..bytes.len() - 1]).is_err());
+    assert!(record::decode(&bytes[..record::HEADER_LEN - 1]).is_err());
+}
+
+#[test]
+fn test_f16_dot_product() {
+    for dim in [1, 7, 8, 9, 100, 512].iter() {
+        let query = random_centered(*dim);
+        let doc: Vec<u16> = random_centered(*dim)
+            .values()
+            .iter()
+            .map(|value| f32_to_f16(*value))
+            .collect();
+        let fast = dot_product_f16(query.values(), &doc);
+        let base = dot_product_f16_base(query.values(), &doc);
+        assert!(
+            (fast - base).abs() < 1e-4 * *dim as f32,
+            "{:?} {:?}",
+            fast,
+            base
+        );
+    }
+
+    let one = Item::random(512);
+    let another = Item::random(512);
+    let half = F16Item::quantize(&another);
+    assert!(half.size_in_bytes() * 3 < another.size_in_bytes() * 2);
+    let exact = one.dot_product(&another);
+    let approximate = half.dot_product(one.values());
+    assert!((exact - approximate).abs() / exact < 1e-3);
+}
+
+#[test]
+fn test_f16_storage() {
+    let f32_factory = ScorerFactory::new(512, 1 << 24);
+    let f16_factory = ScorerFactory::with_storage(512, 1 << 24, Storage::F16);
+    let query = random_centered(512);
+    for i in 0..100 {
+        let item = if i == 42 {
+            query.clone()
+        } else {
+            random_centered(512)
+        };
+        f32_factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(item.clone().into()));
+        f16_factory.cache.insert(
+            CacheKey::new(1, i),
+            Arc::new(DocVector::encode(item, Storage::F16)),
+        );
+    }
+    for metric in [
+        Metric::Euclidean,
+        Metric::InnerProduct,
+        Metric::Cosine,
+        Metric::Angular,
+    ]
+    .iter()
+    {
+        let exact = f32_factory
+            .scorer(query.clone(), *metric, Normalization::Lucene)
+            .unwrap()
+            .top_k(1, 100, None);
+        let approximate = f16_factory
+            .scorer(query.clone(), *metric, Normalization::Lucene)
+            .unwrap()
+            .top_k(1, 100, None);
+        assert_eq!(approximate[0].doc, 42, "{:?}", metric);
+        for hit in approximate.iter() {
+            let expected = exact.iter().find(|e| e.doc == hit.doc).unwrap();
+            assert!(
+                (hit.score - expected.score).abs() <= expected.score.abs() * 0.01 + 1e-3,
+                "{:?}: {:?} != {:?}",
+                metric,
+                hit.score,
+                expected.score
+            );
+        }
+    }
+}
+
+#[test]
+fn test_unit_norm() {
+    let mut item = Item::random(100);
+    let raw = item.clone();
+    item.normalize();
+    assert_eq!(item.magnitude(), 1f32);
+    let norm: f32 = item.values().iter().map(|value| value * value).sum();
+    assert!((norm.sqrt() - 1f32).abs() < 1e-6);
+    assert!((item.dot_product(&item) - item.cosine_similarity(&item)).abs() < 1e-6);
+
+    assert!(UnitNorm::Validate.apply(raw.clone()).is_err());
+    assert!(UnitNorm::Validate.apply(item.clone()).is_ok());
+    assert!(UnitNorm::Normalize.apply(Item::new(100)).is_err());
+    assert_eq!(
+        UnitNorm::Off.apply(raw.clone()).unwrap().magnitude(),
+        raw.magnitude()
+    );
+
+    let query = Item::random(100);
+    let unit_factory = ScorerFactory::with_format(
+        100,
+        1 << 24,
+        Storage::F32,
+        Encoding::F32,
+        UnitNorm::Normalize,
+        0,
+    );
+    let factory = ScorerFactory::new(100, 1 << 24);
+    for i in 0..50 {
+        let mut doc = Item::random(100);
+        factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(doc.clone().into()));
+        doc.normalize();
+        unit_factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(doc.into()));
+    }
+    for metric in [Metric::Cosine, Metric::Angular].iter() {
+        let exact = factory
+            .scorer(query.clone(), *metric, Normalization::None)
+            .unwrap()
+            .top_k(1, 50, None);
+        let unit = unit_factory
+            .scorer(query.clone(), *metric, Normalization::None)
+            .unwrap()
+            .top_k(1, 50, None);
+        for (hit, expected) in unit.iter().zip(exact.iter()) {
+            assert_eq!(hit.doc, expected.doc, "{:?}", metric);
+            assert!((hit.score - expected.score).abs() < 1e-4);
+        }
+    }
+
+    let validating = ScorerFactory::with_format(
+        100,
+        1 << 24,
+        Storage::F32,
+        Encoding::F32,
+        UnitNorm::Validate,
+        0,
+    );
+    assert!(validating
+        .scorer(raw, Metric::Cosine, Normalization::None)
+        .is_err());
+    assert!(validating
+        .scorer(item, Metric::Cosine, Normalization::None)
+        .is_ok());
+}
+
+#[test]
+fn test_cache_shards() {
+    let item_bytes = Item::new(128).size_in_bytes();
+    let cache = Cache::with_shards(item_bytes * 64, 6);
+    for i in 0..1000 {
+        cache.insert(CacheKey::new(i % 3, i), Arc::new(Item::new(128).into()));
+    }
+    assert!(cache.used_bytes() <= cache.capacity_bytes());
+    assert!(cache.len() > 0 && cache.len() <= 64);
+
+    let cached_in_segment_1 = (0..1000)
+        .filter(|i| cache.get(&CacheKey::new(1, *i)).is_some())
+        .count();
+    let len = cache.len();
+    assert_eq!(cache.drop_segment(1), cached_in_segment_1);
+    assert_eq!(cache.len(), len - cached_in_segment_1);
+}
+
+#[test]
+fn test_cache_small_budget() {
+    // room for fewer vectors than there are default shards, a single shard holds them all
+    for storage in [Storage::F32, Storage::Int8, Storage::Binary, Storage::F16].iter() {
+        let item_bytes = DocVector::encode(Item::new(128), *storage).size_in_bytes();
+        let factory = ScorerFactory::with_storage(128, item_bytes * 4, *storage);
+        assert_eq!(factory.cache.shard_count(), 1);
+        for i in 0..4 {
+            factory.cache.insert(
+                CacheKey::new(1, i),
+                Arc::new(DocVector::encode(random_centered(128), *storage)),
+            );
+        }
+        assert_eq!(factory.cache.len(), 4, "{:?}", storage);
+    }
+
+    let item_bytes = Item::new(128).size_in_bytes();
+    assert_eq!(
+        Cache::for_items(item_bytes * MIN_ITEMS_PER_SHARD * 4, item_bytes).shard_count(),
+        4
+    );
+    assert_eq!(
+        Cache::for_items(1 << 30, item_bytes).shard_count(),
+        DEFAULT_SHARDS
+    );
+}
+
+#[test]
+fn test_critical_call() {
+    assert_eq!(critical_call(|| Some(1f32)), 1f32);
+    assert!(critical_call(|| None).is_nan());
+    assert!(critical_call(|| {
+        let one = Item::random(16);
+        let two = Item::random(32);
+        Some(one.dot_product(&two))
+    })
+    .is_nan());
+}
+
+fn temp_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("iq_facescoring_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn test_vector_store() {
+    let path = temp_path("vector_store_f32");
+    let items: Vec<Item> = (0..50).map(|_| random_centered(100)).collect();
+    let mut writer = StoreWriter::create(&path, 100, Encoding::F32).unwrap();
+    // added out of order, the key table is sorted by finish
+    for (i, item) in items.iter().enumerate().rev() {
+        writer.add((2 - i % 2) as i64, i as i64, item).unwrap();
+    }
+    assert!(writer.add(1, 100, &random_centered(99)).is_err());
+    // keys local to the process would name another segment once the store is reopened
+    assert!(writer.add(NO_SEGMENT, 100, &random_centered(100)).is_err());
+    assert!(writer.add(-1, 100, &random_centered(100)).is_err());
+    assert_eq!(writer.finish().unwrap(), 50);
+
+    let store = VectorStore::open(&path).unwrap();
+    assert_eq!(store.dim(), 100);
+    assert_eq!(store.len(), 50);
+    for (i, item) in items.iter().enumerate() {
+        let stored = store.get((2 - i % 2) as i64, i as i64).unwrap();
+        match stored.values {
+            StoredValues::F32(values) => {
+                assert_eq!(values, item.values());
+                assert_eq!(values.as_ptr() as usize % store::ALIGNMENT, 0);
+            }
+            _ => panic!("f32 store returned another element type"),
+        }
+        assert_eq!(stored.magnitude, item.magnitude());
+    }
+    assert!(store.get(1, 0).is_none());
+    assert!(store.get(3, 0).is_none());
+    let mut docs = Vec::new();
+    store.for_each_in_segment(1, |doc, _| docs.push(doc));
+    assert_eq!(docs, (0..25).map(|i| 2 * i + 1).collect::<Vec<i64>>());
+
+    // scored in place, without calling back for the vector
+    let factory = ScorerFactory::new(100, 1 << 20);
+    let cached = ScorerFactory::new(100, 1 << 20);
+    for (i, item) in items.iter().enumerate() {
+        cached.cache.insert(
+            CacheKey::new((2 - i % 2) as i64, i as i64),
+            Arc::new(item.clone().into()),
+        );
+    }
+    assert_eq!(factory.open_store(&path).unwrap(), 50);
+    for metric in [Metric::Euclidean, Metric::InnerProduct, Metric::Cosine].iter() {
+        let scorer = factory
+            .scorer(items[7].clone(), *metric, Normalization::Lucene)
+            .unwrap();
+        let expected = cached
+            .scorer(items[7].clone(), *metric, Normalization::Lucene)
+            .unwrap();
+        let score = scorer
+            .score_bytes(1, 7, ByteOrder::LittleEndian, || {
+                panic!("stored vectors are not requested")
+            })
+            .unwrap();
+        let expected_score = expected
+            .score_bytes(1, 7, ByteOrder::LittleEndian, || unreachable!())
+            .unwrap();
+        assert!((score - expected_score).abs() < 1e-4, "{:?}", metric);
+        let hits = scorer.top_k(1, 3, None);
+        assert_eq!(hits[0].doc, 7, "{:?}", metric);
+        assert_eq!(
+            hits.iter().map(|hit| hit.doc).collect::<Vec<i64>>(),
+            expected
+                .top_k(1, 3, None)
+                .iter()
+                .map(|hit| hit.doc)
+                .collect::<Vec<i64>>()
+        );
+        assert_eq!(scorer.top_k(2, 3, Some(&[4, 6, 200])).len(), 2);
+    }
+    assert_eq!(factory.cache.len(), 0);
+    assert!(factory.close_store());
+    assert!(!factory.close_store());
+    assert!(ScorerFactory::new(64, 1 << 20).open_store(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_vector_store_reopen() {
+    let path = temp_path("vector_store_reopen");
+    let items: Vec<Item> = (0..20).map(|_| random_centered(32)).collect();
+    // stable keys, as hashed from segment ids, rather than per process counters
+    let segments = [0x1d2c_3b4a_5968_7786i64, 0x0123_4567_89ab_cdef];
+    let mut writer = StoreWriter::create(&path, 32, Encoding::F32).unwrap();
+    for (i, item) in items.iter().enumerate() {
+        writer.add(segments[i % 2], i as i64, item).unwrap();
+    }
+    assert_eq!(writer.finish().unwrap(), 20);
+
+    let query = random_centered(32);
+    let scores = |factory: &ScorerFactory| -> Vec<f32> {
+        let scorer = factory
+            .scorer(query.clone(), Metric::Cosine, Normalization::None)
+            .unwrap();
+        (0..20)
+            .map(|i| {
+                scorer
+                    .score_bytes(segments[i % 2], i as i64, ByteOrder::LittleEndian, || {
+                        panic!("stored vectors are not requested")
+                    })
+                    .unwrap()
+            })
+            .collect()
+    };
+    let first = ScorerFactory::new(32, 1 << 20);
+    assert_eq!(first.open_store(&path).unwrap(), 20);
+    let expected = scores(&first);
+    drop(first);
+
+    // a fresh factory, as after a restart, finds every vector under the same keys
+    let reopened = ScorerFactory::new(32, 1 << 20);
+    assert_eq!(reopened.open_store(&path).unwrap(), 20);
+    assert_eq!(scores(&reopened), expected);
+    let scorer = reopened
+        .scorer(query.clone(), Metric::Cosine, Normalization::None)
+        .unwrap();
+    let mut requested = false;
+    let _ = scorer.score_bytes(segments[1], 0, ByteOrder::LittleEndian, || {
+        requested = true;
+        Ok(items[0]
+            .values()
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect())
+    });
+    assert!(
+        requested,
+        "a doc stored under another segment is not served"
+    );
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_vector_store_zero_vector() {
+    let path = temp_path("vector_store_zero");
+    let mut writer = StoreWriter::create(&path, 16, Encoding::F32).unwrap();
+    // the store takes vectors as they come, a zero one included
+    writer.add(1, 0, &Item::new(16)).unwrap();
+    for doc in 1..10 {
+        writer.add(1, doc, &random_centered(16)).unwrap();
+    }
+    writer.finish().unwrap();
+
+    let factory = ScorerFactory::with_format(
+        16,
+        1 << 20,
+        Storage::F32,
+        Encoding::F32,
+        UnitNorm::Normalize,
+        0,
+    );
+    factory.open_store(&path).unwrap();
+    let mut query = random_centered(16);
+    query.normalize();
+    for metric in [Metric::Euclidean, Metric::InnerProduct, Metric::Cosine].iter() {
+        let scorer = factory
+            .scorer(query.clone(), *metric, Normalization::None)
+            .unwrap();
+        // scored as a vector orthogonal to the query
+        let expected = metric.compute_from_dot_product(0f32, 1f32, 1f32);
+        let score = scorer
+            .score_bytes(1, 0, ByteOrder::LittleEndian, || unreachable!())
+            .unwrap();
+        assert_eq!(score, expected, "{:?}", metric);
+        let hits = scorer.top_k(1, 10, None);
+        assert_eq!(hits.len(), 10);
+        assert!(hits.iter().all(|hit| !hit.score.is_nan()), "{:?}", metric);
+        assert!(hits.iter().any(|hit| hit.doc == 0));
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_vector_store_half() {
+    let items: Vec<Item> = (0..10).map(|_| random_centered(33)).collect();
+    for element in [Encoding::F16, Encoding::BF16].iter() {
+        let path = temp_path(&format!("vector_store_{:?}", element));
+        let mut writer = StoreWriter::create(&path, 33, *element).unwrap();
+        for (i, item) in items.iter().enumerate() {
+            writer.add(3, i as i64, item).unwrap();
+        }
+        writer.finish().unwrap();
+        let store = VectorStore::open(&path).unwrap();
+        for (i, item) in items.iter().enumerate() {
+            let stored = store.get(3, i as i64).unwrap();
+            let widened: Vec<f32> = match (element, stored.values) {
+                (Encoding::F16, StoredValues::F16(bits)) => {
+                    bits.iter().map(|b| f16_to_f32(*b)).collect()
+                }
+                (Encoding::BF16, StoredValues::BF16(bits)) => {
+                    bits.iter().map(|b| bf16_to_f32(*b)).collect()
+                }
+                _ => panic!("{:?} store returned another element type", element),
+            };
+            for (value, expected) in widened.iter().zip(item.values()) {
+                assert!((value - expected).abs() <= expected.abs() / 128f32 + 1e-6);
+            }
+            let dot = stored.dot_product(items[0].values());
+            assert!(
+                (dot - items[0].dot_product(item)).abs() < 0.05,
+                "{:?}",
+                element
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+    assert!(StoreWriter::create(&temp_path("vector_store_record"), 33, Encoding::Record).is_err());
+}
+
+#[test]
+fn test_handles() {
+    let registry = Registry::new();
+    let factory = registry.insert(ScorerFactory::new(8, 1 << 20), NO_HANDLE);
+    let item = registry.insert(Item::from_slice(&[1f32; 8]), NO_HANDLE);
+    assert_ne!(factory, NO_HANDLE);
+    assert_ne!(factory, item);
+    assert_eq!(registry.get::<ScorerFactory>(factory).unwrap().dim(), 8);
+    assert_eq!(registry.get::<Item>(item).unwrap().dim(), 8);
+
+    // wrong type, null and made up handles are rejected
+    match registry.get::<Item>(factory) {
+        Err(Error::IllegalArgument(message)) => {
+            assert_eq!(
+                message,
+                format!(
+                    "handle {:#x} is of type ScorerFactory, expected Item",
+                    factory
+                )
+            )
+        }
+        _ => panic!("factory handle taken for an item"),
+    }
+    assert!(matches!(
+        registry.get::<Item>(NO_HANDLE),
+        Err(Error::IllegalArgument(_))
+    ));
+    assert!(matches!(
+        registry.get::<Item>(item + (1 << 32)),
+        Err(Error::IllegalState(_))
+    ));
+    assert!(matches!(
+        registry.get::<Item>(12345),
+        Err(Error::IllegalState(_))
+    ));
+
+    // a destroyed handle stays invalid after its slot is reused
+    let removed = registry.remove::<Item>(item).unwrap();
+    assert_eq!(removed.dim(), 8);
+    assert!(matches!(
+        registry.remove::<Item>(item),
+        Err(Error::IllegalState(_))
+    ));
+    let reused = registry.insert(Item::from_slice(&[2f32; 4]), NO_HANDLE);
+    assert_eq!(reused as u32, item as u32);
+    assert_ne!(reused, item);
+    assert!(matches!(
+        registry.get::<Item>(item),
+        Err(Error::IllegalState(_))
+    ));
+    assert_eq!(registry.get::<Item>(reused).unwrap().dim(), 4);
+    assert_eq!(registry.len(), 2);
+
+    // a slot is retired rather than reused once its generation would wrap
+    let last = registry.set_generation(reused, u32::MAX);
+    assert_eq!(registry.get::<Item>(last).unwrap().dim(), 4);
+    registry.remove::<Item>(last).unwrap();
+    let fresh = registry.insert(Item::from_slice(&[3f32; 4]), NO_HANDLE);
+    assert_ne!(fresh as u32, reused as u32);
+    // neither the last handle of the slot nor the one of its first object validates
+    for stale in [last, item].iter() {
+        assert!(matches!(
+            registry.get::<Item>(*stale),
+            Err(Error::IllegalState(_))
+        ));
+    }
+    registry.remove::<Item>(fresh).unwrap();
+    assert_eq!(registry.len(), 1);
+
+    // scorers still open when their factory goes are reported in debug mode only
+    let scorer = registry
+        .get::<ScorerFactory>(factory)
+        .unwrap()
+        .scorer(
+            Item::from_slice(&[1f32; 8]),
+            Metric::Cosine,
+            Normalization::None,
+        )
+        .unwrap();
+    let scorer = registry.insert(scorer, factory);
+    assert!(registry.leaked(factory).is_empty());
+    registry.set_debug(true);
+    registry.remove::<ScorerFactory>(factory).unwrap();
+    assert_eq!(
+        registry.leaked(factory),
+        vec![(scorer, "Scorer".to_string())]
+    );
+    assert!(registry.leaked(NO_HANDLE).is_empty());
+    registry.remove::<Scorer>(scorer).unwrap();
+    assert!(registry.leaked(factory).is_empty());
+
+    let writer: Mutex<Option<Vec<f32>>> = Mutex::new(None);
+    let writer = registry.insert(writer, NO_HANDLE);
+    match registry.get::<Item>(writer) {
+        Err(Error::IllegalArgument(message)) => {
+            assert!(message.contains("Mutex<Option<Vec<f32>>>"))
+        }
+        _ => panic!("writer handle taken for an item"),
+    }
+}
+
+#[test]
+fn test_warm_segment() {
+    let dim = 24;
+    let vectors: Vec<Vec<f32>> = (0..1000)
+        .map(|_| random_centered(dim).values().to_vec())
+        .collect();
+    let mut packed = Vec::new();
+    for vector in vectors.iter() {
+        for value in vector.iter() {
+            packed.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    let factory = ScorerFactory::with_format(
+        dim,
+        1 << 24,
+        Storage::F32,
+        Encoding::F32,
+        UnitNorm::Normalize,
+        0,
+    );
+    let warmer = factory.warmer();
+    assert_eq!(
+        warmer.warm(1, &packed, ByteOrder::BigEndian, None).unwrap(),
+        1000
+    );
+    let doc_ids: Vec<i32> = (0..1000).map(|i| i * 2).collect();
+    assert_eq!(
+        warmer
+            .warm(2, &packed, ByteOrder::BigEndian, Some(&doc_ids))
+            .unwrap(),
+        1000
+    );
+    assert_eq!(factory.cache.len(), 2000);
+    for (i, vector) in vectors.iter().enumerate() {
+        let mut expected = Item::from_slice(vector);
+        expected.normalize();
+        for key in [CacheKey::new(1, i as i64), CacheKey::new(2, 2 * i as i64)].iter() {
+            match factory.cache.get(key).unwrap().as_ref() {
+                DocVector::F32(item) => {
+                    assert_eq!(item.values(), expected.values());
+                    assert_eq!(item.magnitude(), expected.magnitude());
+                }
+                _ => panic!("warmed vector is not held as f32"),
+            }
+        }
+    }
+
+    // a rejected vector fails the warm-up before anything is cached
+    factory.clear();
+    let zero = vec![0u8; dim * 4];
+    let mut with_zero = packed[..10 * dim * 4].to_vec();
+    with_zero.extend_from_slice(&zero);
+    assert!(warmer
+        .warm(1, &with_zero, ByteOrder::BigEndian, None)
+        .is_err());
+    assert_eq!(factory.cache.len(), 0);
+
+    assert!(warmer
+        .warm(1, &packed[1..], ByteOrder::BigEndian, None)
+        .is_err());
+    assert!(warmer
+        .warm(1, &packed, ByteOrder::BigEndian, Some(&doc_ids[1..]))
+        .is_err());
+    assert_eq!(warmer.warm(1, &[], ByteOrder::BigEndian, None).unwrap(), 0);
+    let records = ScorerFactory::with_format(
+        dim,
+        1 << 24,
+        Storage::F32,
+        Encoding::Record,
+        UnitNorm::Off,
+        0,
+    );
+    assert!(records
+        .warmer()
+        .warm(1, &packed, ByteOrder::BigEndian, None)
+        .is_err());
+}
+
+#[test]
+fn test_warm_segment_half() {
+    let dim = 16;
+    let vectors: Vec<Vec<f32>> = (0..300)
+        .map(|_| random_centered(dim).values().to_vec())
+        .collect();
+    for encoding in [Encoding::F16, Encoding::BF16].iter() {
+        let mut packed = Vec::new();
+        for vector in vectors.iter() {
+            for value in vector.iter() {
+                let bits = match encoding {
+                    Encoding::F16 => f32_to_f16(*value),
+                    _ => f32_to_bf16(*value),
+                };
+                packed.extend_from_slice(&bits.to_le_bytes());
+            }
+        }
+        let factory =
+            ScorerFactory::with_format(dim, 1 << 24, Storage::Int8, *encoding, UnitNorm::Off, 0);
+        assert_eq!(
+            factory
+                .warmer()
+                .warm(7, &packed, ByteOrder::LittleEndian, None)
+                .unwrap(),
+            300
+        );
+        for (doc, bytes) in packed.chunks(dim * 2).enumerate() {
+            let item = encoding
+                .decode(bytes, dim, ByteOrder::LittleEndian)
+                .unwrap();
+            match factory
+                .cache
+                .get(&CacheKey::new(7, doc as i64))
+                .unwrap()
+                .as_ref()
+            {
+                DocVector::Int8(warmed) => {
+                    assert_eq!(warmed.parts(), Int8Item::quantize(&item).parts())
+                }
+                _ => panic!("warmed vector is not held as int8"),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parallel_top_k() {
+    let path = temp_path("parallel_top_k");
+    let mut writer = StoreWriter::create(&path, 64, Encoding::F32).unwrap();
+    for doc in 0..500 {
+        writer.add(doc % 3 + 1, doc, &random_centered(64)).unwrap();
+    }
+    writer.finish().unwrap();
+    let serial = ScorerFactory::with_storage(64, 1 << 26, Storage::Int8);
+    let parallel =
+        ScorerFactory::with_format(64, 1 << 26, Storage::Int8, Encoding::F32, UnitNorm::Off, 5);
+    for factory in [&serial, &parallel].iter() {
+        factory.open_store(&path).unwrap();
+    }
+    // cached documents overlap the stored ones, those are scored from the cache
+    for doc in 300..3000 {
+        let doc_vector = Arc::new(DocVector::encode(random_centered(64), Storage::Int8));
+        serial
+            .cache
+            .insert(CacheKey::new(doc % 3 + 1, doc), doc_vector.clone());
+        parallel
+            .cache
+            .insert(CacheKey::new(doc % 3 + 1, doc), doc_vector);
+    }
+    let query = random_centered(64);
+    for metric in [Metric::Euclidean, Metric::Cosine].iter() {
+        for k in [1, 10, 2000].iter() {
+            for segment in 1..5 {
+                let expected = serial
+                    .scorer(query.clone(), *metric, Normalization::None)
+                    .unwrap()
+                    .top_k(segment, *k, None);
+                let actual = parallel
+                    .scorer(query.clone(), *metric, Normalization::None)
+                    .unwrap()
+                    .top_k(segment, *k, None);
+                assert_eq!(
+                    actual, expected,
+                    "{:?} k={:?} segment={:?}",
+                    metric, k, segment
+                );
+                if segment == 4 {
+                    assert!(actual.is_empty());
+                } else {
+                    assert_eq!(actual.len(), (*k).min(1000));
+                }
+            }
         }
     }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_parallel_top_k_concurrent_insert() {
+    let path = temp_path("parallel_top_k_concurrent");
+    let mut writer = StoreWriter::create(&path, 32, Encoding::F32).unwrap();
+    for doc in 0..2000 {
+        writer.add(1, doc, &random_centered(32)).unwrap();
+    }
+    writer.finish().unwrap();
+    let factory =
+        ScorerFactory::with_format(32, 1 << 26, Storage::F32, Encoding::F32, UnitNorm::Off, 4);
+    factory.open_store(&path).unwrap();
+    for doc in 2000..2500 {
+        factory
+            .cache
+            .insert(CacheKey::new(1, doc), Arc::new(random_centered(32).into()));
+    }
+    let scorer = factory
+        .scorer(random_centered(32), Metric::Cosine, Normalization::None)
+        .unwrap();
+    let done = std::sync::atomic::AtomicBool::new(false);
+    let rankings: Vec<Vec<i64>> = std::thread::scope(|scope| {
+        // stored documents get cached while the parts scan, as by concurrent score calls
+        scope.spawn(|| {
+            let mut doc = 0;
+            while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                factory
+                    .cache
+                    .insert(CacheKey::new(1, doc), Arc::new(random_centered(32).into()));
+                doc = (doc + 7) % 2000;
+            }
+        });
+        let rankings = (0..50)
+            .map(|_| {
+                let hits = scorer.top_k(1, 3000, None);
+                hits.iter().map(|hit| hit.doc).collect()
+            })
+            .collect();
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        rankings
+    });
+    for mut docs in rankings.into_iter() {
+        let len = docs.len();
+        docs.sort_unstable();
+        docs.dedup();
+        assert_eq!(docs.len(), len, "a document was ranked twice");
+        assert_eq!(docs, (0..2500).collect::<Vec<i64>>());
+    }
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_thread_pool() {
+    let pool = ThreadPool::new(3);
+    assert_eq!(pool.size(), 3);
+    let values: Vec<u64> = (0..1000).collect();
+    // tasks borrow from the caller and results come back in task order
+    let sums = pool.map(10, |task| {
+        values[task * 100..(task + 1) * 100].iter().sum::<u64>()
+    });
+    assert_eq!(sums.len(), 10);
+    assert_eq!(sums[0], (0..100).sum::<u64>());
+    assert_eq!(sums.iter().sum::<u64>(), values.iter().sum::<u64>());
+    assert!(pool.map(0, |task| task).is_empty());
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pool.map(4, |task| {
+            if task == 2 {
+                panic!("task failed");
+            }
+            task
+        })
+    }));
+    assert!(panicked.is_err());
+    // the workers survive a panicking task
+    assert_eq!(pool.map(4, |task| task * 2), vec![0, 2, 4, 6]);
+
+    let mut top_k = TopK::new(3);
+    top_k.push(1, 0.5);
+    top_k.push(2, 0.1);
+    let mut other = TopK::new(3);
+    other.push(3, 0.7);
+    other.push(4, 0.2);
+    top_k.merge(other);
+    let docs: Vec<i64> = top_k.into_sorted_vec().iter().map(|hit| hit.doc).collect();
+    assert_eq!(docs, vec![3, 1, 4]);
+}
+
+#[test]
+fn test_vector_store_rejects() {
+    let path = temp_path("vector_store_rejects");
+    let mut writer = StoreWriter::create(&path, 8, Encoding::F32).unwrap();
+    writer.add(1, 1, &random_centered(8)).unwrap();
+    writer.add(1, 1, &random_centered(8)).unwrap();
+    assert!(writer.finish().is_err());
+    assert!(!path.exists());
+
+    // a writer dropped unfinished leaves nothing behind
+    let mut writer = StoreWriter::create(&path, 8, Encoding::F32).unwrap();
+    writer.add(1, 1, &random_centered(8)).unwrap();
+    drop(writer);
+    assert!(!path.exists());
+    assert!(VectorStore::open(&path).is_err());
+
+    let mut writer = StoreWriter::create(&path, 8, Encoding::F32).unwrap();
+    writer.add(1, 1, &random_centered(8)).unwrap();
+    writer.finish().unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+    assert!(VectorStore::open(&path).is_err());
+    let mut corrupt = bytes.clone();
+    corrupt[0] = b'X';
+    std::fs::write(&path, &corrupt).unwrap();
+    assert!(VectorStore::open(&path).is_err());
+    let mut newer = bytes;
+    newer[4] = store::VERSION + 1;
+    std::fs::write(&path, &newer).unwrap();
+    assert!(VectorStore::open(&path).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_snapshot() {
+    for storage in [Storage::F32, Storage::Int8, Storage::Binary, Storage::F16].iter() {
+        let path = temp_path(&format!("snapshot_{:?}", storage));
+        let factory = ScorerFactory::with_storage(100, 1 << 24, *storage);
+        for i in 0..50 {
+            let item = random_centered(100);
+            factory.cache.insert(
+                CacheKey::new(i % 3 + 1, i),
+                Arc::new(DocVector::encode(item, *storage)),
+            );
+        }
+        // keys local to the process would name another segment once loaded, they are left out
+        for segment in [NO_SEGMENT, -1].iter() {
+            factory.cache.insert(
+                CacheKey::new(*segment, 0),
+                Arc::new(DocVector::encode(random_centered(100), *storage)),
+            );
+        }
+        assert_eq!(factory.save_snapshot(&path).unwrap(), 50);
+
+        let restored = ScorerFactory::with_storage(100, 1 << 24, *storage);
+        assert_eq!(restored.load_snapshot(&path).unwrap(), 50);
+        assert_eq!(restored.cache.len(), 50);
+        let query = random_centered(100);
+        for metric in [Metric::Euclidean, Metric::Cosine].iter() {
+            for segment in 1..4 {
+                let expected = factory
+                    .scorer(query.clone(), *metric, Normalization::None)
+                    .unwrap()
+                    .top_k(segment, 50, None);
+                let actual = restored
+                    .scorer(query.clone(), *metric, Normalization::None)
+                    .unwrap()
+                    .top_k(segment, 50, None);
+                assert_eq!(actual.len(), expected.len());
+                for (actual, expected) in actual.iter().zip(expected.iter()) {
+                    assert_eq!(actual.doc, expected.doc, "{:?}", storage);
+                    assert_eq!(actual.score, expected.score, "{:?}", storage);
+                }
+            }
+        }
+
+        // only a factory of the same dimension, storage and unit norm can load it
+        assert!(ScorerFactory::with_storage(64, 1 << 24, *storage)
+            .load_snapshot(&path)
+            .is_err());
+        let other = if *storage == Storage::F32 {
+            Storage::F16
+        } else {
+            Storage::F32
+        };
+        assert!(ScorerFactory::with_storage(100, 1 << 24, other)
+            .load_snapshot(&path)
+            .is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
+
+#[test]
+fn test_snapshot_corrupt() {
+    let path = temp_path("snapshot_corrupt");
+    let factory = ScorerFactory::new(16, 1 << 20);
+    for i in 0..10 {
+        factory
+            .cache
+            .insert(CacheKey::new(1, i), Arc::new(Item::random(16).into()));
+    }
+    factory.save_snapshot(&path).unwrap();
+    let bytes = std::fs::read(&path).unwrap();
+
+    let restored = ScorerFactory::new(16, 1 << 20);
+    let mut flipped = bytes.clone();
+    flipped[100] ^= 1;
+    std::fs::write(&path, &flipped).unwrap();
+    assert!(restored.load_snapshot(&path).is_err());
+    std::fs::write(&path, &bytes[..bytes.len() - 10]).unwrap();
+    assert!(restored.load_snapshot(&path).is_err());
+    assert_eq!(restored.cache.len(), 0);
+
+    std::fs::write(&path, &bytes).unwrap();
+    assert_eq!(restored.load_snapshot(&path).unwrap(), 10);
+    std::fs::remove_file(&path).unwrap();
+    assert!(restored.load_snapshot(&path).is_err());
+
+    // an empty cache makes an empty snapshot
+    let empty = ScorerFactory::new(16, 1 << 20);
+    assert_eq!(empty.save_snapshot(&path).unwrap(), 0);
+    assert_eq!(empty.load_snapshot(&path).unwrap(), 0);
+    std::fs::remove_file(&path).unwrap();
 }
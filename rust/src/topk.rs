@@ -0,0 +1,98 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::aligned::DocId;
+
+/// A document and its score as returned by a top-k search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScoredDoc {
+    pub doc: DocId,
+    pub score: f32,
+}
+
+impl ScoredDoc {
+    pub fn new(doc: DocId, score: f32) -> ScoredDoc {
+        ScoredDoc { doc, score }
+    }
+}
+
+/// Orders by rank: the better document is the greater one. Higher scores win and ties go to the
+/// lower doc id, as with Lucene's `TopScoreDocCollector`.
+struct Ranked(ScoredDoc);
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Ranked) -> Ordering {
+        self.0
+            .score
+            .total_cmp(&other.0.score)
+            .then_with(|| other.0.doc.cmp(&self.0.doc))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Ranked) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Ranked) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+/// Keeps the `k` best documents offered to it in a bounded min-heap, the worst kept document
+/// sits on top and is the one replaced by a better candidate.
+pub struct TopK {
+    k: usize,
+    heap: BinaryHeap<std::cmp::Reverse<Ranked>>,
+}
+
+impl TopK {
+    pub fn new(k: usize) -> TopK {
+        TopK {
+            k,
+            heap: BinaryHeap::with_capacity(k),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    #[inline]
+    pub fn push(&mut self, doc: DocId, score: f32) {
+        if self.k == 0 {
+            return;
+        }
+        let candidate = Ranked(ScoredDoc::new(doc, score));
+        if self.heap.len() < self.k {
+            self.heap.push(std::cmp::Reverse(candidate));
+        } else if let Some(mut worst) = self.heap.peek_mut() {
+            if candidate > worst.0 {
+                *worst = std::cmp::Reverse(candidate);
+            }
+        }
+    }
+
+    /// Offers every document kept by `other`, e.g. to combine the heaps of a parallel scan.
+    pub fn merge(&mut self, other: TopK) {
+        for ranked in other.heap.into_iter() {
+            let hit = (ranked.0).0;
+            self.push(hit.doc, hit.score);
+        }
+    }
+
+    /// The kept documents, best first.
+    pub fn into_sorted_vec(self) -> Vec<ScoredDoc> {
+        // ascending order of Reverse is descending rank
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|ranked| (ranked.0).0)
+            .collect()
+    }
+}
@@ -0,0 +1,116 @@
+use std::panic;
+use std::sync::Arc;
+use std::thread;
+
+use jni::sys::jint;
+
+use crate::aligned::{DocId, DocVector, SegmentKey, Storage, UnitNorm};
+use crate::cache::{Cache, CacheKey};
+use crate::error::{Error, Result};
+use crate::half::{ByteOrder, Encoding};
+
+/// Fewest vectors worth a thread of their own, smaller warm-ups run on fewer threads.
+const MIN_VECTORS_PER_THREAD: usize = 256;
+
+/// Preloads the cache of a `ScorerFactory` with whole segments, see `ScorerFactory::warmer`.
+/// Holds what it needs of the factory rather than borrowing it, so that a warm-up running on a
+/// background thread outlives a factory destroyed meanwhile.
+#[derive(Clone)]
+pub struct Warmer {
+    pub(crate) dim: usize,
+    pub(crate) storage: Storage,
+    pub(crate) encoding: Encoding,
+    pub(crate) unit_norm: UnitNorm,
+    pub(crate) cache: Arc<Cache>,
+}
+
+impl Warmer {
+    /// Number of vectors packed in `len` bytes, checked against `doc_ids` when given. Fails
+    /// when the bytes do not hold a whole number of vectors of the factory `Encoding`.
+    pub fn count(&self, len: usize, doc_ids: Option<&[jint]>) -> Result<usize> {
+        let stride = match self.encoding.bytes_per_component() {
+            Some(width) => width * self.dim,
+            None => {
+                return Err(Error::IllegalArgument(
+                    "records cannot be packed, warm-up needs a fixed size encoding".to_string(),
+                ))
+            }
+        };
+        let count = doc_ids.map_or(len / stride, |doc_ids| doc_ids.len());
+        if len != count * stride {
+            return Err(Error::IllegalArgument(format!(
+                "{:?} bytes do not hold {:?} {:?} vectors of dimension {:?}",
+                len, count, self.encoding, self.dim
+            )));
+        }
+        Ok(count)
+    }
+
+    /// Caches the vectors packed back to back in `bytes`, each of `dim` components in the
+    /// `Encoding` of the factory, without the trailing magnitude of doc values. Vector `i` is
+    /// the one of doc `doc_ids[i]`, or of doc `i` without `doc_ids`, doc ids being local to
+    /// the segment as scorers look them up.
+    ///
+    /// Vectors are decoded, passed through the `UnitNorm` and encoded in the `Storage` of the
+    /// factory on all cores. Nothing is cached when any of them is rejected. Returns the number
+    /// of vectors cached.
+    pub fn warm(
+        &self,
+        segment: SegmentKey,
+        bytes: &[u8],
+        order: ByteOrder,
+        doc_ids: Option<&[jint]>,
+    ) -> Result<usize> {
+        let count = self.count(bytes.len(), doc_ids)?;
+        if count == 0 {
+            return Ok(0);
+        }
+        let stride = bytes.len() / count;
+        let threads = thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(count.div_ceil(MIN_VECTORS_PER_THREAD));
+        let per_thread = count.div_ceil(threads);
+        let encoded = thread::scope(|scope| {
+            let workers: Vec<_> = bytes
+                .chunks(per_thread * stride)
+                .enumerate()
+                .map(|(chunk, bytes)| {
+                    let first = chunk * per_thread;
+                    scope.spawn(move || -> Result<Vec<(CacheKey, DocVector)>> {
+                        bytes
+                            .chunks_exact(stride)
+                            .enumerate()
+                            .map(|(i, bytes)| {
+                                let i = first + i;
+                                let doc = doc_ids.map_or(i as DocId, |ids| ids[i] as DocId);
+                                let item = self.encoding.decode(bytes, self.dim, order)?;
+                                let item = self.unit_norm.apply(item).map_err(|e| {
+                                    Error::IllegalArgument(format!(
+                                        "doc {:?} of segment {:?}: {}",
+                                        doc, segment, e
+                                    ))
+                                })?;
+                                Ok((
+                                    CacheKey::new(segment, doc),
+                                    DocVector::encode(item, self.storage),
+                                ))
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|payload| panic::resume_unwind(payload))
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        for (key, doc) in encoded.into_iter().flatten() {
+            self.cache.insert(key, Arc::new(doc));
+        }
+        Ok(count)
+    }
+}
@@ -60,7 +60,7 @@ public class BenchSearchState {
                 break;
             }
             case "native": {
-                scorerFactory = new VScorerNativeFactory();
+                scorerFactory = new VScorerNativeFactory(BenchUtils.VECTOR_SIZE);
                 break;
             }
             default:
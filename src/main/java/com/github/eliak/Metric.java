@@ -0,0 +1,12 @@
+package com.github.eliak;
+
+/**
+ * Similarity metric computed by the native scorer. The ordinal is passed over JNI,
+ * keep the order in sync with {@code metric::Metric} on the native side.
+ */
+public enum Metric {
+    Euclidean,
+    InnerProduct,
+    Cosine,
+    Angular
+}
@@ -0,0 +1,11 @@
+package com.github.eliak;
+
+/**
+ * How the native scorer turns a metric value into a score. {@code Lucene} follows
+ * {@code VectorSimilarityFunction}, so scores are non-negative and higher is better.
+ * The ordinal is passed over JNI, keep the order in sync with {@code metric::Normalization}.
+ */
+public enum ScoreNormalization {
+    None,
+    Lucene
+}
@@ -0,0 +1,13 @@
+package com.github.eliak;
+
+/**
+ * Whether a native scorer factory holds unit normalized vectors only, which turns cosine into a plain dot
+ * product. {@code Validate} rejects query and document vectors whose norm is off by more than 1e-3 with
+ * {@link IllegalArgumentException}, {@code Normalize} normalizes them on ingestion. The ordinal is passed
+ * over JNI, keep the order in sync with {@code aligned::UnitNorm}.
+ */
+public enum UnitNorm {
+    Off,
+    Validate,
+    Normalize
+}
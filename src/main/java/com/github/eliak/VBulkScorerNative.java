@@ -0,0 +1,98 @@
+package com.github.eliak;
+
+import org.apache.lucene.index.BinaryDocValues;
+import org.apache.lucene.search.BulkScorer;
+import org.apache.lucene.search.DocIdSetIterator;
+import org.apache.lucene.search.LeafCollector;
+import org.apache.lucene.search.Scorable;
+import org.apache.lucene.util.Bits;
+
+import java.io.IOException;
+
+/**
+ * Collects documents in blocks of {@link #BLOCK_SIZE} and scores each block with a single
+ * {@link VScoreNative#scoreBatch} call instead of crossing JNI once per document.
+ */
+public class VBulkScorerNative extends BulkScorer implements VScoreNative.BatchCallback {
+    public static final int BLOCK_SIZE = 256;
+
+    private final VScorerNative scorer;
+    private final BinaryDocValues lookupValues;
+    private final int[] docIDs = new int[BLOCK_SIZE];
+    private final float[] scores = new float[BLOCK_SIZE];
+    private final BlockScorable scorable = new BlockScorable();
+
+    /**
+     * @param lookupValues a second iterator over the field, used to load vectors missing from the
+     *                     native cache while the scorer's own iterator is already past the block
+     */
+    public VBulkScorerNative(VScorerNative scorer, BinaryDocValues lookupValues) {
+        this.scorer = scorer;
+        this.lookupValues = lookupValues;
+    }
+
+    @Override
+    public int score(LeafCollector collector, Bits acceptDocs, int min, int max) throws IOException {
+        collector.setScorer(scorable);
+        final DocIdSetIterator iterator = scorer.iterator();
+        int doc = iterator.docID();
+        if (doc < min) {
+            doc = iterator.advance(min);
+        }
+        while (doc < max) {
+            int count = 0;
+            while (doc < max && count < BLOCK_SIZE) {
+                if (acceptDocs == null || acceptDocs.get(doc)) {
+                    docIDs[count++] = doc;
+                }
+                doc = iterator.nextDoc();
+            }
+            if (count == 0) {
+                continue;
+            }
+            scorer.scoreBatch(docIDs, count, scores, this);
+            for (int i = 0; i < count; i++) {
+                scorable.docID = docIDs[i];
+                scorable.score = scores[i];
+                collector.collect(docIDs[i]);
+            }
+        }
+        return doc;
+    }
+
+    @Override
+    public float[] binaryValue(int docID) throws IOException {
+        if (!lookupValues.advanceExact(docID)) {
+            return null;
+        }
+        return VScorerNative.toFloats(lookupValues.binaryValue());
+    }
+
+    @Override
+    public byte[] binaryBytes(int docID) throws IOException {
+        if (!lookupValues.advanceExact(docID)) {
+            return null;
+        }
+        return VScorerNative.toBytes(lookupValues.binaryValue());
+    }
+
+    @Override
+    public long cost() {
+        return scorer.iterator().cost();
+    }
+
+    private static final class BlockScorable extends Scorable {
+        int docID = -1;
+        float score;
+
+        @Override
+        public float score() {
+            return score;
+        }
+
+        @Override
+        public int docID() {
+            return docID;
+        }
+    }
+}
@@ -0,0 +1,70 @@
+package com.github.eliak;
+
+import org.apache.lucene.search.ScoreDoc;
+
+/**
+ * Native approximate nearest neighbour index, a Hierarchical Navigable Small World graph.
+ */
+public class VHnswIndex implements AutoCloseable {
+    public static final int DEFAULT_M = 16;
+    public static final int DEFAULT_EF_CONSTRUCTION = 200;
+    public static final int DEFAULT_EF_SEARCH = 64;
+
+    protected final long indexPtr;
+    private volatile boolean closed;
+
+    public VHnswIndex(int dim, Metric metric, ScoreNormalization normalization) {
+        this(dim, metric, normalization, DEFAULT_M, DEFAULT_EF_CONSTRUCTION, DEFAULT_EF_SEARCH);
+    }
+
+    /**
+     * @param m              links kept per node, twice as many on the bottom layer
+     * @param efConstruction size of the candidate list while inserting
+     * @param efSearch       size of the candidate list of {@link #search(float[], int)}
+     */
+    public VHnswIndex(int dim, Metric metric, ScoreNormalization normalization, int m, int efConstruction, int efSearch) {
+        this.indexPtr = VScoreNative.createIndex(dim, metric.ordinal(), normalization.ordinal(), m, efConstruction, efSearch);
+    }
+
+    /**
+     * Inserts the vector, a document id can be added only once.
+     */
+    public void add(int docID, float[] vector) {
+        VScoreNative.addVector(indexPtr, docID, vector);
+    }
+
+    public int size() {
+        return VScoreNative.indexSize(indexPtr);
+    }
+
+    public ScoreDoc[] search(float[] query, int k) {
+        return search(query, k, 0);
+    }
+
+    /**
+     * @param ef size of the candidate list, 0 for the efSearch of the index
+     * @return up to {@code k} documents, best first
+     */
+    public ScoreDoc[] search(float[] query, int k, int ef) {
+        final int[] docIDs = new int[k];
+        final float[] scores = new float[k];
+        final int count = VScoreNative.search(indexPtr, query, k, ef, docIDs, scores);
+        final ScoreDoc[] hits = new ScoreDoc[count];
+        for (int i = 0; i < count; i++) {
+            hits[i] = new ScoreDoc(docIDs[i], scores[i]);
+        }
+        return hits;
+    }
+
+    @Override
+    public void close() {
+        if (!closed) {
+            closed = true;
+            try {
+                VScoreNative.destroyIndex(indexPtr);
+            } catch (Throwable e) {
+                e.printStackTrace();
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+package com.github.eliak;
+
+import org.apache.lucene.search.ScoreDoc;
+
+/**
+ * Native inverted file index: k-means centroids with flat posting lists. A search scans only the
+ * lists of the {@code nprobe} centroids nearest to the query, more lists trade latency for recall.
+ */
+public class VIvfIndex implements AutoCloseable {
+    public static final int DEFAULT_TRAINING_ITERATIONS = 25;
+
+    protected final long indexPtr;
+    protected final int dim;
+    private volatile boolean closed;
+
+    /**
+     * @param nlist  number of centroids
+     * @param nprobe number of lists scanned by default
+     */
+    public VIvfIndex(int dim, Metric metric, ScoreNormalization normalization, int nlist, int nprobe) {
+        this.indexPtr = VScoreNative.createIvfIndex(dim, metric.ordinal(), normalization.ordinal(), nlist, nprobe);
+        this.dim = dim;
+    }
+
+    /**
+     * Trains the centroids, documents added before are reassigned to them.
+     * @param samples at least nlist training vectors
+     */
+    public void train(float[][] samples) {
+        train(samples, DEFAULT_TRAINING_ITERATIONS);
+    }
+
+    public void train(float[][] samples, int iterations) {
+        final float[] flat = new float[samples.length * dim];
+        for (int i = 0; i < samples.length; i++) {
+            if (samples[i].length != dim) {
+                throw new IllegalArgumentException("sample " + i + " has dimension " + samples[i].length);
+            }
+            System.arraycopy(samples[i], 0, flat, i * dim, dim);
+        }
+        VScoreNative.trainIvf(indexPtr, flat, iterations);
+    }
+
+    /**
+     * Inserts the vector, a document id can be added only once and only after training.
+     */
+    public void add(int docID, float[] vector) {
+        VScoreNative.addIvfVector(indexPtr, docID, vector);
+    }
+
+    public int size() {
+        return VScoreNative.ivfSize(indexPtr);
+    }
+
+    public void setNprobe(int nprobe) {
+        VScoreNative.setNprobe(indexPtr, nprobe);
+    }
+
+    public ScoreDoc[] search(float[] query, int k) {
+        return search(query, k, 0);
+    }
+
+    /**
+     * @param nprobe number of lists to scan, 0 for the default of the index
+     * @return up to {@code k} documents, best first
+     */
+    public ScoreDoc[] search(float[] query, int k, int nprobe) {
+        final int[] docIDs = new int[k];
+        final float[] scores = new float[k];
+        final int count = VScoreNative.searchIvf(indexPtr, query, k, nprobe, docIDs, scores);
+        final ScoreDoc[] hits = new ScoreDoc[count];
+        for (int i = 0; i < count; i++) {
+            hits[i] = new ScoreDoc(docIDs[i], scores[i]);
+        }
+        return hits;
+    }
+
+    @Override
+    public void close() {
+        if (!closed) {
+            closed = true;
+            try {
+                VScoreNative.destroyIvfIndex(indexPtr);
+            } catch (Throwable e) {
+                e.printStackTrace();
+            }
+        }
+    }
+}
@@ -0,0 +1,105 @@
+package com.github.eliak;
+
+import org.apache.lucene.util.BytesRef;
+
+/**
+ * Native product quantizer. A vector is split into {@code m} sub-vectors, each encoded by one byte
+ * addressing the nearest of 256 trained centroids, and queries score codes through precomputed
+ * asymmetric distance tables without decoding them.
+ */
+public class VProductQuantizer implements AutoCloseable {
+    public static final int DEFAULT_TRAINING_ITERATIONS = 25;
+
+    protected final long quantizerPtr;
+    protected final int dim;
+    protected final int m;
+    private volatile boolean closed;
+
+    /**
+     * @param m number of sub-quantizers and length of a code in bytes, must divide {@code dim}
+     */
+    public VProductQuantizer(int dim, int m, Metric metric, ScoreNormalization normalization) {
+        this.quantizerPtr = VScoreNative.createProductQuantizer(dim, m, metric.ordinal(), normalization.ordinal());
+        this.dim = dim;
+        this.m = m;
+    }
+
+    /**
+     * @param samples at least 256 training vectors
+     */
+    public void train(float[][] samples) {
+        train(samples, DEFAULT_TRAINING_ITERATIONS);
+    }
+
+    public void train(float[][] samples, int iterations) {
+        final float[] flat = new float[samples.length * dim];
+        for (int i = 0; i < samples.length; i++) {
+            if (samples[i].length != dim) {
+                throw new IllegalArgumentException("sample " + i + " has dimension " + samples[i].length);
+            }
+            System.arraycopy(samples[i], 0, flat, i * dim, dim);
+        }
+        VScoreNative.trainProductQuantizer(quantizerPtr, flat, iterations);
+    }
+
+    public byte[] encode(float[] vector) {
+        return VScoreNative.pqEncode(quantizerPtr, vector);
+    }
+
+    public int codeSize() {
+        return m;
+    }
+
+    /**
+     * Precomputes the distance tables of the query, the returned query must be closed.
+     */
+    public Query query(float[] vector) {
+        return new Query(VScoreNative.createPqQuery(quantizerPtr, vector), m);
+    }
+
+    @Override
+    public void close() {
+        if (!closed) {
+            closed = true;
+            try {
+                VScoreNative.destroyProductQuantizer(quantizerPtr);
+            } catch (Throwable e) {
+                e.printStackTrace();
+            }
+        }
+    }
+
+    public static class Query implements AutoCloseable {
+        protected final long queryPtr;
+        protected final int codeSize;
+        private volatile boolean closed;
+
+        Query(long queryPtr, int codeSize) {
+            this.queryPtr = queryPtr;
+            this.codeSize = codeSize;
+        }
+
+        public float score(BytesRef code) {
+            return VScoreNative.pqScore(queryPtr, code.bytes, code.offset);
+        }
+
+        /**
+         * Scores {@code count} codes stored back to back in {@code codes}.
+         */
+        public void scoreBatch(byte[] codes, int count, float[] scores) {
+            VScoreNative.pqScoreBatch(queryPtr, codes, count, scores);
+        }
+
+        @Override
+        public void close() {
+            if (!closed) {
+                closed = true;
+                try {
+                    VScoreNative.destroyPqQuery(queryPtr);
+                } catch (Throwable e) {
+                    e.printStackTrace();
+                }
+            }
+        }
+    }
+}
@@ -1,17 +1,155 @@
 package com.github.eliak;
 
 import java.io.IOException;
+import java.nio.ByteBuffer;
 
+/**
+ * Native bindings. Invalid arguments raise {@link IllegalArgumentException}, a missing document
+ * vector {@link IOException}, an exception thrown by {@link ScorerCallback} propagates as is and
+ * any other native failure raises {@link VScoreNativeException}.
+ * <p>
+ * The {@code long} pointers are opaque handles checked on every call: a handle used after it was
+ * destroyed, including a second destroy, raises {@link IllegalStateException} rather than touching
+ * freed memory.
+ */
 public class VScoreNative {
     public static native float cosineSimilarity(float[] one, float[] another);
     public static native float cosineSimilarity2(float[] one, float[] another);
     public static native float cosineSimilarityCritical(int one_len, float[] one, int another_len, float[] another);
 
-    public static native long createScorerFactory();
+    /**
+     * @param scanThreads size of a native thread pool {@link #topK} without candidates splits its scan of the
+     *                    segment over, each thread ranking its share into a heap of its own. 0 or 1 scans on the
+     *                    calling thread.
+     */
+    public static native long createScorerFactory(int dim, long cacheCapacityBytes, int storage, int encoding,
+                                                  int unitNorm, int scanThreads);
+    /**
+     * Returns the number of handles created from the factory that are still open, reported in debug mode only,
+     * see {@link #setDebugHandles}.
+     */
     public static native long destroyScorerFactory(long factoryPtr);
-    public static native long createScorer(long factoryPtr, float[] vector);
+    public static native boolean invalidate(long factoryPtr, long segmentKey, int docID);
+    public static native int dropSegment(long factoryPtr, long segmentKey);
+    public static native void clear(long factoryPtr);
+    /**
+     * Writes every vector of the factory cache, with its precomputed magnitude, to a snapshot file closed by
+     * a CRC-32 checksum. Only vectors of segments under stable keys, see
+     * {@link VScorerNativeFactory#stableSegmentKey}, are written. Returns the number of vectors written.
+     */
+    public static native int saveSnapshot(long factoryPtr, String path) throws IOException;
+    /**
+     * Warms the factory cache from a snapshot saved by a factory of the same dimension, {@link VectorStorage}
+     * and {@link UnitNorm}, also after a restart or on a node holding a copy of the index since segments are
+     * saved under stable keys. A corrupt snapshot raises an {@link IOException} and leaves the cache untouched.
+     * Returns the number of vectors read.
+     */
+    public static native int loadSnapshot(long factoryPtr, String path) throws IOException;
+    /**
+     * Preloads the factory cache with the vectors of a segment in one call, instead of one callback per miss.
+     * {@code packedVectors} is a direct buffer holding, between its position and limit, vectors of
+     * {@code dim} components back to back in the {@link VectorEncoding} of the factory and the byte order of
+     * the buffer. Vector {@code i} is the one of doc {@code docIDs[i]}, or of doc {@code i} when {@code docIDs}
+     * is null, doc ids being local to the segment like those passed to {@link #score}. Magnitudes are computed
+     * natively on all cores and nothing is cached when a vector is rejected by the {@link UnitNorm} of the
+     * factory.
+     * <p>
+     * Without a callback the call returns once the vectors are cached. With one it returns after checking the
+     * arguments and warms on a background thread, the buffer can be reused right away and the callback is
+     * told the outcome from that thread. Returns the number of vectors cached or being cached.
+     */
+    public static native int warmSegment(long factoryPtr, long segmentKey, ByteBuffer packedVectors,
+                                         int[] docIDs, WarmCallback callback);
+    /**
+     * Maps a vector store built with {@link #createVectorStoreWriter} read-only. Documents found in it are
+     * scored in place on cache misses instead of calling back, a store opened before is replaced. Scorers
+     * created earlier keep the store they started with. Returns the number of stored vectors.
+     */
+    public static native int openVectorStore(long factoryPtr, String path) throws IOException;
+    public static native boolean closeVectorStore(long factoryPtr);
+    /**
+     * Starts a vector store file of 64-byte aligned vectors addressed by segment key and doc id, with
+     * components stored as {@link VectorEncoding#F32}, {@link VectorEncoding#F16} or {@link VectorEncoding#BF16}.
+     * The store outlives the process, segment keys must be stable ones from
+     * {@link VScorerNativeFactory#stableSegmentKey} rather than keys that name another segment after a restart.
+     * Vectors can be added in any order. The file appears at {@code path} once {@link #finishVectorStore}
+     * returns, {@link #abortVectorStore} discards it.
+     */
+    public static native long createVectorStoreWriter(String path, int dim, int encoding) throws IOException;
+    public static native void addToVectorStore(long writerPtr, long segmentKey, int docID, float[] vector)
+            throws IOException;
+    /**
+     * Completes the store and releases the writer. Returns the number of vectors written.
+     */
+    public static native int finishVectorStore(long writerPtr) throws IOException;
+    public static native void abortVectorStore(long writerPtr);
+    public static native long createScorer(long factoryPtr, float[] vector, int metric, int normalization);
     public static native void destroyScorer(long scorerPtr);
-    public static native float score(long scorerPtr, int docID, ScorerCallback callback);
+    public static native float score(long scorerPtr, long segmentKey, int docID, ScorerCallback callback) throws IOException;
+    /**
+     * Scores the document from the serialized vector of its binary doc value, decoded natively with the
+     * {@link VectorEncoding} of the factory and the given byte order. The bytes are only read when the
+     * vector is not cached, no callback is made.
+     */
+    public static native float scoreBytes(long scorerPtr, long segmentKey, int docID, byte[] bytes, int offset,
+                                          int length, boolean bigEndian);
+    public static native void scoreBatch(long scorerPtr, long segmentKey, int[] docIDs, int count, float[] scores,
+                                         BatchCallback callback) throws IOException;
+    /**
+     * Writes the {@code k} best documents of the segment to {@code docIDs} and {@code scores}, best first,
+     * and returns how many were found. Only vectors resident in the factory cache or its vector store are
+     * ranked, all of them when {@code candidates} is null. With {@code rerank} greater than {@code k} and a
+     * callback, the best {@code rerank} documents are rescored on the full precision vectors the callback
+     * returns.
+     */
+    public static native int topK(long scorerPtr, long segmentKey, int k, int[] candidates, int[] docIDs, float[] scores,
+                                  int rerank, BatchCallback callback) throws IOException;
+    public static native long createIndex(int dim, int metric, int normalization, int m, int efConstruction, int efSearch);
+    public static native void destroyIndex(long indexPtr);
+    public static native void addVector(long indexPtr, int docID, float[] vector);
+    public static native int indexSize(long indexPtr);
+    /**
+     * Writes up to {@code k} approximate nearest documents to {@code docIDs} and {@code scores}, best first,
+     * and returns how many were found. {@code ef} of 0 uses the efSearch the index was created with.
+     */
+    public static native int search(long indexPtr, float[] query, int k, int ef, int[] docIDs, float[] scores);
+    public static native long createIvfIndex(int dim, int metric, int normalization, int nlist, int nprobe);
+    public static native void destroyIvfIndex(long indexPtr);
+    /**
+     * @param samples training vectors concatenated, a multiple of the dimension long
+     */
+    public static native void trainIvf(long indexPtr, float[] samples, int iterations);
+    public static native void addIvfVector(long indexPtr, int docID, float[] vector);
+    public static native int ivfSize(long indexPtr);
+    public static native void setNprobe(long indexPtr, int nprobe);
+    /**
+     * Same contract as {@link #search}, {@code nprobe} of 0 uses the default of the index.
+     */
+    public static native int searchIvf(long indexPtr, float[] query, int k, int nprobe, int[] docIDs, float[] scores);
+    public static native long createProductQuantizer(int dim, int m, int metric, int normalization);
+    public static native void destroyProductQuantizer(long quantizerPtr);
+    public static native void trainProductQuantizer(long quantizerPtr, float[] samples, int iterations);
+    public static native byte[] pqEncode(long quantizerPtr, float[] vector);
+    public static native long createPqQuery(long quantizerPtr, float[] query);
+    public static native void destroyPqQuery(long queryPtr);
+    public static native float pqScore(long queryPtr, byte[] codes, int offset);
+    public static native void pqScoreBatch(long queryPtr, byte[] codes, int count, float[] scores);
+    /**
+     * Serializes the vector as a versioned record: a 16 byte little-endian header with magic {@code VR},
+     * version, element type, normalized flag, dimension and norm, followed by the components. Store it in
+     * binary doc values read by a factory with {@link VectorEncoding#Record}.
+     */
+    public static native byte[] encodeVector(float[] vector);
+    /**
+     * Instruction set of the vector kernels selected for this CPU when the library was loaded: {@code scalar},
+     * {@code sse}, {@code avx2+fma} or {@code avx512f}.
+     */
+    public static native String cpuFeatures();
+    /**
+     * Turns on or off the report of leaked handles: once on, {@link #destroyScorerFactory} prints the scorers
+     * of the factory that were not destroyed to stderr and returns their number, it returns 0 otherwise.
+     */
+    public static native void setDebugHandles(boolean enabled);
     public static native float identity(float num);
 
     static {
@@ -20,5 +158,39 @@ public class VScoreNative {
 
     interface ScorerCallback {
         float[] binaryValue() throws IOException;
+
+        /**
+         * Serialized vector of the current document, requested instead of {@link #binaryValue()} by
+         * factories with a {@link VectorEncoding} other than {@code F32}.
+         */
+        default byte[] binaryBytes() throws IOException {
+            throw new UnsupportedOperationException("callback does not supply serialized vectors");
+        }
+    }
+
+    /**
+     * Completion of a {@link #warmSegment} running in the background, called on the native warming thread.
+     */
+    interface WarmCallback {
+        /**
+         * @param count   number of vectors cached, zero on failure
+         * @param failure why nothing was cached, or null
+         */
+        void completed(int count, Throwable failure);
+    }
+
+    /**
+     * Supplies vectors missing from the native cache during {@link #scoreBatch}, the documents
+     * are requested in the order of the batch.
+     */
+    interface BatchCallback {
+        float[] binaryValue(int docID) throws IOException;
+
+        /**
+         * Serialized vector of the document, see {@link ScorerCallback#binaryBytes()}.
+         */
+        default byte[] binaryBytes(int docID) throws IOException {
+            throw new UnsupportedOperationException("callback does not supply serialized vectors");
+        }
     }
 }
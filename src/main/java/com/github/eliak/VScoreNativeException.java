@@ -0,0 +1,11 @@
+package com.github.eliak;
+
+/**
+ * Thrown by the native library for failures that are neither a bad argument nor an I/O error,
+ * e.g. a failed JNI call or a bug on the native side.
+ */
+public class VScoreNativeException extends RuntimeException {
+    public VScoreNativeException(String message) {
+        super(message);
+    }
+}
@@ -1,8 +1,17 @@
 package com.github.eliak;
 
 import org.apache.lucene.index.BinaryDocValues;
+import org.apache.lucene.index.LeafReaderContext;
 
 @FunctionalInterface
 public interface VScorerFactory {
     VScorer create(VWeight weight, BinaryDocValues docValues, int docBase);
+
+    /**
+     * Invoked by {@link VWeight} for every segment, factories that key state by segment
+     * override this one.
+     */
+    default VScorer create(VWeight weight, LeafReaderContext context, BinaryDocValues docValues) {
+        return create(weight, docValues, context.docBase);
+    }
 }
@@ -5,36 +5,78 @@ import org.apache.lucene.util.BytesRef;
 
 import java.io.IOException;
 import java.nio.ByteBuffer;
+import java.util.Arrays;
 
 public class VScorerNative extends VScorer implements VScoreNative.ScorerCallback, AutoCloseable  {
     final float[] queryVectorWithDotProduct;
     final long scorerPtr;
+    final long segmentKey;
     boolean closed;
 
-    public VScorerNative(VScorerNative sibling, BinaryDocValues docValues, int docBase) {
+    public VScorerNative(VScorerNative sibling, BinaryDocValues docValues, int docBase, long segmentKey) {
         super((VWeight) sibling.getWeight(), docValues, docBase);
         this.queryVectorWithDotProduct = sibling.queryVectorWithDotProduct;
         this.scorerPtr = sibling.scorerPtr;
+        this.segmentKey = segmentKey;
         sibling.closed = true;
     }
 
-    public VScorerNative(VWeight weight, BinaryDocValues docValues, int docBase, long factoryPtr) {
+    public VScorerNative(VWeight weight, BinaryDocValues docValues, int docBase, long segmentKey, long factoryPtr,
+                         Metric metric, ScoreNormalization normalization) {
         super(weight, docValues, docBase);
+        this.segmentKey = segmentKey;
         this.queryVectorWithDotProduct = new float[this.queryVector.length + 1];
         System.arraycopy(this.queryVector, 0, this.queryVectorWithDotProduct, 0, this.queryVector.length);
         this.queryVectorWithDotProduct[this.queryVector.length] = (float) this.queryDotProduct;
-        this.scorerPtr = VScoreNative.createScorer(factoryPtr, this.queryVectorWithDotProduct);
+        this.scorerPtr = VScoreNative.createScorer(factoryPtr, this.queryVectorWithDotProduct,
+                metric.ordinal(), normalization.ordinal());
     }
 
     @Override
     public float score() throws IOException {
-        final int docID = docValues.docID() + docBase;
         // return VScoreNative.identity(0.123f);
-        return VScoreNative.score(scorerPtr, docID, this);
+        final BytesRef vector = docValues.binaryValue();
+        return VScoreNative.scoreBytes(scorerPtr, segmentKey, docValues.docID(), vector.bytes, vector.offset,
+                vector.length, true);
+    }
+
+    /**
+     * Scores the first {@code count} documents of {@code docIDs}, which are segment local and ascending.
+     */
+    public void scoreBatch(int[] docIDs, int count, float[] scores, VScoreNative.BatchCallback callback) throws IOException {
+        VScoreNative.scoreBatch(scorerPtr, segmentKey, docIDs, count, scores, callback);
+    }
+
+    /**
+     * Ranks the cached vectors of this segment natively, see {@link VScoreNative#topK}.
+     */
+    public int topK(int k, int[] candidates, int[] docIDs, float[] scores) throws IOException {
+        return VScoreNative.topK(scorerPtr, segmentKey, k, candidates, docIDs, scores, 0, null);
+    }
+
+    /**
+     * Like {@link #topK(int, int[], int[], float[])}, rescoring the best {@code rerank} quantized hits on
+     * the full precision vectors of the callback.
+     */
+    public int topK(int k, int[] candidates, int[] docIDs, float[] scores, int rerank,
+                    VScoreNative.BatchCallback callback) throws IOException {
+        return VScoreNative.topK(scorerPtr, segmentKey, k, candidates, docIDs, scores, rerank, callback);
     }
 
     public float[] binaryValue() throws IOException {
-        final BytesRef vector = docValues.binaryValue();
+        return toFloats(docValues.binaryValue());
+    }
+
+    @Override
+    public byte[] binaryBytes() throws IOException {
+        return toBytes(docValues.binaryValue());
+    }
+
+    static byte[] toBytes(BytesRef vector) {
+        return Arrays.copyOfRange(vector.bytes, vector.offset, vector.offset + vector.length);
+    }
+
+    static float[] toFloats(BytesRef vector) {
         final ByteBuffer byteBuffer = ByteBuffer.wrap(vector.bytes, vector.offset, vector.length);
         final float[] floats = new float[vector.length / Float.BYTES];
         for (int i = 0; i < floats.length; i++) {
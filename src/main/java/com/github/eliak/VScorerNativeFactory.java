@@ -1,14 +1,91 @@
 package com.github.eliak;
 
 import org.apache.lucene.index.BinaryDocValues;
+import org.apache.lucene.index.FilterLeafReader;
+import org.apache.lucene.index.IndexReader;
+import org.apache.lucene.index.LeafReader;
+import org.apache.lucene.index.LeafReaderContext;
+import org.apache.lucene.index.SegmentCommitInfo;
+import org.apache.lucene.index.SegmentReader;
 import org.apache.lucene.search.Scorer;
 
+import java.util.Map;
+import java.util.concurrent.ConcurrentHashMap;
+import java.util.concurrent.atomic.AtomicLong;
+
 public class VScorerNativeFactory implements AutoCloseable, VScorerFactory {
+    public static final long DEFAULT_CACHE_CAPACITY_BYTES = 1L << 30;
+
     protected final long factoryPtr;
-    private boolean closed;
+    protected final Metric metric;
+    protected final ScoreNormalization normalization;
+    /** Native keys of the live segment cores, by generation of their doc values updates. */
+    private final Map<IndexReader.CacheKey, Map<Long, Long>> segmentKeys = new ConcurrentHashMap<>();
+    private final AtomicLong nextLocalSegmentKey = new AtomicLong(1);
+    private volatile boolean closed;
+
+    public VScorerNativeFactory(int dim) {
+        this(dim, Metric.Cosine, ScoreNormalization.None);
+    }
+
+    public VScorerNativeFactory(int dim, Metric metric, ScoreNormalization normalization) {
+        this(dim, metric, normalization, DEFAULT_CACHE_CAPACITY_BYTES);
+    }
+
+    public VScorerNativeFactory(int dim, Metric metric, ScoreNormalization normalization, long cacheCapacityBytes) {
+        this(dim, metric, normalization, cacheCapacityBytes, VectorStorage.F32);
+    }
+
+    /**
+     * @param cacheCapacityBytes upper bound of native memory held by cached document vectors
+     * @param storage            representation of the cached document vectors
+     */
+    public VScorerNativeFactory(int dim, Metric metric, ScoreNormalization normalization, long cacheCapacityBytes,
+                                VectorStorage storage) {
+        this(dim, metric, normalization, cacheCapacityBytes, storage, VectorEncoding.F32);
+    }
+
+    /**
+     * @param encoding serialization of the vectors in the binary doc values
+     */
+    public VScorerNativeFactory(int dim, Metric metric, ScoreNormalization normalization, long cacheCapacityBytes,
+                                VectorStorage storage, VectorEncoding encoding) {
+        this(dim, metric, normalization, cacheCapacityBytes, storage, encoding, UnitNorm.Off);
+    }
+
+    /**
+     * @param unitNorm whether queries and documents are unit normalized
+     */
+    public VScorerNativeFactory(int dim, Metric metric, ScoreNormalization normalization, long cacheCapacityBytes,
+                                VectorStorage storage, VectorEncoding encoding, UnitNorm unitNorm) {
+        this(dim, metric, normalization, cacheCapacityBytes, storage, encoding, unitNorm, 0);
+    }
+
+    /**
+     * @param scanThreads native threads an exhaustive top-k search of a segment is split over, so that one query
+     *                    uses several cores with a single threaded searcher. 0 or 1 scans on the searching thread.
+     */
+    public VScorerNativeFactory(int dim, Metric metric, ScoreNormalization normalization, long cacheCapacityBytes,
+                                VectorStorage storage, VectorEncoding encoding, UnitNorm unitNorm, int scanThreads) {
+        this.factoryPtr = VScoreNative.createScorerFactory(dim, cacheCapacityBytes, storage.ordinal(),
+                encoding.ordinal(), unitNorm.ordinal(), scanThreads);
+        this.metric = metric;
+        this.normalization = normalization;
+    }
+
+    /**
+     * Drops the cached vector of the document, e.g. after it was updated.
+     * @param docID segment local document id
+     * @return whether the document was cached
+     */
+    public boolean invalidate(LeafReaderContext context, int docID) {
+        final Map<Long, Long> keys = segmentKeys.get(context.reader().getCoreCacheHelper().getKey());
+        final Long segmentKey = keys == null ? null : keys.get(docValuesGen(context.reader()));
+        return segmentKey != null && VScoreNative.invalidate(factoryPtr, segmentKey, docID);
+    }
 
-    public VScorerNativeFactory() {
-        this.factoryPtr = VScoreNative.createScorerFactory();
+    public void clear() {
+        VScoreNative.clear(factoryPtr);
     }
 
     @Override
@@ -25,14 +102,85 @@ public class VScorerNativeFactory implements AutoCloseable, VScorerFactory {
 
     @Override
     public VScorer create(VWeight weight, BinaryDocValues docValues, int docBase) {
+        throw new UnsupportedOperationException("native scorer needs the segment, use create(weight, context, docValues)");
+    }
+
+    @Override
+    public VScorer create(VWeight weight, LeafReaderContext context, BinaryDocValues docValues) {
+        final long segmentKey = segmentKey(context);
         for (Scorer scorer : weight.scorers) {
             if (!(scorer instanceof VScorerNative)) {
                 continue;
             }
             if (!((VScorerNative) scorer).closed) {
-                return new VScorerNative((VScorerNative) scorer, docValues, docBase);
+                return new VScorerNative((VScorerNative) scorer, docValues, context.docBase, segmentKey);
+            }
+        }
+        return new VScorerNative(weight, docValues, context.docBase, segmentKey, factoryPtr, metric, normalization);
+    }
+
+    /**
+     * Native cache key of the segment core and the generation of its doc values updates, see
+     * {@link #stableSegmentKey}. Cached vectors stay valid across reopens as long as the core is alive and its
+     * doc values are not updated, a reader seeing an update gets a fresh key. They are dropped once the core is
+     * closed.
+     */
+    protected long segmentKey(LeafReaderContext context) {
+        final LeafReader reader = context.reader();
+        final IndexReader.CacheHelper cacheHelper = reader.getCoreCacheHelper();
+        if (cacheHelper == null) {
+            throw new IllegalArgumentException("reader " + reader + " has no core cache helper");
+        }
+        final Map<Long, Long> keys = segmentKeys.computeIfAbsent(cacheHelper.getKey(), key -> {
+            cacheHelper.addClosedListener(this::dropSegment);
+            return new ConcurrentHashMap<>();
+        });
+        return keys.computeIfAbsent(docValuesGen(reader), docValuesGen -> stableSegmentKey(reader));
+    }
+
+    /**
+     * Key of the segment of the reader that is the same in every process opening the index: a positive 64-bit
+     * hash of the unique id Lucene gives the segment when writing it and of the generation of its doc values
+     * updates. Vector stores and snapshots address vectors by this key, so it must find the same segment after a
+     * restart or on a node holding a copy of the index. Readers not backed by a segment get a negative key local
+     * to this factory, never found in a store or snapshot.
+     */
+    protected long stableSegmentKey(LeafReader reader) {
+        final LeafReader unwrapped = FilterLeafReader.unwrap(reader);
+        if (!(unwrapped instanceof SegmentReader)) {
+            return -nextLocalSegmentKey.getAndIncrement();
+        }
+        final SegmentCommitInfo info = ((SegmentReader) unwrapped).getSegmentInfo();
+        // 64-bit FNV-1a
+        long hash = 0xcbf29ce484222325L;
+        for (byte b : info.info.getId()) {
+            hash = (hash ^ (b & 0xff)) * 0x100000001b3L;
+        }
+        final long docValuesGen = info.getDocValuesGen();
+        for (int shift = 0; shift < 64; shift += 8) {
+            hash = (hash ^ ((docValuesGen >>> shift) & 0xff)) * 0x100000001b3L;
+        }
+        // 0 is the native key of documents addressed without a segment
+        return Math.max(1, hash & Long.MAX_VALUE);
+    }
+
+    /**
+     * Generation of the doc values updates of the segment of the reader, -1 for readers not backed by a segment.
+     */
+    private static long docValuesGen(LeafReader reader) {
+        final LeafReader unwrapped = FilterLeafReader.unwrap(reader);
+        if (!(unwrapped instanceof SegmentReader)) {
+            return -1;
+        }
+        return ((SegmentReader) unwrapped).getSegmentInfo().getDocValuesGen();
+    }
+
+    private void dropSegment(IndexReader.CacheKey key) {
+        final Map<Long, Long> keys = segmentKeys.remove(key);
+        if (keys != null && !closed) {
+            for (long segmentKey : keys.values()) {
+                VScoreNative.dropSegment(factoryPtr, segmentKey);
             }
         }
-        return new VScorerNative(weight, docValues, docBase, factoryPtr);
     }
 }
@@ -4,6 +4,7 @@ import org.apache.lucene.index.BinaryDocValues;
 import org.apache.lucene.index.DocValues;
 import org.apache.lucene.index.LeafReaderContext;
 import org.apache.lucene.index.Term;
+import org.apache.lucene.search.BulkScorer;
 import org.apache.lucene.search.Explanation;
 import org.apache.lucene.search.Scorer;
 import org.apache.lucene.search.Weight;
@@ -52,7 +53,24 @@ public class VWeight extends Weight implements AutoCloseable {
         if (docValues == null) {
             return null;
         }
-        return query.scorerFactory.create(this, docValues, context.docBase);
+        return query.scorerFactory.create(this, context, docValues);
+    }
+
+    /**
+     * Native scorers score whole blocks of documents per JNI call, everything else goes
+     * through the default document at a time bulk scorer.
+     */
+    @Override
+    public BulkScorer bulkScorer(LeafReaderContext context) throws IOException {
+        final Scorer scorer = scorer(context);
+        if (scorer == null) {
+            return null;
+        }
+        if (scorer instanceof VScorerNative) {
+            final BinaryDocValues lookupValues = context.reader().getBinaryDocValues(((VQuery) parentQuery).field);
+            return new VBulkScorerNative((VScorerNative) scorer, lookupValues);
+        }
+        return new DefaultBulkScorer(scorer);
     }
 
     @Override
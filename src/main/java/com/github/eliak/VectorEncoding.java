@@ -0,0 +1,15 @@
+package com.github.eliak;
+
+/**
+ * How document vectors are serialized in the binary doc values. {@code F32} vectors are decoded in Java and
+ * handed over as {@code float[]}, {@code F16} (IEEE half precision) and {@code BF16} (bfloat16) vectors are
+ * handed over as the raw bytes and decoded natively, two big-endian bytes per component. {@code Record} vectors
+ * are the self-describing records written by {@link VScoreNative#encodeVector}. The ordinal is
+ * passed over JNI, keep the order in sync with {@code half::Encoding}.
+ */
+public enum VectorEncoding {
+    F32,
+    F16,
+    BF16,
+    Record
+}
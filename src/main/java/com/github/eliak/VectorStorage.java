@@ -0,0 +1,15 @@
+package com.github.eliak;
+
+/**
+ * How a native scorer factory keeps document vectors in its cache. {@code Int8} stores one byte per
+ * component, four times as many documents fit the same cache capacity at a small loss of precision.
+ * {@code Binary} keeps the sign bit of every component only and ranks by Hamming distance, use it as the
+ * first stage of {@link VScoreNative#topK} with a full precision re-rank. {@code F16} keeps half precision
+ * components, half the memory at a negligible loss of precision. The ordinal is passed over JNI, keep the order in sync with {@code aligned::Storage}.
+ */
+public enum VectorStorage {
+    F32,
+    Int8,
+    Binary,
+    F16
+}
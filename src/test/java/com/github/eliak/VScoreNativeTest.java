@@ -1,8 +1,12 @@
 package com.github.eliak;
 
+import org.apache.lucene.search.ScoreDoc;
+import org.apache.lucene.util.BytesRef;
 import org.testng.annotations.Test;
 
 import java.io.IOException;
+import java.nio.ByteBuffer;
+import java.nio.ByteOrder;
 
 import static com.github.eliak.ScoreUtils.*;
 import static org.testng.Assert.*;
@@ -29,15 +33,423 @@ public class VScoreNativeTest {
     }
 
     @Test
-    public void naive() {
+    public void naive() throws IOException {
         final float[] array = generateArray(16, true);
-        final long scorerFactoryPtr = VScoreNative.createScorerFactory();
-        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, array);
-        final float similarity1 = VScoreNative.score(scorerPtr, 0, () -> array);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, array,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        final float similarity1 = VScoreNative.score(scorerPtr, 1, 0, () -> array);
         assertEquals(Math.round(similarity1 * 10000), 10000f);
-        final float similarity2 = VScoreNative.score(scorerPtr, 0, () -> array);
+        final float similarity2 = VScoreNative.score(scorerPtr, 1, 0, () -> array);
         assertEquals(Math.round(similarity2 * 10000), 10000f);
         VScoreNative.destroyScorer(scorerPtr);
         VScoreNative.destroyScorerFactory(scorerFactoryPtr);
     }
-}
\ No newline at end of file
+
+    @Test
+    public void scoreBytes() {
+        final float[] query = generateArray(16);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            final BytesRef bigEndian = toBytesRef(query, true);
+            final ByteBuffer littleEndian = ByteBuffer.allocate(bigEndian.length + 8).order(ByteOrder.LITTLE_ENDIAN);
+            littleEndian.position(8);
+            littleEndian.asFloatBuffer().put(VScorerNative.toFloats(bigEndian));
+            assertEquals(VScoreNative.scoreBytes(scorerPtr, 1, 0, bigEndian.bytes, bigEndian.offset,
+                    bigEndian.length, true), 1f, 1e-6f);
+            assertEquals(VScoreNative.scoreBytes(scorerPtr, 1, 1, littleEndian.array(), 8,
+                    bigEndian.length, false), 1f, 1e-6f);
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test
+    public void encodedVectorRecord() {
+        final float[] query = generateArray(16);
+        final byte[] record = VScoreNative.encodeVector(query);
+        assertEquals(record.length, 16 + 16 * Float.BYTES);
+        assertEquals(record[0], 'V');
+        assertEquals(record[1], 'R');
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.Record.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            assertEquals(VScoreNative.scoreBytes(scorerPtr, 1, 0, record, 0, record.length, true), 1f, 1e-6f);
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test(expectedExceptions = IllegalArgumentException.class)
+    public void unitNormRejectsQuery() {
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Validate.ordinal(), 0);
+        try {
+            final float[] query = generateArray(16);
+            VScoreNative.createScorer(scorerFactoryPtr, query, Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        } finally {
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test
+    public void snapshot() throws IOException {
+        final java.io.File file = java.io.File.createTempFile("cache", ".vsnp");
+        final float[] query = generateArray(16);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.Record.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long restoredFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.Record.ordinal(), UnitNorm.Off.ordinal(), 0);
+        try {
+            final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                    Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+            try {
+                for (int docID = 0; docID < 10; docID++) {
+                    final byte[] record = VScoreNative.encodeVector(docID == 3 ? query : generateArray(16));
+                    VScoreNative.scoreBytes(scorerPtr, 1, docID, record, 0, record.length, true);
+                }
+            } finally {
+                VScoreNative.destroyScorer(scorerPtr);
+            }
+            assertEquals(VScoreNative.saveSnapshot(scorerFactoryPtr, file.getPath()), 10);
+            assertEquals(VScoreNative.loadSnapshot(restoredFactoryPtr, file.getPath()), 10);
+
+            final long restoredPtr = VScoreNative.createScorer(restoredFactoryPtr, query,
+                    Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+            try {
+                assertEquals(VScoreNative.score(restoredPtr, 1, 3, () -> {
+                    throw new AssertionError("restored vector requested");
+                }), 1f, 1e-5f);
+            } finally {
+                VScoreNative.destroyScorer(restoredPtr);
+            }
+        } finally {
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+            VScoreNative.destroyScorerFactory(restoredFactoryPtr);
+            file.delete();
+        }
+    }
+
+    @Test
+    public void vectorStore() throws IOException {
+        final java.io.File file = java.io.File.createTempFile("vectors", ".vsto");
+        final float[] query = generateArray(16);
+        final long writerPtr = VScoreNative.createVectorStoreWriter(file.getPath(), 16, VectorEncoding.F32.ordinal());
+        for (int docID = 0; docID < 10; docID++) {
+            VScoreNative.addToVectorStore(writerPtr, 1, docID, docID == 3 ? query : generateArray(16));
+        }
+        assertEquals(VScoreNative.finishVectorStore(writerPtr), 10);
+
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        try {
+            assertEquals(VScoreNative.openVectorStore(scorerFactoryPtr, file.getPath()), 10);
+            final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                    Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+            try {
+                // stored vectors are scored without calling back
+                assertEquals(VScoreNative.score(scorerPtr, 1, 3, () -> {
+                    throw new AssertionError("stored vector requested");
+                }), 1f, 1e-5f);
+                final int[] docIDs = new int[3];
+                final float[] scores = new float[3];
+                assertEquals(VScoreNative.topK(scorerPtr, 1, 3, null, docIDs, scores, 0, null), 3);
+                assertEquals(docIDs[0], 3);
+            } finally {
+                VScoreNative.destroyScorer(scorerPtr);
+            }
+            assertTrue(VScoreNative.closeVectorStore(scorerFactoryPtr));
+        } finally {
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+            file.delete();
+        }
+    }
+
+    @Test
+    public void warmSegment() throws Exception {
+        final float[] query = generateArray(16);
+        final ByteBuffer packed = ByteBuffer.allocateDirect(10 * 16 * 4).order(ByteOrder.LITTLE_ENDIAN);
+        for (int docID = 0; docID < 10; docID++) {
+            for (float value : docID == 3 ? query : generateArray(16)) {
+                packed.putFloat(value);
+            }
+        }
+        packed.flip();
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        try {
+            assertEquals(VScoreNative.warmSegment(scorerFactoryPtr, 1, packed, null, null), 10);
+
+            final java.util.concurrent.CompletableFuture<Integer> warmed = new java.util.concurrent.CompletableFuture<>();
+            assertEquals(VScoreNative.warmSegment(scorerFactoryPtr, 2, packed, new int[]{0, 2, 4, 6, 8, 10, 12, 14, 16, 18},
+                    (count, failure) -> {
+                        if (failure != null) {
+                            warmed.completeExceptionally(failure);
+                        } else {
+                            warmed.complete(count);
+                        }
+                    }), 10);
+            assertEquals(warmed.get().intValue(), 10);
+
+            final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                    Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+            try {
+                assertEquals(VScoreNative.score(scorerPtr, 1, 3, () -> {
+                    throw new AssertionError("warmed vector requested");
+                }), 1f, 1e-5f);
+                assertEquals(VScoreNative.score(scorerPtr, 2, 6, () -> {
+                    throw new AssertionError("warmed vector requested");
+                }), 1f, 1e-5f);
+            } finally {
+                VScoreNative.destroyScorer(scorerPtr);
+            }
+            expectThrows(IllegalArgumentException.class, () ->
+                    VScoreNative.warmSegment(scorerFactoryPtr, 1, packed, new int[]{0, 1}, null));
+        } finally {
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test
+    public void closedHandles() throws IOException {
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, generateArray(16),
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        VScoreNative.destroyScorer(scorerPtr);
+        expectThrows(IllegalStateException.class, () -> VScoreNative.destroyScorer(scorerPtr));
+        expectThrows(IllegalStateException.class, () -> VScoreNative.score(scorerPtr, 1, 0, () -> generateArray(16)));
+        expectThrows(IllegalArgumentException.class, () -> VScoreNative.destroyScorer(scorerFactoryPtr));
+
+        VScoreNative.setDebugHandles(true);
+        try {
+            VScoreNative.createScorer(scorerFactoryPtr, generateArray(16),
+                    Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+            assertEquals(VScoreNative.destroyScorerFactory(scorerFactoryPtr), 1L);
+        } finally {
+            VScoreNative.setDebugHandles(false);
+        }
+        expectThrows(IllegalStateException.class, () -> VScoreNative.clear(scorerFactoryPtr));
+    }
+
+    @Test
+    public void cpuFeatures() {
+        assertTrue(java.util.Arrays.asList("scalar", "sse", "avx2+fma", "avx512f").contains(VScoreNative.cpuFeatures()));
+    }
+
+    @Test
+    public void topK() throws IOException {
+        final float[] query = generateArray(16);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            final int[] batch = new int[32];
+            final float[][] vectors = new float[batch.length][];
+            for (int i = 0; i < batch.length; i++) {
+                batch[i] = i;
+                vectors[i] = i == 7 ? query : generateArray(16);
+            }
+            final float[] batchScores = new float[batch.length];
+            VScoreNative.scoreBatch(scorerPtr, 1, batch, batch.length, batchScores, docID -> vectors[docID]);
+
+            final int[] docIDs = new int[4];
+            final float[] scores = new float[4];
+            assertEquals(VScoreNative.topK(scorerPtr, 1, 4, null, docIDs, scores, 0, null), 4);
+            assertEquals(docIDs[0], 7);
+            for (int i = 1; i < scores.length; i++) {
+                assertTrue(scores[i - 1] >= scores[i]);
+            }
+            assertEquals(VScoreNative.topK(scorerPtr, 1, 4, new int[]{3, 5}, docIDs, scores, 0, null), 2);
+            assertEquals(VScoreNative.topK(scorerPtr, 2, 4, null, docIDs, scores, 0, null), 0);
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test
+    public void hnswSearch() {
+        try (VHnswIndex index = new VHnswIndex(16, Metric.Cosine, ScoreNormalization.None)) {
+            final float[][] vectors = new float[200][];
+            for (int i = 0; i < vectors.length; i++) {
+                vectors[i] = generateArray(16);
+                index.add(i, vectors[i]);
+            }
+            assertEquals(index.size(), vectors.length);
+            final ScoreDoc[] hits = index.search(vectors[42], 5);
+            assertEquals(hits.length, 5);
+            assertEquals(hits[0].doc, 42);
+            assertEquals(Math.round(hits[0].score * 10000), 10000f);
+        }
+    }
+
+    @Test
+    public void ivfSearch() {
+        try (VIvfIndex index = new VIvfIndex(16, Metric.Cosine, ScoreNormalization.None, 8, 2)) {
+            final float[][] vectors = new float[400][];
+            for (int i = 0; i < vectors.length; i++) {
+                vectors[i] = generateArray(16);
+            }
+            index.train(vectors);
+            for (int i = 0; i < vectors.length; i++) {
+                index.add(i, vectors[i]);
+            }
+            assertEquals(index.size(), vectors.length);
+            final ScoreDoc[] hits = index.search(vectors[42], 5, 8);
+            assertEquals(hits.length, 5);
+            assertEquals(hits[0].doc, 42);
+            index.setNprobe(1);
+            assertEquals(index.search(vectors[42], 1)[0].doc, 42);
+        }
+    }
+
+    @Test
+    public void productQuantizer() {
+        try (VProductQuantizer quantizer = new VProductQuantizer(16, 4, Metric.Cosine, ScoreNormalization.None)) {
+            final float[][] samples = new float[300][];
+            for (int i = 0; i < samples.length; i++) {
+                samples[i] = generateArray(16);
+            }
+            quantizer.train(samples, 5);
+            final byte[] codes = new byte[2 * quantizer.codeSize()];
+            System.arraycopy(quantizer.encode(samples[0]), 0, codes, 0, quantizer.codeSize());
+            System.arraycopy(quantizer.encode(samples[1]), 0, codes, quantizer.codeSize(), quantizer.codeSize());
+            try (VProductQuantizer.Query query = quantizer.query(samples[0])) {
+                final float[] scores = new float[2];
+                query.scoreBatch(codes, 2, scores);
+                assertEquals(query.score(new BytesRef(codes, 0, quantizer.codeSize())), scores[0]);
+                assertEquals(query.score(new BytesRef(codes, quantizer.codeSize(), quantizer.codeSize())), scores[1]);
+                assertTrue(scores[0] > 0.9f);
+            }
+        }
+    }
+
+    @Test
+    public void int8StorageRerank() throws IOException {
+        final float[] query = generateArray(16);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.Int8.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            final int[] batch = new int[32];
+            final float[][] vectors = new float[batch.length][];
+            for (int i = 0; i < batch.length; i++) {
+                batch[i] = i;
+                vectors[i] = i == 7 ? query : generateArray(16);
+            }
+            VScoreNative.scoreBatch(scorerPtr, 1, batch, batch.length, new float[batch.length], docID -> vectors[docID]);
+
+            final int[] docIDs = new int[4];
+            final float[] scores = new float[4];
+            assertEquals(VScoreNative.topK(scorerPtr, 1, 4, null, docIDs, scores, 16, docID -> vectors[docID]), 4);
+            assertEquals(docIDs[0], 7);
+            assertEquals(scores[0], VScoreNative.cosineSimilarity(query, query), 1e-6f);
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test
+    public void binaryStorageTwoStage() throws IOException {
+        final float[] query = centered(generateArray(64));
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(64, 1 << 20, VectorStorage.Binary.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            final int[] batch = new int[256];
+            final float[][] vectors = new float[batch.length][];
+            for (int i = 0; i < batch.length; i++) {
+                batch[i] = i;
+                vectors[i] = i == 7 ? query : centered(generateArray(64));
+            }
+            VScoreNative.scoreBatch(scorerPtr, 1, batch, batch.length, new float[batch.length], docID -> vectors[docID]);
+
+            final int[] docIDs = new int[4];
+            final float[] scores = new float[4];
+            assertEquals(VScoreNative.topK(scorerPtr, 1, 4, null, docIDs, scores, 32, docID -> vectors[docID]), 4);
+            assertEquals(docIDs[0], 7);
+            assertEquals(scores[0], VScoreNative.cosineSimilarity(query, query), 1e-6f);
+            for (int i = 1; i < scores.length; i++) {
+                assertEquals(scores[i], VScoreNative.cosineSimilarity(query, vectors[docIDs[i]]), 1e-6f);
+            }
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test
+    public void bf16EncodedF16Storage() throws IOException {
+        final float[] query = generateArray(16);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F16.ordinal(), VectorEncoding.BF16.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, query,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            final int[] batch = new int[32];
+            final byte[][] vectors = new byte[batch.length][];
+            for (int i = 0; i < batch.length; i++) {
+                batch[i] = i;
+                vectors[i] = bf16(i == 7 ? query : generateArray(16));
+            }
+            final float[] scores = new float[batch.length];
+            VScoreNative.scoreBatch(scorerPtr, 1, batch, batch.length, scores, new VScoreNative.BatchCallback() {
+                @Override
+                public float[] binaryValue(int docID) {
+                    throw new AssertionError("bf16 factory requested a float vector");
+                }
+
+                @Override
+                public byte[] binaryBytes(int docID) {
+                    return vectors[docID];
+                }
+            });
+            assertEquals(scores[7], 1f, 1e-2f);
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    private static byte[] bf16(float[] vector) {
+        final ByteBuffer buffer = ByteBuffer.allocate(vector.length * 2);
+        for (float value : vector) {
+            buffer.putShort((short) (Float.floatToIntBits(value) >>> 16));
+        }
+        return buffer.array();
+    }
+
+    private static float[] centered(float[] vector) {
+        for (int i = 0; i < vector.length; i++) {
+            vector[i] = vector[i] * 2 - 1;
+        }
+        return vector;
+    }
+
+    @Test(expectedExceptions = IOException.class, expectedExceptionsMessageRegExp = "from callback")
+    public void callbackExceptionPropagates() throws IOException {
+        final float[] array = generateArray(16, true);
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        final long scorerPtr = VScoreNative.createScorer(scorerFactoryPtr, array,
+                Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        try {
+            VScoreNative.score(scorerPtr, 1, 0, () -> {
+                throw new IOException("from callback");
+            });
+        } finally {
+            VScoreNative.destroyScorer(scorerPtr);
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+
+    @Test(expectedExceptions = IllegalArgumentException.class)
+    public void wrongLengthThrows() {
+        final long scorerFactoryPtr = VScoreNative.createScorerFactory(16, 1 << 20, VectorStorage.F32.ordinal(), VectorEncoding.F32.ordinal(), UnitNorm.Off.ordinal(), 0);
+        try {
+            VScoreNative.createScorer(scorerFactoryPtr, generateArray(8),
+                    Metric.Cosine.ordinal(), ScoreNormalization.None.ordinal());
+        } finally {
+            VScoreNative.destroyScorerFactory(scorerFactoryPtr);
+        }
+    }
+}
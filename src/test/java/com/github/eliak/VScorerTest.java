@@ -3,10 +3,14 @@ package com.github.eliak;
 import org.apache.lucene.analysis.standard.StandardAnalyzer;
 import org.apache.lucene.document.BinaryDocValuesField;
 import org.apache.lucene.document.Document;
+import org.apache.lucene.document.Field;
+import org.apache.lucene.document.StringField;
 import org.apache.lucene.index.DirectoryReader;
 import org.apache.lucene.index.IndexReader;
 import org.apache.lucene.index.IndexWriter;
 import org.apache.lucene.index.IndexWriterConfig;
+import org.apache.lucene.index.LeafReaderContext;
+import org.apache.lucene.index.Term;
 import org.apache.lucene.search.IndexSearcher;
 import org.apache.lucene.search.TopDocs;
 import org.apache.lucene.store.ByteBuffersDirectory;
@@ -17,10 +21,13 @@ import org.testng.annotations.Test;
 import java.io.IOException;
 import java.util.Arrays;
 import java.util.HashMap;
+import java.util.HashSet;
+import java.util.Set;
 
 import static com.github.eliak.ScoreUtils.*;
 import static org.testng.Assert.assertEquals;
 import static org.testng.Assert.assertNotNull;
+import static org.testng.Assert.assertTrue;
 
 public class VScorerTest {
     ByteBuffersDirectory dir;
@@ -73,7 +80,7 @@ public class VScorerTest {
     public void searchNative() throws IOException {
         final IndexReader reader = DirectoryReader.open(writer);
         final IndexSearcher searcher = new IndexSearcher(reader);
-        final VScorerNativeFactory scorerFactory = new VScorerNativeFactory();
+        final VScorerNativeFactory scorerFactory = new VScorerNativeFactory(VECTOR_SIZE);
         for (float[] query_value : Arrays.asList(generateArray(), floats)) {
             final VQuery query = new VQuery(FIELD_NAME, query_value, scorerFactory);
             final TopDocs n = searcher.search(query, 100);
@@ -86,4 +93,51 @@ public class VScorerTest {
         scorerFactory.close();
     }
 
-}
\ No newline at end of file
+    @Test
+    public void stableSegmentKeys() throws IOException {
+        final IndexReader reader = DirectoryReader.open(writer);
+        // a fresh factory, as after a restart, finds the segments under the same keys
+        try (VScorerNativeFactory first = new VScorerNativeFactory(VECTOR_SIZE);
+             VScorerNativeFactory second = new VScorerNativeFactory(VECTOR_SIZE)) {
+            final Set<Long> keys = new HashSet<>();
+            for (LeafReaderContext context : reader.leaves()) {
+                final long segmentKey = first.segmentKey(context);
+                assertTrue(segmentKey > 0);
+                assertEquals(second.segmentKey(context), segmentKey);
+                assertTrue(keys.add(segmentKey));
+            }
+        } finally {
+            reader.close();
+        }
+    }
+
+    @Test
+    public void searchNativeAfterDocValuesUpdate() throws IOException {
+        try (ByteBuffersDirectory updatedDir = new ByteBuffersDirectory();
+             IndexWriter updatedWriter = new IndexWriter(updatedDir, new IndexWriterConfig(new StandardAnalyzer()));
+             VScorerNativeFactory scorerFactory = new VScorerNativeFactory(VECTOR_SIZE)) {
+            final float[] query = generateArray();
+            for (int i = 0; i < 10; i++) {
+                final Document doc = new Document();
+                doc.add(new StringField("id", Integer.toString(i), Field.Store.NO));
+                doc.add(new BinaryDocValuesField(FIELD_NAME, i == 3 ? toBytesRef(query, true) : generateBytesRef(true)));
+                updatedWriter.addDocument(doc);
+            }
+            updatedWriter.commit();
+            final DirectoryReader reader = DirectoryReader.open(updatedDir);
+            final VQuery vQuery = new VQuery(FIELD_NAME, query, scorerFactory);
+            assertEquals(new IndexSearcher(reader).search(vQuery, 1).scoreDocs[0].doc, 3);
+
+            // the reopened reader shares the segment core, the vectors cached for doc 3 and 5 are stale
+            updatedWriter.updateBinaryDocValue(new Term("id", "3"), FIELD_NAME, generateBytesRef(true));
+            updatedWriter.updateBinaryDocValue(new Term("id", "5"), FIELD_NAME, toBytesRef(query, true));
+            updatedWriter.commit();
+            final DirectoryReader updated = DirectoryReader.openIfChanged(reader);
+            assertNotNull(updated);
+            assertEquals(new IndexSearcher(updated).search(vQuery, 1).scoreDocs[0].doc, 5);
+            vQuery.close();
+            updated.close();
+            reader.close();
+        }
+    }
+}